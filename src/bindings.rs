@@ -4,6 +4,7 @@ use crate::prelude::*;
 #[derive(Default)]
 pub struct Bindings {
     map: HashMap<Vec<Key>, DK>,
+    counts: bool,
 }
 
 pub struct BindingsBuilder {
@@ -30,12 +31,20 @@ impl BindingsBuilder {
         }
         self.bindings.map.insert(keys, dk);
     }
+    /// Lets a count typed before any of these bindings (as in `3w`) be passed along to the
+    /// bound command.
+    pub fn accept_counts(&mut self) {
+        self.bindings.counts = true;
+    }
     pub fn get_bindings(self) -> Bindings {
         self.bindings
     }
 }
 
 impl Bindings {
+    pub fn accepts_counts(&self) -> bool {
+        self.counts
+    }
     pub fn get_map(self) -> HashMap<Vec<Key>, DK> {
         self.map
     }
@@ -53,6 +62,12 @@ impl KeysLike for Key {
     }
 }
 
+impl KeysLike for Vec<Key> {
+    fn parse_keys(self) -> Vec<Key> {
+        self
+    }
+}
+
 pub trait IntoDKBinding {
     fn to_dk_with_default_vk(self, vk: ViewKey) -> DK;
 }
@@ -1,10 +1,12 @@
 use crate::buf::Buf;
-use crate::color::{BgColor, FgColor};
+use crate::color::{BgColor, ColorDepth, FgColor};
 use crate::error::{ErrorContext, Result};
 use crate::format::Format;
 use crate::glyph::Glyph;
 use crate::prelude::*;
+use crate::utils::grapheme_width;
 use std::fmt::Write;
+use unicode_segmentation::UnicodeSegmentation;
 
 #[derive(Debug, Clone)]
 pub struct Bitmap {
@@ -41,7 +43,7 @@ impl Bitmap {
         self.cursor
     }
 
-    pub fn diff(bmp_last: &Self, bmp: &Self, buf: &mut Buf) -> Result<()> {
+    pub fn diff(bmp_last: &Self, bmp: &Self, buf: &mut Buf, depth: ColorDepth) -> Result<()> {
         assert!(bmp_last.size == bmp.size);
         write!(buf, "\x1b[0m").context("clearing graphic rendition")?;
         let mut last_format: Format = Format::none();
@@ -55,7 +57,7 @@ impl Bitmap {
                 write!(buf, "\x1b[{};{}H", y + 1, 1).context("writing raster start")?;
                 for x in 0..size.width {
                     let glyph: &Glyph = &bmp.glyphs[x + y * size.width];
-                    last_format = glyph.encode_utf8_to_buf(buf, last_format)?;
+                    last_format = glyph.encode_utf8_to_buf(buf, last_format, depth)?;
                 }
             }
         }
@@ -65,6 +67,17 @@ impl Bitmap {
     pub fn get_glyph(&self, pos: Pos) -> &Glyph {
         &self.glyphs[pos.x + pos.y * self.size.width]
     }
+    /// Blanks the wide glyphs that straddle either end of `cells`, a range of indexes within one
+    /// row that's about to be overwritten, so that none is left in half.
+    fn break_wide_glyphs(&mut self, cells: Range<usize>) {
+        let row_start = cells.start - cells.start % self.size.width;
+        if cells.start > row_start && self.glyphs[cells.start].is_continuation() {
+            self.glyphs[cells.start - 1].blank();
+        }
+        if cells.end < row_start + self.size.width && self.glyphs[cells.end].is_continuation() {
+            self.glyphs[cells.end].blank();
+        }
+    }
 }
 
 pub struct BitmapView<'a> {
@@ -101,33 +114,53 @@ impl<'a> BitmapView<'a> {
     }
 
     pub fn set_glyph(&mut self, pos: Pos, glyph: Glyph) {
-        let target_glyph = self.get_glyph(pos);
-        target_glyph.ch = glyph.ch;
+        self.put_glyph(pos, glyph, 1);
+    }
 
-        match glyph.format {
-            Format {
-                fg: FgColor::None,
-                bg: BgColor::None,
-            } => {}
-            Format {
-                fg,
-                bg: BgColor::None,
-            } => {
-                target_glyph.format.fg = fg;
-            }
-            Format {
-                fg: FgColor::None,
-                bg,
-            } => {
-                target_glyph.format.bg = bg;
+    /// Puts `glyph` at `pos`, covering the `width - 1` cells after it with continuations. Any
+    /// wide glyph left with only one of its halves is blanked.
+    fn put_glyph(&mut self, pos: Pos, glyph: Glyph, width: Coord) {
+        // Check that the whole glyph is within the view.
+        self.get_glyph(Pos {
+            x: pos.x + width - 1,
+            ..pos
+        });
+        let pos = pos + self.frame.top_left();
+        let start = pos.x + pos.y * self.bitmap.size.width;
+        self.bitmap.break_wide_glyphs(start..start + width);
+        for (i, target_glyph) in self.bitmap.glyphs[start..start + width]
+            .iter_mut()
+            .enumerate()
+        {
+            // Colors left unset show through from what's beneath, but attributes belong to the
+            // char.
+            let mut format = glyph.format;
+            if format.fg == FgColor::None {
+                format.fg = target_glyph.format.fg;
             }
-            Format { fg, bg } => {
-                target_glyph.format.fg = fg;
-                target_glyph.format.bg = bg;
+            if format.bg == BgColor::None {
+                format.bg = target_glyph.format.bg;
             }
+            *target_glyph = if i == 0 {
+                Glyph { format, ..glyph }
+            } else {
+                Glyph::continuation(format)
+            };
         }
     }
 
+    /// Adds zero-width `marks` to the glyph at `pos`, or to the wide glyph it's the right half of.
+    fn add_marks(&mut self, mut pos: Pos, marks: impl Iterator<Item = char>) {
+        if pos.x > 0 && self.get_glyph(pos).is_continuation() {
+            pos.x -= 1;
+        }
+        let target_glyph = self.get_glyph(pos);
+        for mark in marks {
+            target_glyph.add_mark(mark);
+        }
+    }
+
+    #[allow(dead_code)]
     pub fn set_bg(&mut self, pos: Pos, bg: BgColor) {
         let target_glyph = self.get_glyph(pos);
         target_glyph.format.bg = bg;
@@ -139,22 +172,50 @@ impl<'a> BitmapView<'a> {
     {
         pos.x += self.append_chars_at(*pos, chs, format);
     }
+    /// Puts `chs` in the cells from `pos` on, a grapheme cluster at a time, and returns how many
+    /// cells they took. Wide graphemes take two cells, and marks with nothing before them to
+    /// combine with join the cell to the left of `pos`.
     pub fn append_chars_at<T>(&mut self, mut pos: Pos, chs: T, format: Format) -> usize
     where
         T: Iterator<Item = char>,
     {
         let max_pos = self.get_size().width;
-        let mut count = 0;
-        for ch in chs {
+        let start = pos.x;
+        let text: String = chs.collect();
+        for grapheme in text.graphemes(true) {
             if pos.x >= max_pos {
                 break;
             }
-            self.set_glyph(pos, Glyph { ch, format });
-            pos.x += 1;
-            count += 1;
+            let mut chars = grapheme.chars();
+            let width = grapheme_width(grapheme);
+            if width == 0 {
+                if pos.x > 0 {
+                    self.add_marks(
+                        Pos {
+                            x: pos.x - 1,
+                            ..pos
+                        },
+                        chars,
+                    );
+                }
+                continue;
+            }
+            if pos.x + width > max_pos {
+                // There's no room for the other half.
+                self.set_glyph(pos, Glyph::new(' ', format));
+                pos.x += 1;
+                break;
+            }
+            let mut glyph = Glyph::new(chars.next().unwrap(), format);
+            for mark in chars {
+                glyph.add_mark(mark);
+            }
+            self.put_glyph(pos, glyph, width);
+            pos.x += width;
         }
-        count
+        pos.x - start
     }
+    #[allow(dead_code)]
     pub fn end_line_with_str(&mut self, mut pos: Pos, s: &str) {
         // log::trace!("calling end_line_with_str(pos={:?}, s={:?})", pos, s);
         let count = s.chars().count();
@@ -185,3 +246,83 @@ macro_rules! bmp_fmt_at {
     }};
 }
 pub(crate) use bmp_fmt_at;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Attrs;
+
+    #[test]
+    fn diff_emits_minimal_transitions() -> Result<()> {
+        let size = Size {
+            width: 4,
+            height: 1,
+        };
+        let blank = Glyph::from_char(' ');
+        let last = Bitmap::new(size, blank);
+        let mut bmp = Bitmap::new(size, blank);
+        let bold = Format {
+            attrs: Attrs::BOLD,
+            ..Format::none()
+        };
+        let mut view = BitmapView::new(&mut bmp, size.into());
+        view.append_chars_at(Pos::zero(), "ab".chars(), bold);
+        view.append_chars_at(
+            Pos { x: 2, y: 0 },
+            "c".chars(),
+            Format {
+                attrs: Attrs::BOLD | Attrs::UNDERLINE,
+                ..Format::none()
+            },
+        );
+        let mut buf = Buf::default();
+        Bitmap::diff(&last, &bmp, &mut buf, ColorDepth::TrueColor)?;
+        assert_eq!(buf.as_bytes(), b"\x1b[0m\x1b[1;1H\x1b[1mab\x1b[4mc\x1b[0m ");
+        Ok(())
+    }
+
+    fn render(width: Coord, draw: impl FnOnce(&mut BitmapView)) -> Result<String> {
+        let size = Size { width, height: 1 };
+        let blank = Glyph::from_char(' ');
+        let last = Bitmap::new(size, blank);
+        let mut bmp = Bitmap::new(size, blank);
+        draw(&mut BitmapView::new(&mut bmp, size.into()));
+        let mut buf = Buf::default();
+        Bitmap::diff(&last, &bmp, &mut buf, ColorDepth::TrueColor)?;
+        Ok(String::from_utf8(buf.as_bytes().to_vec()).unwrap())
+    }
+
+    #[test]
+    fn wide_and_combining_graphemes() -> Result<()> {
+        let prefix = "\x1b[0m\x1b[1;1H";
+        let mut taken = 0;
+        let out = render(5, |view| {
+            taken = view.append_chars_at(Pos::zero(), "中e\u{301}x".chars(), Format::none())
+        })?;
+        assert_eq!(taken, 4);
+        assert_eq!(out, format!("{}中e\u{301}x ", prefix));
+        // Marks at the start of a run join the cell before it.
+        let out = render(3, |view| {
+            view.append_chars_at(Pos::zero(), "中".chars(), Format::none());
+            view.append_chars_at(Pos { x: 2, y: 0 }, "\u{301}a".chars(), Format::none());
+        })?;
+        assert_eq!(out, format!("{}中\u{301}a", prefix));
+        // A wide char with no room for its right half is a blank.
+        let out = render(3, |view| {
+            view.append_chars_at(Pos::zero(), "ab中".chars(), Format::none());
+        })?;
+        assert_eq!(out, format!("{}ab ", prefix));
+        Ok(())
+    }
+
+    #[test]
+    fn overwriting_half_a_wide_char_blanks_the_other() -> Result<()> {
+        let out = render(4, |view| {
+            view.append_chars_at(Pos::zero(), "中中".chars(), Format::none());
+            view.set_glyph(Pos { x: 1, y: 0 }, Glyph::from_char('a'));
+            view.set_glyph(Pos { x: 2, y: 0 }, Glyph::from_char('b'));
+        })?;
+        assert_eq!(out, "\x1b[0m\x1b[1;1H ab ");
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+use crate::doc::Doc;
+use crate::marks::FileMark;
+use crate::types::Pos;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub type BuffersRef = Rc<RefCell<Buffers>>;
+pub type DocRef = Rc<RefCell<Doc>>;
+/// Buffer ids are never reused, so `:b 3` means the same buffer for the whole session.
+pub type BufferId = usize;
+
+/// An open document, along with where the cursor was the last time a view showed it.
+pub struct Buffer {
+    pub id: BufferId,
+    pub doc: DocRef,
+    pub cursor: Pos,
+    pub scroll_offset: Pos,
+}
+
+impl Buffer {
+    pub fn is_dirty(&self) -> bool {
+        self.doc.borrow().is_dirty()
+    }
+    /// The buffer's name as `:ls` shows it.
+    pub fn name(&self) -> String {
+        self.doc
+            .borrow()
+            .get_filename()
+            .unwrap_or("[No Name]")
+            .to_string()
+    }
+}
+
+/// The buffer list, shared by every doc view. Buffers are kept in the order they were added.
+#[derive(Default)]
+pub struct Buffers {
+    buffers: Vec<Buffer>,
+    last_id: BufferId,
+    /// The uppercase marks, which name a place in one file out of all of them.
+    file_marks: HashMap<char, FileMark>,
+}
+
+impl Buffers {
+    pub fn new() -> BuffersRef {
+        Rc::new(RefCell::new(Self::default()))
+    }
+    /// Adds `doc` to the end of the list, returning its new buffer id.
+    pub fn add(&mut self, doc: Doc) -> BufferId {
+        self.last_id += 1;
+        self.buffers.push(Buffer {
+            id: self.last_id,
+            doc: Rc::new(RefCell::new(doc)),
+            cursor: Pos::zero(),
+            scroll_offset: Pos::zero(),
+        });
+        self.last_id
+    }
+    pub fn remove(&mut self, id: BufferId) -> Option<Buffer> {
+        // File marks in the buffer stay where its doc last had them.
+        let names: Vec<char> = self
+            .file_marks
+            .iter()
+            .filter(|(_, mark)| mark.buffer == id)
+            .map(|(&name, _)| name)
+            .collect();
+        for name in names {
+            match self.file_mark(name) {
+                Some(mark) => self.file_marks.insert(name, mark),
+                None => self.file_marks.remove(&name),
+            };
+        }
+        let index = self.index_of(id)?;
+        Some(self.buffers.remove(index))
+    }
+    /// Sets uppercase mark `name` to `pos` in buffer `id`, moving it from whichever buffer had
+    /// it before.
+    pub fn set_file_mark(&mut self, name: char, id: BufferId, pos: Pos) {
+        if let Some(old) = self
+            .file_marks
+            .get(&name)
+            .and_then(|mark| self.get(mark.buffer))
+        {
+            old.doc.borrow_mut().marks_mut().remove(name);
+        }
+        let filename = self.get(id).and_then(|buffer| {
+            let mut doc = buffer.doc.borrow_mut();
+            doc.marks_mut().set(name, pos);
+            doc.get_filename().map(str::to_string)
+        });
+        self.file_marks.insert(
+            name,
+            FileMark {
+                buffer: id,
+                filename,
+                pos,
+            },
+        );
+    }
+    /// Where uppercase mark `name` is. While its buffer is open, that's wherever the buffer's
+    /// doc has moved it to.
+    pub fn file_mark(&self, name: char) -> Option<FileMark> {
+        let mark = self.file_marks.get(&name)?;
+        match self.get(mark.buffer) {
+            Some(buffer) => {
+                let doc = buffer.doc.borrow();
+                Some(FileMark {
+                    buffer: mark.buffer,
+                    filename: doc.get_filename().map(str::to_string),
+                    pos: doc.marks().get(name)?,
+                })
+            }
+            None => Some(mark.clone()),
+        }
+    }
+    pub fn get(&self, id: BufferId) -> Option<&Buffer> {
+        self.buffers.iter().find(|buffer| buffer.id == id)
+    }
+    pub fn get_mut(&mut self, id: BufferId) -> Option<&mut Buffer> {
+        self.buffers.iter_mut().find(|buffer| buffer.id == id)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &Buffer> {
+        self.buffers.iter()
+    }
+    /// If buffer `id` has been deleted, the buffer for a view that was showing it to show instead.
+    pub fn replacement_for(&self, id: BufferId) -> Option<BufferId> {
+        match self.get(id) {
+            Some(_) => None,
+            None => self.buffers.first().map(|buffer| buffer.id),
+        }
+    }
+    /// Finds the buffer editing `filename`, if any.
+    pub fn find_file(&self, filename: &str) -> Option<BufferId> {
+        self.buffers
+            .iter()
+            .find(|buffer| buffer.doc.borrow().get_filename() == Some(filename))
+            .map(|buffer| buffer.id)
+    }
+    /// The buffer `count` places after (or, with `forward` false, before) `id`, wrapping around
+    /// the ends of the list.
+    pub fn cycle(&self, id: BufferId, count: usize, forward: bool) -> Option<BufferId> {
+        let len = self.buffers.len();
+        let index = self.index_of(id)?;
+        let step = count % len;
+        let next = if forward {
+            (index + step) % len
+        } else {
+            (index + len - step) % len
+        };
+        Some(self.buffers[next].id)
+    }
+    fn index_of(&self, id: BufferId) -> Option<usize> {
+        self.buffers.iter().position(|buffer| buffer.id == id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycle_wraps_around() {
+        let mut buffers = Buffers::default();
+        let one = buffers.add(Doc::empty());
+        let two = buffers.add(Doc::empty());
+        let three = buffers.add(Doc::empty());
+        assert_eq!(buffers.cycle(one, 1, true), Some(two));
+        assert_eq!(buffers.cycle(three, 1, true), Some(one));
+        assert_eq!(buffers.cycle(one, 1, false), Some(three));
+        assert_eq!(buffers.cycle(two, 5, true), Some(one));
+        buffers.remove(two);
+        assert_eq!(buffers.cycle(one, 1, true), Some(three));
+        // Ids aren't reused once a buffer is gone.
+        assert_eq!(buffers.add(Doc::empty()), 4);
+    }
+
+    #[test]
+    fn file_marks_move_between_buffers() {
+        let mut buffers = Buffers::default();
+        let one = buffers.add(Doc::empty());
+        let two = buffers.add(Doc::empty());
+        let pos = Pos { x: 0, y: 0 };
+        buffers.set_file_mark('A', one, pos);
+        buffers.set_file_mark('A', two, pos);
+        assert_eq!(
+            buffers.get(one).unwrap().doc.borrow().marks().get('A'),
+            None
+        );
+        assert_eq!(buffers.file_mark('A').map(|mark| mark.buffer), Some(two));
+        // The mark outlives its buffer.
+        buffers.remove(two);
+        assert_eq!(buffers.file_mark('A').map(|mark| mark.pos), Some(pos));
+        assert_eq!(buffers.file_mark('B'), None);
+    }
+}
@@ -1,16 +1,84 @@
-#[derive(Copy, Clone, Eq, PartialEq)]
+use unicode_general_category::{get_general_category, GeneralCategory};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum CharType {
     Text,
     Punct,
     Space,
+    /// CJK ideographs. Like vim, runs of these are words of their own, apart from any kana or
+    /// hangul next to them.
+    Han,
+    Hiragana,
+    Katakana,
+    Hangul,
+}
+
+/// The chars that words are made of, as vim's `iskeyword` says. Letters, marks, numbers and
+/// connectors like `_` always are; a file type can add more, as CSS adds `-`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct WordChars {
+    extra: Vec<char>,
 }
 
-pub fn classify(ch: char) -> CharType {
-    if ch.is_ascii_punctuation() {
-        CharType::Punct
-    } else if ch.is_whitespace() {
+impl WordChars {
+    /// Words made of the usual chars along with each of `extra`.
+    pub fn new(extra: &str) -> Self {
+        Self {
+            extra: extra.chars().collect(),
+        }
+    }
+    pub fn classify(&self, ch: char) -> CharType {
+        if ch.is_whitespace() {
+            return CharType::Space;
+        }
+        if self.extra.contains(&ch) {
+            return CharType::Text;
+        }
+        match ch as u32 {
+            0x3040..=0x309f => return CharType::Hiragana,
+            0x30a0..=0x30ff => return CharType::Katakana,
+            0x1100..=0x11ff | 0x3130..=0x318f | 0xac00..=0xd7a3 => return CharType::Hangul,
+            0x3400..=0x4dbf | 0x4e00..=0x9fff | 0xf900..=0xfaff | 0x20000..=0x2fa1f => {
+                return CharType::Han
+            }
+            _ => {}
+        }
+        use GeneralCategory::*;
+        match get_general_category(ch) {
+            UppercaseLetter | LowercaseLetter | TitlecaseLetter | ModifierLetter | OtherLetter
+            | NonspacingMark | SpacingMark | EnclosingMark | DecimalNumber | LetterNumber
+            | OtherNumber | ConnectorPunctuation => CharType::Text,
+            SpaceSeparator | LineSeparator | ParagraphSeparator => CharType::Space,
+            _ => CharType::Punct,
+        }
+    }
+}
+
+/// The class of `ch` in a WORD, which is any run of chars but whitespace.
+pub fn classify_big_word(ch: char) -> CharType {
+    if ch.is_whitespace() {
         CharType::Space
     } else {
         CharType::Text
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_by_unicode_category() {
+        let chars = WordChars::default();
+        let classes = |s: &str| s.chars().map(|ch| chars.classify(ch)).collect::<Vec<_>>();
+        use CharType::*;
+        assert_eq!(classes("aé_9"), [Text, Text, Text, Text]);
+        assert_eq!(classes("-—«€"), [Punct, Punct, Punct, Punct]);
+        assert_eq!(classes(" \u{3000}\t"), [Space, Space, Space]);
+        assert_eq!(
+            classes("中かカ한。"),
+            [Han, Hiragana, Katakana, Hangul, Punct]
+        );
+        assert_eq!(WordChars::new("-").classify('-'), Text);
+    }
+}
@@ -1,6 +1,5 @@
 use crate::format::Format;
 use serde_derive::Deserialize;
-use std::fmt;
 
 #[allow(dead_code)]
 #[derive(Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
@@ -34,36 +33,188 @@ pub enum BgColor {
     Rgb { r: u8, g: u8, b: u8 },
 }
 
-impl fmt::Display for FgColor {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::None => write!(f, "\x1b[39m"),
-            Self::Rgb { r, g, b } => write!(f, "\x1b[38;2;{};{};{}m", r, g, b),
-            Self::Black => write!(f, "\x1b[30m"),
-            Self::Red => write!(f, "\x1b[31m"),
-            Self::Green => write!(f, "\x1b[32m"),
-            Self::Yellow => write!(f, "\x1b[33m"),
-            Self::Blue => write!(f, "\x1b[34m"),
-            Self::Purple => write!(f, "\x1b[35m"),
-            Self::Cyan => write!(f, "\x1b[36m"),
-            Self::White => write!(f, "\x1b[37m"),
+/// How many colors the terminal can show.
+#[derive(Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ColorDepth {
+    /// The eight ANSI colors and their bright versions.
+    #[serde(rename = "16")]
+    Ansi16,
+    /// xterm's palette of the ANSI colors, a 6x6x6 color cube and 24 grays.
+    #[serde(rename = "256")]
+    Xterm256,
+    /// Any 24-bit RGB color.
+    #[serde(rename = "truecolor")]
+    TrueColor,
+}
+
+impl ColorDepth {
+    /// Works out the color depth from the `COLORTERM` and `TERM` environment variables.
+    pub fn from_env() -> Self {
+        Self::detect(
+            std::env::var("COLORTERM").ok().as_deref(),
+            std::env::var("TERM").ok().as_deref(),
+        )
+    }
+    fn detect(colorterm: Option<&str>, term: Option<&str>) -> Self {
+        let term = term.unwrap_or_default();
+        if matches!(colorterm, Some("truecolor" | "24bit"))
+            || term.ends_with("-direct")
+            || term.contains("truecolor")
+            || term.contains("24bit")
+        {
+            Self::TrueColor
+        } else if term.contains("256color") {
+            Self::Xterm256
+        } else {
+            Self::Ansi16
+        }
+    }
+}
+
+/// The RGB values xterm gives the 16 ANSI colors.
+static ANSI16: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+/// The levels of each component in xterm's 6x6x6 color cube.
+static CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    d(r1, r2) + d(g1, g2) + d(b1, b2)
+}
+
+/// The index of the ANSI color closest to `(r, g, b)`.
+fn nearest_ansi16(r: u8, g: u8, b: u8) -> u8 {
+    (0..16)
+        .min_by_key(|&index| distance(ANSI16[index], (r, g, b)))
+        .unwrap() as u8
+}
+
+/// The index of the color in xterm's 256-color palette closest to `(r, g, b)`, out of the
+/// color cube and the gray ramp. The first 16 are left out, as terminals often change them.
+fn nearest_xterm256(r: u8, g: u8, b: u8) -> u8 {
+    let level = |component: u8| {
+        (0..6)
+            .min_by_key(|&index| CUBE_LEVELS[index].abs_diff(component))
+            .unwrap()
+    };
+    let (ri, gi, bi) = (level(r), level(g), level(b));
+    let cube = (CUBE_LEVELS[ri], CUBE_LEVELS[gi], CUBE_LEVELS[bi]);
+    let cube_index = 16 + 36 * ri + 6 * gi + bi;
+    // The grays run from 8 to 238 in steps of 10.
+    let average = (r as u32 + g as u32 + b as u32) / 3;
+    let gray_step = (average.saturating_sub(3) / 10).min(23) as u8;
+    let gray_level = 8 + 10 * gray_step;
+    if distance((gray_level, gray_level, gray_level), (r, g, b)) < distance(cube, (r, g, b)) {
+        232 + gray_step
+    } else {
+        cube_index as u8
+    }
+}
+
+/// An underline's own color, for terminals that can draw one apart from the text's.
+#[allow(dead_code)]
+#[derive(Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UnderlineColor {
+    None,
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Purple,
+    Cyan,
+    White,
+    Rgb { r: u8, g: u8, b: u8 },
+}
+
+impl FgColor {
+    /// The SGR parameter that sets this color on a terminal with `depth` colors.
+    pub fn sgr(&self, depth: ColorDepth) -> String {
+        match *self {
+            Self::None => "39".to_string(),
+            Self::Rgb { r, g, b } => match depth {
+                ColorDepth::TrueColor => format!("38;2;{};{};{}", r, g, b),
+                ColorDepth::Xterm256 => format!("38;5;{}", nearest_xterm256(r, g, b)),
+                ColorDepth::Ansi16 => match nearest_ansi16(r, g, b) {
+                    index @ 0..=7 => (30 + index).to_string(),
+                    index => (90 + index - 8).to_string(),
+                },
+            },
+            Self::Black => "30".to_string(),
+            Self::Red => "31".to_string(),
+            Self::Green => "32".to_string(),
+            Self::Yellow => "33".to_string(),
+            Self::Blue => "34".to_string(),
+            Self::Purple => "35".to_string(),
+            Self::Cyan => "36".to_string(),
+            Self::White => "37".to_string(),
         }
     }
 }
 
-impl fmt::Display for BgColor {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::None => write!(f, "\x1b[49m"),
-            Self::Rgb { r, g, b } => write!(f, "\x1b[48;2;{};{};{}m", r, g, b),
-            Self::Black => write!(f, "\x1b[40m"),
-            Self::Red => write!(f, "\x1b[41m"),
-            Self::Green => write!(f, "\x1b[42m"),
-            Self::Yellow => write!(f, "\x1b[43m"),
-            Self::Blue => write!(f, "\x1b[44m"),
-            Self::Purple => write!(f, "\x1b[45m"),
-            Self::Cyan => write!(f, "\x1b[46m"),
-            Self::White => write!(f, "\x1b[47m"),
+impl BgColor {
+    /// The SGR parameter that sets this color on a terminal with `depth` colors.
+    pub fn sgr(&self, depth: ColorDepth) -> String {
+        match *self {
+            Self::None => "49".to_string(),
+            Self::Rgb { r, g, b } => match depth {
+                ColorDepth::TrueColor => format!("48;2;{};{};{}", r, g, b),
+                ColorDepth::Xterm256 => format!("48;5;{}", nearest_xterm256(r, g, b)),
+                ColorDepth::Ansi16 => match nearest_ansi16(r, g, b) {
+                    index @ 0..=7 => (40 + index).to_string(),
+                    index => (100 + index - 8).to_string(),
+                },
+            },
+            Self::Black => "40".to_string(),
+            Self::Red => "41".to_string(),
+            Self::Green => "42".to_string(),
+            Self::Yellow => "43".to_string(),
+            Self::Blue => "44".to_string(),
+            Self::Purple => "45".to_string(),
+            Self::Cyan => "46".to_string(),
+            Self::White => "47".to_string(),
+        }
+    }
+}
+
+impl UnderlineColor {
+    /// The SGR parameter that sets this color on a terminal with `depth` colors. There are no
+    /// short codes for underline colors, so the named ones are given as their indexes in the
+    /// 256-color palette.
+    pub fn sgr(&self, depth: ColorDepth) -> String {
+        match *self {
+            Self::None => "59".to_string(),
+            Self::Rgb { r, g, b } => match depth {
+                ColorDepth::TrueColor => format!("58;2;{};{};{}", r, g, b),
+                ColorDepth::Xterm256 => format!("58;5;{}", nearest_xterm256(r, g, b)),
+                ColorDepth::Ansi16 => format!("58;5;{}", nearest_ansi16(r, g, b)),
+            },
+            Self::Black => "58;5;0".to_string(),
+            Self::Red => "58;5;1".to_string(),
+            Self::Green => "58;5;2".to_string(),
+            Self::Yellow => "58;5;3".to_string(),
+            Self::Blue => "58;5;4".to_string(),
+            Self::Purple => "58;5;5".to_string(),
+            Self::Cyan => "58;5;6".to_string(),
+            Self::White => "58;5;7".to_string(),
         }
     }
 }
@@ -72,7 +223,7 @@ impl From<BgColor> for Format {
     fn from(bg: BgColor) -> Self {
         Self {
             bg,
-            fg: FgColor::None,
+            ..Format::none()
         }
     }
 }
@@ -81,7 +232,7 @@ impl From<FgColor> for Format {
     fn from(fg: FgColor) -> Self {
         Self {
             fg,
-            bg: BgColor::None,
+            ..Format::none()
         }
     }
 }
@@ -90,7 +241,11 @@ impl std::ops::Add<FgColor> for BgColor {
     type Output = Format;
     #[inline]
     fn add(self, fg: FgColor) -> Format {
-        Format { fg, bg: self }
+        Format {
+            fg,
+            bg: self,
+            ..Format::none()
+        }
     }
 }
 
@@ -98,6 +253,50 @@ impl std::ops::Add<BgColor> for FgColor {
     type Output = Format;
     #[inline]
     fn add(self, bg: BgColor) -> Format {
-        Format { fg: self, bg }
+        Format {
+            fg: self,
+            bg,
+            ..Format::none()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_color_depth() {
+        let detect = ColorDepth::detect;
+        assert_eq!(
+            detect(Some("truecolor"), Some("xterm")),
+            ColorDepth::TrueColor
+        );
+        assert_eq!(detect(None, Some("xterm-direct")), ColorDepth::TrueColor);
+        assert_eq!(detect(None, Some("tmux-256color")), ColorDepth::Xterm256);
+        assert_eq!(detect(None, Some("screen")), ColorDepth::Ansi16);
+        assert_eq!(detect(None, None), ColorDepth::Ansi16);
+    }
+
+    #[test]
+    fn quantizes_rgb() {
+        let color = |r, g, b| FgColor::Rgb { r, g, b };
+        assert_eq!(
+            color(1, 2, 3).sgr(ColorDepth::TrueColor),
+            "38;2;1;2;3".to_string()
+        );
+        assert_eq!(color(255, 0, 0).sgr(ColorDepth::Xterm256), "38;5;196");
+        assert_eq!(color(0, 95, 135).sgr(ColorDepth::Xterm256), "38;5;24");
+        assert_eq!(
+            color(0x26, 0x26, 0x26).sgr(ColorDepth::Xterm256),
+            "38;5;235"
+        );
+        assert_eq!(color(250, 10, 10).sgr(ColorDepth::Ansi16), "91");
+        assert_eq!(color(200, 200, 200).sgr(ColorDepth::Ansi16), "37");
+        assert_eq!(
+            BgColor::Rgb { r: 0, g: 0, b: 0 }.sgr(ColorDepth::Ansi16),
+            "40"
+        );
+        assert_eq!(FgColor::Red.sgr(ColorDepth::Ansi16), "31");
     }
 }
@@ -1,9 +1,10 @@
 use crate::bindings::BindingsBuilder;
 use crate::bitmap::bmp_fmt_at;
-use crate::color::{BgColor, FgColor};
 use crate::error::{Error, Result};
-use crate::format::Format;
+use crate::ex::ExCommand;
 use crate::prelude::*;
+use crate::search::{Direction, SearchRef};
+use crate::theme::Group;
 
 #[allow(dead_code)]
 pub struct CommandLine {
@@ -14,11 +15,20 @@ pub struct CommandLine {
     scroll_offset: Coord,
     text: String,
     status: Status,
+    /// ':' for ex commands, '/' or '?' for searches.
+    prompt: char,
+    search: SearchRef,
+    settings: Rc<Settings>,
 }
 
 #[allow(dead_code)]
 impl CommandLine {
-    pub fn new(plugin: PluginRef, view_key: ViewKey) -> Self {
+    pub fn new(
+        plugin: PluginRef,
+        view_key: ViewKey,
+        search: SearchRef,
+        settings: Rc<Settings>,
+    ) -> Self {
         Self {
             plugin,
             view_key,
@@ -27,6 +37,22 @@ impl CommandLine {
             scroll_offset: 0,
             text: String::new(),
             status: Status::Ok,
+            prompt: ':',
+            search,
+            settings,
+        }
+    }
+    fn search_direction(&self) -> Option<Direction> {
+        [Direction::Forward, Direction::Backward]
+            .into_iter()
+            .find(|direction| direction.prompt() == self.prompt)
+    }
+}
+
+impl CommandLine {
+    fn update_incremental_search(&mut self) {
+        if self.search_direction().is_some() {
+            self.search.borrow_mut().set_incremental(&self.text);
         }
     }
 }
@@ -43,67 +69,29 @@ impl View for CommandLine {
         Default::default()
     }
     fn display(&self, view_map: &ViewMap, bmp: &mut BitmapView) {
-        let size = bmp.get_size();
-        assert!(size.height == 2);
-        let is_dirty = view_map
-            .focused_view()
-            .get_property_bool(PROP_DOC_IS_MODIFIED, false);
-        let current_filename = view_map
-            .focused_view()
-            .get_property_string(PROP_DOC_FILENAME);
-        let status_text = view_map
-            .focused_view()
-            .get_property_string(PROP_DOCVIEW_STATUS);
-        log::trace!("PROP_DOCVIEW_STATUS={:?}", status_text);
-        {
-            let bgcolor = BgColor::Rgb {
-                r: 100,
-                g: 105,
-                b: 102,
-            };
-            for x in 0..bmp.get_size().width {
-                bmp.set_bg(Pos { x, y: 0 }, bgcolor);
-            }
-            let mut pos = Pos { x: 1, y: 0 };
-            if let Some(current_filename) = current_filename {
-                bmp_fmt_at!(
-                    bmp,
-                    pos,
-                    FgColor::Rgb {
-                        r: 240,
-                        g: 140,
-                        b: 240
-                    }
-                    .into(),
-                    "{}",
-                    current_filename
-                );
-                pos.x += 1;
-                bmp_fmt_at!(
-                    bmp,
-                    pos,
-                    FgColor::Yellow.into(),
-                    " ▍ {}",
-                    if is_dirty { "(modified) ▍" } else { "" }
-                );
-            }
-            if let Status::Message {
-                ref message,
-                expiry,
-            } = self.status
-            {
-                if expiry > Instant::now() {
-                    bmp_fmt_at!(bmp, pos, Format::none(), " {}", message);
+        assert!(bmp.get_size().height == 1);
+        // Each window shows its own status line, leaving this row for typing commands and for
+        // messages.
+        let mut pos = Pos { x: 0, y: 0 };
+        let colors = &self.settings.colors;
+        let msg_area = colors.format(Group::MsgArea);
+        let width = bmp.get_size().width;
+        bmp.append_chars_at(pos, std::iter::repeat_n(' ', width), msg_area);
+        if view_map.focused_view_key() == self.view_key {
+            bmp_fmt_at!(bmp, pos, msg_area, "{}{}", self.prompt, self.text);
+        } else {
+            match self.status {
+                Status::Message {
+                    ref message,
+                    expiry,
+                } if expiry > Instant::now() => {
+                    bmp_fmt_at!(bmp, pos, msg_area, "{}", message);
                 }
+                Status::Error(ref message) => {
+                    bmp_fmt_at!(bmp, pos, colors.format(Group::ErrorMsg), "{}", message);
+                }
+                _ => {}
             }
-            if let Some(status_text) = status_text {
-                bmp.end_line_with_str(pos, &status_text);
-            }
-        }
-
-        if view_map.focused_view_key() == self.view_key {
-            let mut pos = Pos { x: 0, y: 1 };
-            bmp_fmt_at!(bmp, pos, Format::none(), ":{}", self.text);
         }
     }
 
@@ -113,7 +101,7 @@ impl View for CommandLine {
     fn get_cursor_pos(&self) -> Option<Pos> {
         let x = Some(Pos {
             x: 1 + self.cursor - self.scroll_offset,
-            y: 1,
+            y: 0,
         });
         trace!("commandline pos: {:?}", x);
         x
@@ -128,29 +116,34 @@ impl DispatchTarget for CommandLine {
     fn get_key_bindings(&self) -> Bindings {
         let vk = self.get_view_key();
         let mut builder = BindingsBuilder::new(vk);
-        builder.insert(Key::Esc, command("focus").arg(Target::Previous));
-        // TODO: parse the typed text and transform it into an AST.
-        if self.text == "quit" {
-            builder.insert(
-                Key::Enter,
-                DK::Sequence(vec![
-                    command("clear-text").at_view(vk),
-                    command("focus").arg(Target::Previous).at_view_map(),
-                    command("quit").at_focused(),
-                ]),
-            );
-        } else {
-            builder.insert(
-                Key::Enter,
-                DK::Sequence(vec![
-                    command("clear-text").at_view(vk),
-                    command("focus").arg(Target::Previous).at_view_map(),
-                    command("invoke-execute")
-                        .arg(self.text.as_ref())
-                        .at_focused(),
-                ]),
+        builder.insert(
+            Key::Esc,
+            DK::Sequence(vec![
+                command("clear-text").at_view(vk),
+                command("focus").arg(Target::Previous).at_view_map(),
+            ]),
+        );
+        let mut dks = vec![
+            command("clear-text").at_view(vk),
+            command("focus").arg(Target::Previous).at_view_map(),
+        ];
+        if let Some(direction) = self.search_direction() {
+            dks.push(
+                command("search")
+                    .arg(self.text.as_str())
+                    .arg(match direction {
+                        Direction::Forward => "forward",
+                        Direction::Backward => "backward",
+                    })
+                    .at_focused(),
             );
+        } else if !self.text.trim().is_empty() {
+            match self.text.parse::<ExCommand>().and_then(|cmd| cmd.to_dks()) {
+                Ok(cmd_dks) => dks.extend(cmd_dks),
+                Err(error) => dks.push(command("report").arg(error.message()).at_view(vk)),
+            }
         }
+        builder.insert(Key::Enter, DK::Sequence(dks));
         builder.get_bindings()
     }
 
@@ -159,6 +152,7 @@ impl DispatchTarget for CommandLine {
             Key::Utf8(ch) => {
                 self.text.push(ch);
                 self.cursor += 1;
+                self.update_incremental_search();
                 Ok(Status::Ok)
             }
             Key::Backspace => {
@@ -166,6 +160,7 @@ impl DispatchTarget for CommandLine {
                     self.text.pop();
                     self.cursor -= 1;
                 }
+                self.update_incremental_search();
                 Ok(Status::Ok)
             }
             _ => {
@@ -176,7 +171,31 @@ impl DispatchTarget for CommandLine {
     fn execute_command(&mut self, name: String, args: Vec<Variant>) -> Result<Status> {
         if name == "clear-text" {
             self.text.clear();
+            self.cursor = 0;
+            self.search.borrow_mut().clear_incremental();
             Ok(Status::Ok)
+        } else if name == "prompt" {
+            match args.as_slice() {
+                [Variant::String(prompt)] if prompt.chars().count() == 1 => {
+                    self.prompt = prompt.chars().next().unwrap();
+                    Ok(Status::Ok)
+                }
+                [Variant::String(prompt), Variant::Int(count)] if prompt.chars().count() == 1 => {
+                    self.prompt = prompt.chars().next().unwrap();
+                    // As in vim, a count before ':' starts a range covering that many lines.
+                    if self.prompt == ':' && *count > 1 {
+                        self.text = format!(".,.+{}", count - 1);
+                        self.cursor = self.text.chars().count();
+                    }
+                    Ok(Status::Ok)
+                }
+                _ => Err(error!("'prompt' expects a single character")),
+            }
+        } else if name == "report" {
+            match args.as_slice() {
+                [Variant::String(message)] => Ok(status!("{}", message)),
+                _ => Err(error!("'report' expects a message")),
+            }
         } else {
             Err(Error::not_impl(format!(
                 "CommandLine::execute_command does not impl {:?} {:?}",
@@ -4,10 +4,15 @@ pub static PROP_EDITOR_SHOULD_QUIT: &str = "should-quit?";
 // pub static PROP_ROOT_VIEW_KEY: &str = "get-root-view-key";
 // pub static PROP_CMDLINE_TEXT: &str = "cmdline-text";
 pub static PROP_DOCVIEW_CURSOR_POS: &str = "docview-cursor-pos";
-pub static PROP_DOCVIEW_STATUS: &str = "docview-status";
+pub static PROP_DOCVIEW_MODE: &str = "docview-mode";
+pub static PROP_DOCVIEW_BUFFER_ID: &str = "docview-buffer-id";
+pub static PROP_DOCVIEW_WIDTH: &str = "docview-width";
+pub static PROP_DOCVIEW_HEIGHT: &str = "docview-height";
+pub static PROP_DOCVIEW_MODIFIED_BUFFER: &str = "docview-modified-buffer";
 pub static PROP_DOC_FILENAME: &str = "doc-filename";
 pub static PROP_DOC_IS_MODIFIED: &str = "doc-is-modified?";
+pub static PROP_DOC_LINE_COUNT: &str = "doc-line-count";
+pub static PROP_DOC_FILE_TYPE: &str = "doc-file-type";
 
-pub static EMPTY: &[char] = &[];
 pub static BLANKS: &[char] = &[' '; 1024 * 2];
 pub static TAB_STOP_SIZE: usize = 4;
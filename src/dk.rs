@@ -7,6 +7,43 @@ pub enum DK {
     Sequence(Vec<DK>),
 }
 
+impl DK {
+    /// Passes a count typed before the keys bound to this DK on to its (first) command as a
+    /// trailing `Int`. A binding that already carries a count has it multiplied.
+    pub fn with_count(self, count: i64) -> Self {
+        match self {
+            Self::Dispatch(target, Message::Command { name, mut args }) => {
+                match args.last_mut() {
+                    Some(Variant::Int(n)) => *n = n.saturating_mul(count),
+                    _ => args.push(Variant::Int(count)),
+                }
+                Self::Dispatch(target, Message::Command { name, args })
+            }
+            Self::Sequence(mut dks) if !dks.is_empty() => {
+                let first = dks.remove(0).with_count(count);
+                dks.insert(0, first);
+                Self::Sequence(dks)
+            }
+            dk => dk,
+        }
+    }
+    /// Drops any count carried by this DK's commands.
+    pub fn without_count(self) -> Self {
+        match self {
+            Self::Dispatch(target, Message::Command { name, mut args }) => {
+                if let Some(Variant::Int(_)) = args.last() {
+                    args.pop();
+                }
+                Self::Dispatch(target, Message::Command { name, args })
+            }
+            Self::Sequence(dks) => {
+                Self::Sequence(dks.into_iter().map(Self::without_count).collect())
+            }
+            dk => dk,
+        }
+    }
+}
+
 impl<T> From<DK> for Result<DK, T> {
     #[inline]
     fn from(dk: DK) -> Self {
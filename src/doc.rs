@@ -1,21 +1,86 @@
-use crate::classify::{classify, CharType};
-use crate::error::Result;
+use crate::classify::{classify_big_word, CharType, WordChars};
+use crate::error::{Error, Result};
+use crate::files::{hidden_sibling, replace_file, sync_parent_dir, write_atomically};
+use crate::gutter::Signs;
+use crate::indent::Indent;
+use crate::marks::Marks;
 use crate::prelude::*;
 use crate::rel::Rel;
 use crate::row::Row;
+use crate::search::Direction;
+use crate::syntax::{Grammar, Highlighter, Token};
 use crate::types::{Coord, Pos};
-use crate::undo::{Change, ChangeStack};
-use crate::undo::{ChangeOp, ChangeTracker};
-use crate::utils::read_lines;
+use crate::undo::{Change, ChangeOp, ChangeTracker, Travel, UndoTree};
+use crate::utils::{fnv1a_hash, read_lines};
+use regex::Regex;
 use std::fmt;
 
+/// A kind of text that a text object like `iw` or `a(` selects, either just its inside or all of
+/// it along with some surrounding whitespace or delimiters.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TextObj {
+    Word,
+    /// A run of anything but whitespace.
+    BigWord,
+    /// Text up to a `.`, `!` or `?` followed by whitespace, or up to a blank line.
+    Sentence,
+    /// A run of lines that are all blank or all not.
+    Paragraph,
+    /// A string between two of this quote char, on one line.
+    Quote(char),
+    /// Text between an open bracket and its matching close bracket, counting nested pairs.
+    Bracket(char, char),
+    /// An XML or HTML element, from its start tag to its matching end tag.
+    Tag,
+}
+
+impl FromStr for TextObj {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "word" => Ok(Self::Word),
+            "WORD" => Ok(Self::BigWord),
+            "sentence" => Ok(Self::Sentence),
+            "paragraph" => Ok(Self::Paragraph),
+            "tag" => Ok(Self::Tag),
+            "\"" | "'" | "`" => Ok(Self::Quote(s.chars().next().unwrap())),
+            "(" | ")" | "b" => Ok(Self::Bracket('(', ')')),
+            "[" | "]" => Ok(Self::Bracket('[', ']')),
+            "{" | "}" | "B" => Ok(Self::Bracket('{', '}')),
+            "<" | ">" => Ok(Self::Bracket('<', '>')),
+            _ => Err(error!("{} is not a valid text object", s)),
+        }
+    }
+}
+
+/// What a save wrote, and what went wrong after the document itself was safely written.
+pub struct Saved {
+    pub bytes: usize,
+    pub warnings: Vec<Error>,
+}
+
 #[derive(Debug)]
 #[allow(dead_code)]
 pub struct Doc {
     filename: Option<String>,
     tracked_rows: Vec<Row>,
     dirty: bool,
-    change_stack: ChangeStack,
+    undo_tree: UndoTree,
+    /// Whether the undo tree is kept in a file beside the document.
+    undo_file: bool,
+    /// How many groups are open, and the change gathered so far. See `begin_group`.
+    group_depth: usize,
+    group: Option<Change>,
+    /// The signs shown in the gutter beside the doc's lines.
+    signs: Signs,
+    /// Highlights the doc's lines, if there's a grammar for its file type.
+    highlighter: Option<Highlighter>,
+    indent: Indent,
+    /// The chars this doc's words are made of, which depend on its file type.
+    word_chars: WordChars,
+    /// The marks set in the doc, which move with its lines.
+    marks: Marks,
 }
 
 #[allow(dead_code)]
@@ -26,7 +91,15 @@ impl Doc {
             filename: None,
             tracked_rows: vec![Row::default()],
             dirty: false,
-            change_stack: Default::default(),
+            undo_tree: Default::default(),
+            undo_file: false,
+            group_depth: 0,
+            group: None,
+            signs: Default::default(),
+            highlighter: None,
+            indent: Default::default(),
+            word_chars: Default::default(),
+            marks: Default::default(),
         }
     }
     #[must_use]
@@ -41,6 +114,62 @@ impl Doc {
     pub fn is_dirty(&self) -> bool {
         self.dirty
     }
+    pub fn signs(&self) -> &Signs {
+        &self.signs
+    }
+    pub fn signs_mut(&mut self) -> &mut Signs {
+        &mut self.signs
+    }
+    pub fn indent(&self) -> Indent {
+        self.indent
+    }
+    pub fn set_indent(&mut self, indent: Indent) {
+        self.indent = indent;
+    }
+    pub fn word_chars(&self) -> &WordChars {
+        &self.word_chars
+    }
+    pub fn set_word_chars(&mut self, word_chars: WordChars) {
+        self.word_chars = word_chars;
+    }
+    pub fn marks(&self) -> &Marks {
+        &self.marks
+    }
+    pub fn marks_mut(&mut self) -> &mut Marks {
+        &mut self.marks
+    }
+    /// The class of `ch` when moving by `noun`, a word or a WORD.
+    fn word_class(&self, noun: Noun, ch: char) -> CharType {
+        match noun {
+            Noun::BigWord => classify_big_word(ch),
+            _ => self.word_chars.classify(ch),
+        }
+    }
+    /// Highlights the doc with `grammar` from now on, or with nothing.
+    pub fn set_grammar(&mut self, grammar: Option<Rc<Grammar>>) {
+        let unchanged = match (&self.highlighter, &grammar) {
+            (Some(highlighter), Some(grammar)) => Rc::ptr_eq(highlighter.grammar(), grammar),
+            (None, None) => true,
+            _ => false,
+        };
+        if !unchanged {
+            self.highlighter = grammar.map(Highlighter::new);
+        }
+    }
+    /// Makes sure the syntax tokens of lines up to and including `y` are up to date.
+    pub fn highlight_through(&mut self, y: Coord) {
+        if let Some(highlighter) = self.highlighter.as_mut() {
+            highlighter.lex_through(&self.tracked_rows, y);
+        }
+    }
+    /// The syntax tokens of line `y`, as of the last `highlight_through` that reached it.
+    #[must_use]
+    pub fn syntax_tokens(&self, y: Coord) -> &[Token] {
+        match self.highlighter {
+            Some(ref highlighter) => highlighter.tokens(y),
+            None => &[],
+        }
+    }
     #[must_use]
     pub fn get_filename(&self) -> Option<&str> {
         match self.filename {
@@ -81,12 +210,43 @@ impl Doc {
             }
         }
     }
+    /// Runs `f` on the undo tree, which needs the document to apply changes to.
+    fn with_undo_tree<T>(&mut self, f: impl FnOnce(&mut UndoTree, &mut Doc) -> T) -> T {
+        let mut temp = UndoTree::default();
+        std::mem::swap(&mut self.undo_tree, &mut temp);
+        let ret = f(&mut temp, self);
+        std::mem::swap(&mut self.undo_tree, &mut temp);
+        ret
+    }
+    /// Opens a group: changes pushed until the matching `end_group` undo and redo as a single
+    /// change. Groups nest, and only closing the outermost one records the change.
+    pub fn begin_group(&mut self) {
+        self.group_depth += 1;
+    }
+    pub fn end_group(&mut self) {
+        self.group_depth = self.group_depth.saturating_sub(1);
+        if self.group_depth == 0 {
+            if let Some(change) = self.group.take() {
+                self.undo_tree.push_applied(change);
+            }
+        }
+    }
+    /// Runs `f` within a group, so that everything it changes undoes as one.
+    pub fn group<T>(&mut self, f: impl FnOnce(&mut Doc) -> T) -> T {
+        self.begin_group();
+        let ret = f(self);
+        self.end_group();
+        ret
+    }
+    /// Closes any open groups, as moving through the undo tree needs a recorded change.
+    fn end_groups(&mut self) {
+        self.group_depth = 1;
+        self.end_group();
+    }
     #[must_use]
     pub fn undo_change(&mut self) -> Option<Pos> {
-        let mut temp = ChangeStack::default();
-        std::mem::swap(&mut self.change_stack, &mut temp);
-        let pos = temp.pop(self);
-        std::mem::swap(&mut self.change_stack, &mut temp);
+        self.end_groups();
+        let pos = self.with_undo_tree(|undo_tree, doc| undo_tree.pop(doc));
         if pos.is_some() {
             // TODO: save last saved undo index and pass that around.
             self.dirty = true;
@@ -94,54 +254,85 @@ impl Doc {
         pos
     }
     #[must_use]
-    pub fn push_change(&mut self, change: Change) -> Pos {
-        let mut temp = ChangeStack::default();
-        std::mem::swap(&mut self.change_stack, &mut temp);
-        let pos = temp.push(self, change);
-        std::mem::swap(&mut self.change_stack, &mut temp);
+    pub fn push_change(&mut self, mut change: Change) -> Pos {
+        let pos = if self.group_depth > 0 {
+            let pos = change.execute(self);
+            match self.group {
+                Some(ref mut group) => group.absorb(change),
+                None => self.group = Some(change),
+            }
+            pos
+        } else {
+            self.with_undo_tree(|undo_tree, doc| undo_tree.push(doc, change))
+        };
         self.dirty = true;
         pos
     }
     #[must_use]
     pub fn redo_change(&mut self) -> Option<Pos> {
-        let mut temp = ChangeStack::default();
-        std::mem::swap(&mut self.change_stack, &mut temp);
-        let pos = temp.redo(self);
-        std::mem::swap(&mut self.change_stack, &mut temp);
+        self.end_groups();
+        let pos = self.with_undo_tree(|undo_tree, doc| undo_tree.redo(doc));
         self.dirty = true;
         pos
     }
+    /// Moves through the undo tree in the order changes were made, as `g-`, `g+`, `:earlier` and
+    /// `:later` do.
+    #[must_use]
+    pub fn travel(&mut self, travel: Travel, later: bool) -> Option<Pos> {
+        self.end_groups();
+        let pos = self.with_undo_tree(|undo_tree, doc| undo_tree.travel(doc, travel, later));
+        if pos.is_some() {
+            self.dirty = true;
+        }
+        pos
+    }
+    /// The current and latest undo sequence numbers.
+    #[must_use]
+    pub fn undo_seq(&self) -> (usize, usize) {
+        (self.undo_tree.seq(), self.undo_tree.last_seq())
+    }
+    #[must_use]
+    pub fn content_hash(&self) -> u64 {
+        fnv1a_hash(self.get_save_buffer().as_bytes())
+    }
+    fn undo_filename(&self) -> Result<String> {
+        match self.filename {
+            Some(ref filename) => hidden_sibling(filename, "wim-undo"),
+            None => Err(error!("no filename specified!")),
+        }
+    }
+    /// Keeps the undo tree in a file beside the document from now on, first restoring the tree
+    /// from that file if it was written for the document's current contents. Returns whether a
+    /// tree was restored.
+    pub fn use_undo_file(&mut self) -> Result<bool> {
+        self.undo_file = true;
+        let text = match std::fs::read_to_string(self.undo_filename()?) {
+            Ok(text) => text,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(error) => return Err(error.into()),
+        };
+        match UndoTree::from_undo_file(&text, self.content_hash(), self.line_count())? {
+            Some(undo_tree) => {
+                self.undo_tree = undo_tree;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
     pub fn swap_rows(&mut self, range: &mut Range<Coord>, rows: &mut Vec<Row>) {
         assert!((0..=self.tracked_rows.len()).contains(&range.start));
         assert!((range.start..=self.tracked_rows.len()).contains(&range.end));
         let mut result_rows: Vec<Row> = self.tracked_rows[range.clone()].to_vec();
         let mut result_range: Range<Coord> = range.start..range.start + rows.len();
+        if let Some(highlighter) = self.highlighter.as_mut() {
+            highlighter.invalidate_from(range.start);
+        }
+        self.marks.splice(range.clone(), rows.len());
         self.tracked_rows
             .splice(range.clone(), rows.iter().cloned());
         std::mem::swap(&mut result_rows, rows);
         std::mem::swap(&mut result_range, range);
     }
-    pub fn render_line(&self, pos: Pos) -> std::slice::Iter<'_, char> {
-        if pos.y < self.tracked_rows.len() {
-            self.tracked_rows
-                .get(pos.y)
-                .unwrap()
-                .render_chars_from(pos.x)
-        } else {
-            EMPTY.iter()
-        }
-    }
-    #[must_use]
-    pub fn render_line_slice(&self, pos: Pos, len: usize) -> &[char] {
-        if pos.y < self.tracked_rows.len() {
-            self.tracked_rows
-                .get(pos.y)
-                .unwrap()
-                .get_slice(pos.x..pos.x + len)
-        } else {
-            EMPTY
-        }
-    }
     #[must_use]
     pub fn line_count(&self) -> usize {
         self.tracked_rows.len()
@@ -150,11 +341,20 @@ impl Doc {
     pub fn get_row(&self, y: Coord) -> Option<&Row> {
         self.tracked_rows.get(y)
     }
+    /// Reads `filename` into a new doc. A file that doesn't exist yet gives an empty doc that
+    /// saving will create, as in vim.
     pub fn open(filename: String) -> Result<Self> {
         let mut doc = Doc::empty();
-        doc.change_stack.clear();
+        let lines = match read_lines(&filename) {
+            Ok(lines) => lines,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                doc.filename = Some(filename);
+                return Ok(doc);
+            }
+            Err(error) => return Err(error.into()),
+        };
+        doc.undo_tree.clear();
         doc.tracked_rows.truncate(0);
-        let lines = read_lines(&filename)?;
         for line in lines {
             doc.tracked_rows.push(Row::from_line(&line?));
         }
@@ -163,6 +363,281 @@ impl Doc {
         Ok(doc)
     }
     #[must_use]
+    pub fn get_save_buffer(&self) -> Buf {
+        let mut buf = Buf::default();
+        for row in self.tracked_rows.iter() {
+            for &ch in row.as_slice() {
+                buf.push_char(ch);
+            }
+            buf.push_char('\n');
+        }
+        buf
+    }
+    /// Atomically writes the document back to its file.
+    pub fn save(&mut self) -> Result<Saved> {
+        let filename = match self.filename {
+            Some(ref filename) => filename.clone(),
+            None => return Err(error!("no filename specified!")),
+        };
+        self.save_to(&filename)
+    }
+    fn save_to(&mut self, filename: &str) -> Result<Saved> {
+        let save_buffer = self.get_save_buffer();
+        replace_file(filename, save_buffer.as_bytes())?;
+        // The contents are safely in place, so nothing after this leaves the doc modified.
+        self.dirty = false;
+        let mut warnings = Vec::new();
+        if let Err(error) = sync_parent_dir(std::path::Path::new(filename)) {
+            warnings.push(error);
+        }
+        if self.undo_file {
+            if let Err(error) = self.write_undo_file(filename, save_buffer.as_bytes()) {
+                warnings.push(error);
+            }
+        }
+        Ok(Saved {
+            bytes: save_buffer.len(),
+            warnings,
+        })
+    }
+    fn write_undo_file(&self, filename: &str, contents: &[u8]) -> Result<()> {
+        let undo_file = self.undo_tree.to_undo_file(fnv1a_hash(contents))?;
+        write_atomically(&hidden_sibling(filename, "wim-undo")?, undo_file.as_bytes())
+    }
+    /// Writes a copy of the document to another file, leaving its own file and modified state
+    /// alone, as `:w {file}` does. Returns the number of bytes written.
+    pub fn write_copy(&self, filename: &str) -> Result<usize> {
+        let save_buffer = self.get_save_buffer();
+        write_atomically(filename, save_buffer.as_bytes())?;
+        Ok(save_buffer.len())
+    }
+    /// Saves the document to a new file, pointing it at that file once it's written.
+    pub fn save_as(&mut self, filename: String) -> Result<Saved> {
+        let saved = self.save_to(&filename)?;
+        self.filename = Some(filename);
+        Ok(saved)
+    }
+    /// Returns the text between `start` and `end` (exclusive), split into lines.
+    #[must_use]
+    pub fn get_range_text(&self, start: Pos, end: Pos) -> Vec<String> {
+        let mut lines = Vec::new();
+        for y in start.y..=end.y.min(self.tracked_rows.len().saturating_sub(1)) {
+            let row = &self.tracked_rows[y];
+            let from = if y == start.y {
+                start.x.min(row.len())
+            } else {
+                0
+            };
+            let to = if y == end.y {
+                end.x.clamp(from, row.len())
+            } else {
+                row.len()
+            };
+            lines.push(row.get_slice(from..to).iter().collect());
+        }
+        lines
+    }
+    #[must_use]
+    pub fn get_lines_text(&self, range: Range<Coord>) -> Vec<String> {
+        self.tracked_rows
+            [range.start.min(self.tracked_rows.len())..range.end.min(self.tracked_rows.len())]
+            .iter()
+            .map(|row| row.as_slice().iter().collect())
+            .collect()
+    }
+    /// Splices charwise text into the document at `pos`. The returned position is the last
+    /// inserted character.
+    #[must_use]
+    pub fn put_text(&self, pos: Pos, lines: &[String]) -> (ChangeOp, Pos) {
+        let (row, range) = match self.tracked_rows.get(pos.y) {
+            Some(row) => (row.clone(), pos.y..pos.y + 1),
+            None => (Row::default(), pos.y..pos.y),
+        };
+        let x = pos.x.min(row.len());
+        let [prefix, suffix] = row.split_at(x);
+        let mut rows: Vec<Row> = lines.iter().map(|line| Row::from_line(line)).collect();
+        if rows.is_empty() {
+            rows.push(Row::default());
+        }
+        let last = rows.len() - 1;
+        let end = Pos {
+            x: if last == 0 {
+                (x + rows[last].len()).saturating_sub(1)
+            } else {
+                rows[last].len().saturating_sub(1)
+            },
+            y: pos.y + last,
+        };
+        rows[0] = Row::joined_rows(&prefix, &rows[0], prefix.len(), 0);
+        rows[last] = Row::joined_rows(&rows[last], &suffix, rows[last].len(), 0);
+        (ChangeOp { range, rows }, end)
+    }
+    /// Inserts whole lines before row `y`.
+    #[must_use]
+    pub fn insert_lines(&self, y: Coord, lines: &[String]) -> (ChangeOp, Pos) {
+        let y = y.min(self.tracked_rows.len());
+        (
+            ChangeOp {
+                range: y..y,
+                rows: lines.iter().map(|line| Row::from_line(line)).collect(),
+            },
+            Pos { x: 0, y },
+        )
+    }
+    /// Replaces the rows in `range` with a single empty row.
+    #[must_use]
+    pub fn blank_lines(&self, range: Range<Coord>) -> (ChangeOp, Pos) {
+        (
+            ChangeOp {
+                range: range.start.min(self.tracked_rows.len())
+                    ..range.end.min(self.tracked_rows.len()),
+                rows: vec![Row::default()],
+            },
+            Pos {
+                x: 0,
+                y: range.start,
+            },
+        )
+    }
+    #[must_use]
+    pub fn delete_lines(&self, range: Range<Coord>) -> (ChangeOp, Pos) {
+        let start = range.start.min(self.tracked_rows.len());
+        let end = range.end.min(self.tracked_rows.len());
+        // A document always keeps at least one (possibly empty) row.
+        let rows = if start == 0 && end == self.tracked_rows.len() {
+            vec![Row::default()]
+        } else {
+            vec![]
+        };
+        let remaining = self.tracked_rows.len() - (end - start) + rows.len();
+        let y = start.min(remaining.saturating_sub(1));
+        (
+            ChangeOp {
+                range: start..end,
+                rows,
+            },
+            Pos { x: 0, y },
+        )
+    }
+    /// Shifts the rows in `range` a shift width to the right, or to the left, leaving the cursor
+    /// on the first non-blank of the first of them.
+    #[must_use]
+    pub fn shift_lines(&self, range: Range<Coord>, right: bool) -> (ChangeOp, Pos) {
+        let range =
+            range.start.min(self.tracked_rows.len())..range.end.min(self.tracked_rows.len());
+        let rows: Vec<Row> = self.tracked_rows[range.clone()]
+            .iter()
+            .map(|row| Row::from_buf(self.indent.shifted(row.as_slice(), right)))
+            .collect();
+        let x = rows.first().map_or(0, |row| {
+            row.as_slice()
+                .iter()
+                .take_while(|ch| ch.is_whitespace())
+                .count()
+        });
+        let y = range.start;
+        (ChangeOp { range, rows }, Pos { x, y })
+    }
+    /// Returns the screen columns `cols` of each row in `rows`; rows that are too short
+    /// contribute whatever part of the block they reach, possibly nothing.
+    #[must_use]
+    pub fn get_block_text(&self, rows: Range<Coord>, cols: Range<Coord>) -> Vec<String> {
+        let tabstop = self.indent.tabstop;
+        self.tracked_rows
+            [rows.start.min(self.tracked_rows.len())..rows.end.min(self.tracked_rows.len())]
+            .iter()
+            .map(|row| {
+                row.get_slice(row.render_cols_to_range(cols.clone(), tabstop))
+                    .iter()
+                    .collect()
+            })
+            .collect()
+    }
+    /// Removes the screen columns `cols` from each row in `rows`.
+    #[must_use]
+    pub fn delete_block(&self, rows: Range<Coord>, cols: Range<Coord>) -> (ChangeOp, Pos) {
+        let tabstop = self.indent.tabstop;
+        let range = rows.start.min(self.tracked_rows.len())..rows.end.min(self.tracked_rows.len());
+        let rows: Vec<Row> = self.tracked_rows[range.clone()]
+            .iter()
+            .map(|row| row.splice(row.render_cols_to_range(cols.clone(), tabstop), ""))
+            .collect();
+        let x = self
+            .tracked_rows
+            .get(range.start)
+            .map_or(0, |row| row.render_cols_to_range(cols, tabstop).start);
+        (
+            ChangeOp {
+                range: range.clone(),
+                rows,
+            },
+            Pos { x, y: range.start },
+        )
+    }
+    /// Inserts `text` at screen column `col` of each row in `rows`, skipping rows too short to
+    /// reach it.
+    #[must_use]
+    pub fn insert_block_text(&self, rows: Range<Coord>, col: Coord, text: &str) -> ChangeOp {
+        let tabstop = self.indent.tabstop;
+        let range = rows.start.min(self.tracked_rows.len())..rows.end.min(self.tracked_rows.len());
+        ChangeOp {
+            rows: self.tracked_rows[range.clone()]
+                .iter()
+                .map(|row| {
+                    if row.render_width(tabstop) < col {
+                        row.clone()
+                    } else {
+                        let x = row.render_cols_to_range(col..col + 1, tabstop).start;
+                        row.splice(x..x, text)
+                    }
+                })
+                .collect(),
+            range,
+        }
+    }
+    /// Pastes a block of text so that its top left corner lands on `pos`, padding short rows with
+    /// spaces and appending rows past the end of the document as needed.
+    #[must_use]
+    pub fn put_block(&self, pos: Pos, lines: &[String]) -> (ChangeOp, Pos) {
+        let width = lines
+            .iter()
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0);
+        let start = pos.y.min(self.tracked_rows.len());
+        let end = (pos.y + lines.len()).min(self.tracked_rows.len());
+        let rows = lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                let row = self
+                    .tracked_rows
+                    .get(pos.y + i)
+                    .cloned()
+                    .unwrap_or_default();
+                let mut text: String = row.as_slice().iter().collect();
+                let len = row.len();
+                if len < pos.x {
+                    text.extend(std::iter::repeat_n(' ', pos.x - len));
+                }
+                let mut block_line = line.clone();
+                if pos.x < len {
+                    // Keep the block rectangular when there is text to its right.
+                    let pad = width - line.chars().count();
+                    block_line.extend(std::iter::repeat_n(' ', pad));
+                }
+                Row::from_line(&text).splice(pos.x..pos.x, &block_line)
+            })
+            .collect();
+        (
+            ChangeOp {
+                range: start..end,
+                rows,
+            },
+            pos,
+        )
+    }
+    #[must_use]
     pub fn split_newline(&self, cursor: Pos) -> (ChangeOp, Pos) {
         if let Some(row) = self.tracked_rows.get(cursor.y) {
             let x = cursor.x.clamp(0, row.len());
@@ -214,9 +689,9 @@ impl Doc {
                 return None;
             }
             let end_index = match noun {
-                Noun::Line => row.len(),
-                Noun::Char => std::cmp::min(cursor.x + 1, row.len()),
-                Noun::Word => row.next_word_break(cursor.x),
+                Noun::Char => row.next_grapheme(cursor.x),
+                Noun::Word => row.next_word_break(cursor.x, &self.word_chars),
+                _ => row.len(),
             };
             Some(ChangeOp {
                 range: cursor.y..cursor.y + 1,
@@ -227,11 +702,11 @@ impl Doc {
         }
     }
     pub fn delete_range(&self, range: impl RangeBounds<Pos>) -> Option<(ChangeOp, Pos)> {
-        if range.start_bound() == range.end_bound() {
-            return None;
-        }
         let start: Pos = Pos::get_start_pos(&range);
         let end: Pos = Pos::get_end_pos(&range);
+        if start >= end {
+            return None;
+        }
         let new_row = Row::joined_rows(
             &self.tracked_rows[start.y],
             &self.tracked_rows[end.y],
@@ -250,13 +725,13 @@ impl Doc {
         if let Some(row) = self.tracked_rows.get(cursor.y) {
             return match rel {
                 Rel::Next => {
-                    if row.is_empty() || cursor.x >= row.len() - 1 {
+                    if row.is_empty() || cursor.x >= row.len() {
                         return (cursor, cursor);
                     }
                     let end_index = match noun {
-                        Noun::Line => row.len(),
-                        Noun::Char => std::cmp::min(cursor.x + 1, row.len()),
-                        Noun::Word => row.next_word_break(cursor.x),
+                        Noun::Char => row.next_grapheme(cursor.x),
+                        Noun::Word => row.next_word_break(cursor.x, &self.word_chars),
+                        _ => row.len(),
                     };
                     (
                         cursor,
@@ -267,7 +742,32 @@ impl Doc {
                     )
                 }
                 Rel::Prior => {
-                    panic!()
+                    if cursor.x == 0 {
+                        if cursor.y == 0 || noun == Noun::Line {
+                            return (cursor, cursor);
+                        }
+                        // Backing up over the start of a row joins it onto the previous one.
+                        let prior_len = self.tracked_rows[cursor.y - 1].len();
+                        return (
+                            Pos {
+                                x: prior_len,
+                                y: cursor.y - 1,
+                            },
+                            cursor,
+                        );
+                    }
+                    let start_index = match noun {
+                        Noun::Char => row.prev_grapheme(cursor.x),
+                        Noun::Word => row.prev_word_break(cursor.x, &self.word_chars),
+                        _ => 0,
+                    };
+                    (
+                        Pos {
+                            x: start_index,
+                            y: cursor.y,
+                        },
+                        cursor,
+                    )
                 }
                 _ => {
                     panic!()
@@ -296,7 +796,7 @@ impl Doc {
             let start_index = match noun {
                 Noun::Line => 0,
                 Noun::Char => cursor.x - 1,
-                Noun::Word => row.prev_word_break(cursor.x),
+                Noun::Word => row.prev_word_break(cursor.x, &self.word_chars),
             };
             row.splice(start_index..cursor.x, "");
             self.dirty = true;
@@ -327,13 +827,59 @@ impl Doc {
         (Some(new_cursor_pos.x), Some(new_cursor_pos.y))
     }
     */
-    pub fn get_word_end(&self, from: Pos) -> Option<Pos> {
+    /// Finds the next match of `regex` strictly after (or before) `from`, wrapping around the
+    /// ends of the document. The returned flag is true when the search wrapped.
+    pub fn search(&self, regex: &Regex, from: Pos, direction: Direction) -> Option<(Pos, bool)> {
+        let mut text = String::new();
+        let mut offsets: Vec<(usize, Pos)> = Vec::new();
+        for cp in self.iter_from(Pos::zero()) {
+            offsets.push((text.len(), cp.pos));
+            text.push(cp.ch);
+        }
+        let matches: Vec<Pos> = regex
+            .find_iter(&text)
+            .filter_map(|m| {
+                offsets
+                    .binary_search_by_key(&m.start(), |&(offset, _)| offset)
+                    .ok()
+                    .map(|index| offsets[index].1)
+            })
+            .collect();
+        match direction {
+            Direction::Forward => match matches.iter().find(|&&pos| pos > from) {
+                Some(&pos) => Some((pos, false)),
+                None => matches.first().map(|&pos| (pos, true)),
+            },
+            Direction::Backward => match matches.iter().rev().find(|&&pos| pos < from) {
+                Some(&pos) => Some((pos, false)),
+                None => matches.last().map(|&pos| (pos, true)),
+            },
+        }
+    }
+    /// Returns the column ranges of every non-empty match of `regex` within row `y`.
+    #[must_use]
+    pub fn find_in_line(&self, y: Coord, regex: &Regex) -> Vec<Range<Coord>> {
+        let row = match self.tracked_rows.get(y) {
+            Some(row) => row,
+            None => return Vec::new(),
+        };
+        let line: String = row.as_slice().iter().collect();
+        regex
+            .find_iter(&line)
+            .filter(|m| m.start() != m.end())
+            .map(|m| {
+                let start = line[..m.start()].chars().count();
+                start..start + m.as_str().chars().count()
+            })
+            .collect()
+    }
+    pub fn get_word_end(&self, from: Pos, noun: Noun) -> Option<Pos> {
         let mut iter = self.iter_from(from);
         let first_cp = iter.next()?;
-        let mut last_class = classify(first_cp.ch);
+        let mut last_class = self.word_class(noun, first_cp.ch);
         let mut prior_pos = first_cp.pos;
         for cp in iter {
-            let new_class = classify(cp.ch);
+            let new_class = self.word_class(noun, cp.ch);
             if new_class != last_class {
                 return Some(prior_pos);
             } else {
@@ -343,14 +889,14 @@ impl Doc {
         }
         Some(prior_pos)
     }
-    pub fn get_next_word_pos(&self, from: Pos) -> Option<Pos> {
+    pub fn get_next_word_pos(&self, from: Pos, noun: Noun) -> Option<Pos> {
         let mut iter = self.iter_from(from);
         let first_cp = iter.next()?;
-        let mut last_class = classify(first_cp.ch);
+        let mut last_class = self.word_class(noun, first_cp.ch);
         let mut prior_pos = first_cp.pos;
         for cp in iter {
             trace!("fwditer({:?})", cp);
-            let new_class = classify(cp.ch);
+            let new_class = self.word_class(noun, cp.ch);
             if new_class != last_class && new_class != CharType::Space {
                 return Some(cp.pos);
             } else {
@@ -360,18 +906,18 @@ impl Doc {
         }
         Some(prior_pos)
     }
-    pub fn get_prior_word_pos(&self, from: Pos) -> Option<Pos> {
+    pub fn get_prior_word_pos(&self, from: Pos, noun: Noun) -> Option<Pos> {
         let mut iter = self.iter_from(from).rev();
         trace!("AAAA");
         iter.next()?;
         trace!("BBBB");
         let first_cp = iter.next()?;
-        let mut last_class = classify(first_cp.ch);
+        let mut last_class = self.word_class(noun, first_cp.ch);
         let mut prior_pos = first_cp.pos;
         trace!("BBBDBD");
         for cp in iter {
             trace!("reviter({:?})", cp);
-            let new_class = classify(cp.ch);
+            let new_class = self.word_class(noun, cp.ch);
             if new_class != last_class && new_class == CharType::Space {
                 return Some(prior_pos);
             } else {
@@ -381,6 +927,445 @@ impl Doc {
         }
         Some(prior_pos)
     }
+    /// Where the text object `obj` around `cursor` starts and ends (exclusively), if there is
+    /// one. `around` takes all of it rather than just its inside, and `count` goes that many
+    /// levels out for brackets and tags. Paragraphs are whole lines, ending at the start of the
+    /// line after.
+    #[must_use]
+    pub fn text_object(
+        &self,
+        cursor: Pos,
+        obj: TextObj,
+        around: bool,
+        count: usize,
+    ) -> Option<(Pos, Pos)> {
+        let row = self.tracked_rows.get(cursor.y)?;
+        let on_line = |range: Range<Coord>| {
+            (
+                Pos {
+                    x: range.start,
+                    y: cursor.y,
+                },
+                Pos {
+                    x: range.end,
+                    y: cursor.y,
+                },
+            )
+        };
+        let text = self.flat_text();
+        let at = self.flat_offset(cursor);
+        let range = match obj {
+            TextObj::Word => {
+                return word_object(row.as_slice(), cursor.x, around, |ch| {
+                    self.word_chars.classify(ch)
+                })
+                .map(on_line)
+            }
+            TextObj::BigWord => {
+                return word_object(row.as_slice(), cursor.x, around, classify_big_word)
+                    .map(on_line)
+            }
+            TextObj::Quote(quote) => {
+                return quote_object(row.as_slice(), cursor.x, quote, around).map(on_line)
+            }
+            TextObj::Paragraph => {
+                let lines = self.paragraph_object(cursor.y, around);
+                return Some((
+                    Pos {
+                        x: 0,
+                        y: lines.start,
+                    },
+                    Pos { x: 0, y: lines.end },
+                ));
+            }
+            TextObj::Sentence => sentence_object(&text, at, around)?,
+            TextObj::Bracket(open, close) => bracket_object(&text, at, open, close, around, count)?,
+            TextObj::Tag => tag_object(&text, at, around, count)?,
+        };
+        Some((self.flat_pos(range.start), self.flat_pos(range.end)))
+    }
+    /// The column of the first char in row `y` that isn't whitespace, or the row's length if
+    /// it's blank.
+    #[must_use]
+    pub fn first_non_blank(&self, y: Coord) -> Coord {
+        self.tracked_rows.get(y).map_or(0, |row| {
+            row.as_slice()
+                .iter()
+                .take_while(|ch| ch.is_whitespace())
+                .count()
+        })
+    }
+    /// Where `count` paragraph motions from `pos` end up: the first empty line after (or before)
+    /// some text, or else the end (or start) of the doc.
+    #[must_use]
+    pub fn paragraph_boundary(&self, pos: Pos, rel: Rel, count: usize) -> Pos {
+        let is_empty = |y: Coord| self.tracked_rows[y].is_empty();
+        let last = self.tracked_rows.len() - 1;
+        let mut y = pos.y;
+        for _ in 0..count.max(1) {
+            let mut seen_text = false;
+            loop {
+                seen_text |= !is_empty(y);
+                if rel == Rel::Prior {
+                    if y == 0 {
+                        return Pos { x: 0, y: 0 };
+                    }
+                    y -= 1;
+                } else {
+                    if y == last {
+                        return Pos {
+                            x: self.tracked_rows[last].len(),
+                            y: last,
+                        };
+                    }
+                    y += 1;
+                }
+                if seen_text && is_empty(y) {
+                    break;
+                }
+            }
+        }
+        Pos { x: 0, y }
+    }
+    /// Where the bracket that matches the first of `()[]{}` at or after `pos` on its line is.
+    #[must_use]
+    pub fn matching_bracket(&self, pos: Pos) -> Option<Pos> {
+        let line = self.tracked_rows.get(pos.y)?.as_slice();
+        let x = pos.x
+            + line
+                .get(pos.x..)?
+                .iter()
+                .position(|ch| "()[]{}".contains(*ch))?;
+        let (ch, other, forward) = match line[x] {
+            '(' => ('(', ')', true),
+            '[' => ('[', ']', true),
+            '{' => ('{', '}', true),
+            ')' => (')', '(', false),
+            ']' => (']', '[', false),
+            _ => ('}', '{', false),
+        };
+        let text = self.flat_text();
+        let at = self.flat_offset(Pos { x, y: pos.y });
+        let mut depth = 0;
+        let mut matches = |i: &usize| {
+            if text[*i] == ch {
+                depth += 1;
+            } else if text[*i] == other {
+                if depth == 0 {
+                    return true;
+                }
+                depth -= 1;
+            }
+            false
+        };
+        let found = if forward {
+            (at + 1..text.len()).find(&mut matches)
+        } else {
+            (0..at).rev().find(&mut matches)
+        };
+        found.map(|offset| self.flat_pos(offset))
+    }
+    /// The lines of the paragraph `y` is in, along with the blank lines after it (or before it,
+    /// if there are none after) when `around` is set.
+    fn paragraph_object(&self, y: Coord, around: bool) -> Range<Coord> {
+        let is_blank = |y: Coord| {
+            self.tracked_rows[y]
+                .as_slice()
+                .iter()
+                .all(|ch| ch.is_whitespace())
+        };
+        let blank = is_blank(y);
+        let run_end = |mut y: Coord, blank: bool| {
+            while y < self.tracked_rows.len() && is_blank(y) == blank {
+                y += 1;
+            }
+            y
+        };
+        let mut start = y;
+        while start > 0 && is_blank(start - 1) == blank {
+            start -= 1;
+        }
+        let end = run_end(y, blank);
+        if !around {
+            return start..end;
+        }
+        let after = run_end(end, !blank);
+        if after > end || blank {
+            return start..after;
+        }
+        // With nothing after it, a paragraph takes the blank lines before it instead.
+        while start > 0 && is_blank(start - 1) {
+            start -= 1;
+        }
+        start..end
+    }
+    /// The whole doc as one run of chars, with a newline between each row.
+    fn flat_text(&self) -> Vec<char> {
+        let mut text = Vec::new();
+        for (y, row) in self.tracked_rows.iter().enumerate() {
+            if y > 0 {
+                text.push('\n');
+            }
+            text.extend_from_slice(row.as_slice());
+        }
+        text
+    }
+    /// Where `pos` is in `flat_text`.
+    fn flat_offset(&self, pos: Pos) -> usize {
+        self.tracked_rows[..pos.y]
+            .iter()
+            .map(|row| row.len() + 1)
+            .sum::<usize>()
+            + pos.x
+    }
+    /// Where `offset` into `flat_text` is in the doc.
+    fn flat_pos(&self, mut offset: usize) -> Pos {
+        for (y, row) in self.tracked_rows.iter().enumerate() {
+            if offset <= row.len() {
+                return Pos { x: offset, y };
+            }
+            offset -= row.len() + 1;
+        }
+        Pos {
+            x: self.tracked_rows.last().map_or(0, Row::len),
+            y: self.tracked_rows.len().saturating_sub(1),
+        }
+    }
+}
+
+/// The run of chars in `line` of the same class as the one at `x`. Around a word, that takes the
+/// whitespace after it too, or the whitespace before it if there's none after; around
+/// whitespace, it takes the word after.
+fn word_object(
+    line: &[char],
+    x: Coord,
+    around: bool,
+    class: impl Fn(char) -> CharType,
+) -> Option<Range<Coord>> {
+    if line.is_empty() {
+        return None;
+    }
+    let x = x.min(line.len() - 1);
+    let run_end = |from: Coord| {
+        let run_class = class(line[from]);
+        from + line[from..]
+            .iter()
+            .take_while(|&&ch| class(ch) == run_class)
+            .count()
+    };
+    let run_start = |to: Coord| {
+        let run_class = class(line[to - 1]);
+        to - line[..to]
+            .iter()
+            .rev()
+            .take_while(|&&ch| class(ch) == run_class)
+            .count()
+    };
+    let start = run_start(x + 1);
+    let end = run_end(x);
+    if !around {
+        Some(start..end)
+    } else if class(line[x]) == CharType::Space {
+        Some(start..if end < line.len() { run_end(end) } else { end })
+    } else if end < line.len() && class(line[end]) == CharType::Space {
+        Some(start..run_end(end))
+    } else if start > 0 && class(line[start - 1]) == CharType::Space {
+        Some(run_start(start)..end)
+    } else {
+        Some(start..end)
+    }
+}
+
+/// Widens `range` of `text` over the whitespace after it, or if there's none, the whitespace
+/// before it, without going onto another line.
+fn with_surrounding_space(text: &[char], range: Range<usize>) -> Range<usize> {
+    let is_space = |ch: &char| *ch == ' ' || *ch == '\t';
+    let after = text[range.end..]
+        .iter()
+        .take_while(|ch| is_space(ch))
+        .count();
+    if after > 0 {
+        return range.start..range.end + after;
+    }
+    let before = text[..range.start]
+        .iter()
+        .rev()
+        .take_while(|ch| is_space(ch))
+        .count();
+    range.start - before..range.end
+}
+
+/// The quoted string in `line` that `x` is in or on, or else the first one after `x`. Quotes
+/// escaped with a backslash don't count.
+fn quote_object(line: &[char], x: Coord, quote: char, around: bool) -> Option<Range<Coord>> {
+    let quotes: Vec<Coord> = (0..line.len())
+        .filter(|&i| line[i] == quote && (i == 0 || line[i - 1] != '\\'))
+        .collect();
+    let before = quotes.iter().filter(|&&i| i < x).count();
+    let first = if quotes.get(before) == Some(&x) {
+        // On a quote, which opens a string if an even number of quotes came before it.
+        before - before % 2
+    } else if before % 2 == 1 {
+        before - 1
+    } else {
+        before
+    };
+    let (open, close) = (*quotes.get(first)?, *quotes.get(first + 1)?);
+    if around {
+        Some(with_surrounding_space(line, open..close + 1))
+    } else {
+        Some(open + 1..close)
+    }
+}
+
+/// The sentence in `text` that `at` is in, or the whitespace between sentences that it's in.
+/// Around a sentence, that takes the whitespace after it too; around whitespace, the sentence
+/// after.
+fn sentence_object(text: &[char], at: usize, around: bool) -> Option<Range<usize>> {
+    // Split the text into sentences and the whitespace between them.
+    let mut spans: Vec<Range<usize>> = Vec::new();
+    let mut i = 0;
+    while i < text.len() {
+        let start = i;
+        if text[i].is_whitespace() {
+            while i < text.len() && text[i].is_whitespace() {
+                i += 1;
+            }
+        } else {
+            while i < text.len() {
+                if text[i] == '\n' && text.get(i + 1) == Some(&'\n') {
+                    break;
+                }
+                let ends_sentence = matches!(text[i], '.' | '!' | '?');
+                i += 1;
+                if ends_sentence {
+                    while i < text.len() && matches!(text[i], ')' | ']' | '"' | '\'') {
+                        i += 1;
+                    }
+                    if i == text.len() || text[i].is_whitespace() {
+                        break;
+                    }
+                }
+            }
+        }
+        spans.push(start..i);
+    }
+    let index = spans
+        .iter()
+        .position(|span| span.contains(&at))
+        .or_else(|| spans.len().checked_sub(1))?;
+    let span = spans[index].clone();
+    if !around {
+        return Some(span);
+    }
+    let is_space = text[span.start].is_whitespace();
+    match spans.get(index + 1) {
+        Some(next) if is_space || text[next.start].is_whitespace() => Some(span.start..next.end),
+        _ if !is_space && index > 0 => Some(spans[index - 1].start..span.end),
+        _ => Some(span),
+    }
+}
+
+/// The `count`th pair of `open` and `close` brackets in `text` out from `at`, matching nested
+/// pairs. The inside of a pair leaves out a line break just after the open bracket and the
+/// indent before a close bracket that starts its line.
+fn bracket_object(
+    text: &[char],
+    at: usize,
+    open: char,
+    close: char,
+    around: bool,
+    count: usize,
+) -> Option<Range<usize>> {
+    let at = at.min(text.len().checked_sub(1)?);
+    // Work back to the unmatched open bracket, `count` levels out.
+    let mut start = if text[at] == close { at } else { at + 1 };
+    let mut depth = 0;
+    let mut levels = count.max(1);
+    let open_at = loop {
+        start = start.checked_sub(1)?;
+        if text[start] == close && start != at {
+            depth += 1;
+        } else if text[start] == open {
+            if depth == 0 {
+                levels -= 1;
+                if levels == 0 {
+                    break start;
+                }
+            } else {
+                depth -= 1;
+            }
+        }
+    };
+    let mut depth = 0;
+    let close_at = open_at
+        + 1
+        + text[open_at + 1..].iter().position(|&ch| {
+            if ch == open {
+                depth += 1;
+            } else if ch == close {
+                if depth == 0 {
+                    return true;
+                }
+                depth -= 1;
+            }
+            false
+        })?;
+    if around {
+        return Some(open_at..close_at + 1);
+    }
+    let mut inner = open_at + 1..close_at;
+    if text.get(inner.start) == Some(&'\n') {
+        inner.start += 1;
+    }
+    let line_start = text[..close_at]
+        .iter()
+        .rposition(|&ch| ch == '\n')
+        .map_or(0, |i| i + 1);
+    if line_start > inner.start
+        && text[line_start..close_at]
+            .iter()
+            .all(|ch| ch.is_whitespace())
+    {
+        inner.end = line_start;
+    }
+    inner.end = inner.end.max(inner.start);
+    Some(inner)
+}
+
+/// The `count`th XML or HTML element in `text` out from `at`, as its contents or from the start
+/// of its start tag to the end of its end tag.
+fn tag_object(text: &[char], at: usize, around: bool, count: usize) -> Option<Range<usize>> {
+    let string: String = text.iter().collect();
+    let regex = Regex::new(r"<(/?)([A-Za-z][^\s/>]*)[^>]*?(/?)>").unwrap();
+    // Pair up start and end tags, as char offsets, dropping start tags that are never ended.
+    let to_offset = |byte: usize| string[..byte].chars().count();
+    let mut open: Vec<(&str, Range<usize>)> = Vec::new();
+    let mut elements: Vec<(Range<usize>, Range<usize>)> = Vec::new();
+    for captures in regex.captures_iter(&string) {
+        let tag = captures.get(0).unwrap();
+        let range = to_offset(tag.start())..to_offset(tag.end());
+        let name = captures.get(2).unwrap().as_str();
+        if !captures[1].is_empty() {
+            if let Some(i) = open.iter().rposition(|(open_name, _)| *open_name == name) {
+                elements.push((open[i].1.clone(), range));
+                open.truncate(i);
+            }
+        } else if captures[3].is_empty() {
+            open.push((name, range));
+        }
+    }
+    let mut enclosing: Vec<_> = elements
+        .into_iter()
+        .filter(|(start, end)| start.start <= at && at < end.end)
+        .collect();
+    enclosing.sort_by_key(|(start, _)| std::cmp::Reverse(start.start));
+    let (start, end) = enclosing.get(count.max(1) - 1)?;
+    if around {
+        Some(start.start..end.end)
+    } else {
+        Some(start.end..end.start)
+    }
 }
 
 pub struct IterLines<'a> {
@@ -518,3 +1503,142 @@ impl fmt::Display for Doc {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    fn doc(lines: &[&str]) -> Doc {
+        let mut doc = Doc::empty();
+        doc.tracked_rows = lines.iter().map(|line| Row::from_line(line)).collect();
+        doc
+    }
+
+    /// The text that `obj` covers with the cursor at `pos`, with rows joined by newlines.
+    fn object(lines: &[&str], pos: (Coord, Coord), obj: TextObj, around: bool) -> Option<String> {
+        let doc = doc(lines);
+        let (start, end) = doc.text_object(Pos { x: pos.0, y: pos.1 }, obj, around, 1)?;
+        let text = doc.flat_text();
+        Some(
+            text[doc.flat_offset(start)..doc.flat_offset(end)]
+                .iter()
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn failed_save_as_keeps_the_name() {
+        let mut doc = doc(&["one"]);
+        assert!(doc
+            .save_as("/nonexistent-wim-dir/x.txt".to_string())
+            .is_err());
+        assert_eq!(doc.get_filename(), None);
+    }
+
+    #[test]
+    fn failed_undo_file_is_a_warning() -> Result<()> {
+        let dir = TempDir::new("save")?;
+        let filename = dir.join("a.txt").to_string_lossy().to_string();
+        // A directory in the undo file's way.
+        std::fs::create_dir(dir.join(".a.txt.wim-undo"))?;
+        let mut doc = doc(&["one"]);
+        doc.undo_file = true;
+        doc.dirty = true;
+        let saved = doc.save_as(filename.clone())?;
+        assert_eq!(saved.warnings.len(), 1);
+        assert!(!doc.is_dirty());
+        assert_eq!(std::fs::read_to_string(&filename)?, "one\n");
+        Ok(())
+    }
+
+    #[test]
+    fn words_take_the_space_after_them() {
+        let line = ["foo.bar  baz"];
+        let word = |x, around| object(&line, (x, 0), TextObj::Word, around).unwrap();
+        assert_eq!(word(1, false), "foo");
+        assert_eq!(word(5, true), "bar  ");
+        assert_eq!(word(10, true), "  baz");
+        assert_eq!(word(7, true), "  baz");
+        assert_eq!(
+            object(&line, (1, 0), TextObj::BigWord, true).unwrap(),
+            "foo.bar  "
+        );
+    }
+
+    #[test]
+    fn sentences_end_at_punctuation_and_blank_lines() {
+        let lines = ["One. Two (really!) three.", "Four?", "", "Five"];
+        let sentence = |pos, around| object(&lines, pos, TextObj::Sentence, around).unwrap();
+        assert_eq!(sentence((0, 0), true), "One. ");
+        assert_eq!(sentence((6, 0), false), "Two (really!)");
+        assert_eq!(sentence((20, 0), false), "three.");
+        assert_eq!(sentence((2, 1), false), "Four?");
+        assert_eq!(sentence((2, 3), false), "Five");
+    }
+
+    #[test]
+    fn paragraphs_are_whole_lines() {
+        let lines = ["a", "b", "", " ", "c"];
+        let doc = doc(&lines);
+        let lines = |pos, around| {
+            let (start, end) = doc.text_object(pos, TextObj::Paragraph, around, 1).unwrap();
+            start.y..end.y
+        };
+        assert_eq!(lines(Pos { x: 0, y: 1 }, false), 0..2);
+        assert_eq!(lines(Pos { x: 0, y: 1 }, true), 0..4);
+        assert_eq!(lines(Pos { x: 0, y: 2 }, false), 2..4);
+        assert_eq!(lines(Pos { x: 0, y: 4 }, true), 2..5);
+    }
+
+    #[test]
+    fn quotes_pair_up_skipping_escapes() {
+        let line = [r#"x = "a \" b" + "c";"#];
+        let quote = |x, around| object(&line, (x, 0), TextObj::Quote('"'), around);
+        assert_eq!(quote(0, false).unwrap(), r#"a \" b"#);
+        assert_eq!(quote(4, true).unwrap(), r#""a \" b" "#);
+        assert_eq!(quote(13, false).unwrap(), "c");
+        assert_eq!(quote(18, false), None);
+    }
+
+    #[test]
+    fn brackets_match_nested_pairs() {
+        let bracket = TextObj::Bracket('(', ')');
+        let line = ["f(a, (b), c)"];
+        assert_eq!(object(&line, (3, 0), bracket, false).unwrap(), "a, (b), c");
+        assert_eq!(object(&line, (6, 0), bracket, true).unwrap(), "(b)");
+        assert_eq!(object(&line, (7, 0), bracket, false).unwrap(), "b");
+        let doc = doc(&line);
+        let (start, _) = doc
+            .text_object(Pos { x: 6, y: 0 }, bracket, true, 2)
+            .unwrap();
+        assert_eq!(start.x, 1);
+        let block = ["fn f() {", "    x;", "}"];
+        let braces = TextObj::Bracket('{', '}');
+        assert_eq!(object(&block, (4, 1), braces, false).unwrap(), "    x;\n");
+        assert_eq!(object(&block, (0, 0), braces, false), None);
+    }
+
+    #[test]
+    fn brackets_and_paragraphs_span_lines() {
+        let doc = doc(&["fn f() {", "    (x)", "}", "", "", "y"]);
+        let pos = |x, y| Pos { x, y };
+        assert_eq!(doc.matching_bracket(pos(6, 0)), Some(pos(0, 2)));
+        assert_eq!(doc.matching_bracket(pos(0, 2)), Some(pos(7, 0)));
+        assert_eq!(doc.matching_bracket(pos(0, 1)), Some(pos(6, 1)));
+        assert_eq!(doc.matching_bracket(pos(0, 3)), None);
+        assert_eq!(doc.paragraph_boundary(pos(0, 0), Rel::Next, 1), pos(0, 3));
+        assert_eq!(doc.paragraph_boundary(pos(0, 0), Rel::Next, 2), pos(1, 5));
+        assert_eq!(doc.paragraph_boundary(pos(0, 5), Rel::Prior, 1), pos(0, 4));
+        assert_eq!(doc.paragraph_boundary(pos(0, 4), Rel::Prior, 1), pos(0, 0));
+    }
+
+    #[test]
+    fn tags_pair_start_and_end_tags() {
+        let lines = [r#"<div class="x"><p>hi <br/>there</p>"#, "</div>"];
+        let tag = |pos, around| object(&lines, pos, TextObj::Tag, around).unwrap();
+        assert_eq!(tag((19, 0), false), "hi <br/>there");
+        assert_eq!(tag((19, 0), true), "<p>hi <br/>there</p>");
+        assert_eq!(tag((2, 1), false), "<p>hi <br/>there</p>\n");
+    }
+}
@@ -1,19 +1,35 @@
-use crate::bindings::{Bindings, BindingsBuilder};
+use crate::bindings::{Bindings, BindingsBuilder, KeysLike};
+use crate::buffers::{BufferId, BuffersRef, DocRef};
+use crate::classify::WordChars;
 use crate::consts::{
-    PROP_DOCVIEW_CURSOR_POS, PROP_DOCVIEW_STATUS, PROP_DOC_FILENAME, PROP_DOC_IS_MODIFIED,
+    PROP_DOCVIEW_BUFFER_ID, PROP_DOCVIEW_CURSOR_POS, PROP_DOCVIEW_HEIGHT, PROP_DOCVIEW_MODE,
+    PROP_DOCVIEW_MODIFIED_BUFFER, PROP_DOCVIEW_WIDTH, PROP_DOC_FILENAME, PROP_DOC_FILE_TYPE,
+    PROP_DOC_IS_MODIFIED, PROP_DOC_LINE_COUNT,
 };
-use crate::doc::Doc;
-use crate::error::{ensure, Result};
+use crate::doc::{Doc, Saved, TextObj};
+use crate::error::{ensure, Error, Result};
+use crate::ex::LineSpec;
+use crate::filetype;
+use crate::gutter::{Gutter, LineNumbers, Sign};
+use crate::marks::{
+    FileMark, Jump, JumpList, Marks, CHANGE_END, CHANGE_START, LAST_CHANGE, LAST_INSERT, LAST_JUMP,
+};
+use crate::motion::{self, CharFind, MotionKind};
 use crate::plugin::PluginRef;
 use crate::prelude::*;
+use crate::registers::{Register, RegisterKind, Registers, RegistersRef};
 use crate::rel::Rel;
+use crate::row::{RenderCluster, Row};
+use crate::search::{Direction, SearchRef};
 use crate::status::Status;
+use crate::syntax::{scope_group, Grammar};
+use crate::theme::Group;
 use crate::types::{Coord, Pos, Rect, RelCoord};
-use crate::undo::ChangeOp;
+use crate::undo::{ChangeOp, Travel};
 use crate::view::ViewContext;
 use mode::*;
-// use std::fs::OpenOptions;
-// use std::io::{Seek, SeekFrom, Write};
+use regex::Regex;
+use std::path::Path;
 
 pub struct DocView {
     _plugin: PluginRef,
@@ -21,9 +37,51 @@ pub struct DocView {
     cursor: Pos,
     sel: Option<Sel>,
     render_cursor_x: Coord,
-    doc: Doc,
+    doc: DocRef,
     scroll_offset: Pos,
     mode: Mode,
+    registers: RegistersRef,
+    buffers: BuffersRef,
+    /// The buffer this view is showing; `doc` is its document.
+    buffer_id: BufferId,
+    /// The register selected with a `"x` prefix for the next yank, delete or put.
+    register: Option<char>,
+    search: SearchRef,
+    gutter: Gutter,
+    /// Whether tabs, trailing spaces and line ends are drawn visibly, as in vim's list mode.
+    list: bool,
+    block_insert: Option<BlockInsert>,
+    /// A count typed before an operator or register prefix, waiting for the command it applies
+    /// to.
+    pending_count: Option<usize>,
+    /// The arguments of an `f`, `t`, `F` or `T` that's waiting for the char to find.
+    awaiting_find: Option<Vec<Variant>>,
+    /// The last char find, which `;` and `,` repeat.
+    last_find: Option<CharFind>,
+    /// How many rows of text the view had room for when it was last laid out, which `H`, `M` and
+    /// `L` move within.
+    text_height: Coord,
+    /// The room the window had, status line and all, when it was last laid out.
+    window_size: Size,
+    /// The places this window jumped from, which Ctrl-o and Ctrl-i go back and forth through.
+    jumps: JumpList,
+    /// The commands and keys of the change being made, if one is under way.
+    change: Vec<DK>,
+    /// The last complete change, replayed by `.`.
+    last_change: Vec<DK>,
+    settings: Rc<Settings>,
+}
+
+/// A pending insert into every row of a block selection. Text typed into the top row is copied
+/// into `rows` when insert mode ends.
+#[derive(Clone, Debug)]
+struct BlockInsert {
+    rows: Range<Coord>,
+    /// Where the text goes in the top row, and the screen column it goes at in the others.
+    x: Coord,
+    col: Coord,
+    y: Coord,
+    row_len: Coord,
 }
 
 #[allow(dead_code)]
@@ -45,13 +103,6 @@ impl DocView {
     pub fn move_cursor(&mut self, x: RelCoord, y: RelCoord) -> Result<Status> {
         self.cursor.y = (self.cursor.y as RelCoord + y).clamp(0, RelCoord::MAX) as Coord;
         self.cursor.x = (self.cursor.x as RelCoord + x).clamp(0, RelCoord::MAX) as Coord;
-        if matches!(self.mode, Mode::Visual(VisualMode::Char)) {
-            if let Some(ref mut sel) = self.sel {
-                sel.end = self.cursor;
-            } else {
-                panic!("There should be a sel right now!");
-            }
-        }
         self.clamp_cursor();
         Ok(Status::Ok)
     }
@@ -63,82 +114,576 @@ impl DocView {
     }
 
     pub fn do_op_to_range(&mut self, op: Op, range: impl RangeBounds<Pos>) -> Result<Status> {
+        let start = Pos::get_start_pos(&range);
+        let end = Pos::get_end_pos(&range);
+        let register = Register::charwise(self.doc.borrow().get_range_text(start, end));
+        let name = self.register.take();
         match op {
             Op::Delete => {
+                self.registers.borrow_mut().delete(name, register);
                 let ret = self.delete_range(range);
                 self.switch_mode(Mode::Normal);
                 ret
             }
             Op::Change => {
-                let ret = self.delete_range(range);
+                self.registers.borrow_mut().delete(name, register);
+                // Entering insert mode first makes the delete part of the insert's change.
                 self.switch_mode(Mode::Insert);
-                ret
+                self.delete_range(range)
             }
             Op::Yank => {
-                panic!("yank range not impl");
+                self.registers.borrow_mut().yank(name, register);
+                self.switch_mode(Mode::Normal);
+                let last = Pos {
+                    x: end.x.saturating_sub(1),
+                    y: end.y,
+                };
+                self.mark_yank(start, last);
+                self.jump_cursor_pos(Some(start));
+                Ok(Status::Ok)
+            }
+            Op::ShiftLeft | Op::ShiftRight => self.shift_lines(op, start.y..end.y + 1),
+        }
+    }
+
+    pub fn do_op_to_lines(&mut self, op: Op, range: Range<Coord>) -> Result<Status> {
+        let lines = self.doc.borrow().get_lines_text(range.clone());
+        let line_count = lines.len();
+        let register = Register::linewise(lines);
+        let name = self.register.take();
+        match op {
+            Op::Delete => {
+                self.registers.borrow_mut().delete(name, register);
+                self.switch_mode(Mode::Normal);
+                let op_pos = self.doc.borrow().delete_lines(range);
+                self.apply_op_pos(op_pos)
+            }
+            Op::Change => {
+                self.registers.borrow_mut().delete(name, register);
+                self.switch_mode(Mode::Insert);
+                let op_pos = self.doc.borrow().blank_lines(range);
+                self.apply_op_pos(op_pos)
+            }
+            Op::Yank => {
+                self.registers.borrow_mut().yank(name, register);
+                self.switch_mode(Mode::Normal);
+                self.mark_yank(
+                    Pos {
+                        x: 0,
+                        y: range.start,
+                    },
+                    Pos {
+                        x: 0,
+                        y: range.end.saturating_sub(1),
+                    },
+                );
+                // Like vim, the cursor ends up on the first line yanked, which it may not have
+                // started on after `yk` or `Vjy`.
+                self.jump_cursor(None, Some(range.start));
+                if line_count > 2 {
+                    Ok(status!("{} lines yanked", line_count))
+                } else {
+                    Ok(Status::Ok)
+                }
+            }
+            Op::ShiftLeft | Op::ShiftRight => self.shift_lines(op, range),
+        }
+    }
+
+    /// Shifts `lines` a shift width to the left or right, as `op` says.
+    fn shift_lines(&mut self, op: Op, lines: Range<Coord>) -> Result<Status> {
+        self.switch_mode(Mode::Normal);
+        let line_count = lines.len();
+        let op_pos = self.doc.borrow().shift_lines(lines, op == Op::ShiftRight);
+        self.apply_op_pos(op_pos)?;
+        if line_count > 2 {
+            Ok(status!("{} lines {}ed 1 time", line_count, op.as_str()))
+        } else {
+            Ok(Status::Ok)
+        }
+    }
+
+    pub fn put(&mut self, after: bool, count: usize) -> Result<Status> {
+        let name = self.register.take();
+        let register = match self.registers.borrow().get(name) {
+            Some(register) => register.repeated(count),
+            None => {
+                return Ok(status!("Nothing in register {}", name.unwrap_or('"')));
+            }
+        };
+        match register.kind {
+            RegisterKind::Linewise => {
+                let y = self.cursor.y + usize::from(after);
+                let op_pos = self.doc.borrow().insert_lines(y, &register.lines);
+                self.apply_op_pos(op_pos)
+            }
+            RegisterKind::Blockwise => {
+                let row_is_empty = self
+                    .doc
+                    .borrow()
+                    .get_row(self.cursor.y)
+                    .is_none_or(|row| row.is_empty());
+                let pos = Pos {
+                    x: self.cursor.x + usize::from(after && !row_is_empty),
+                    y: self.cursor.y,
+                };
+                let op_pos = self.doc.borrow().put_block(pos, &register.lines);
+                self.apply_op_pos(op_pos)
+            }
+            RegisterKind::Charwise => {
+                let row_is_empty = self
+                    .doc
+                    .borrow()
+                    .get_row(self.cursor.y)
+                    .is_none_or(|row| row.is_empty());
+                let pos = Pos {
+                    x: self.cursor.x + usize::from(after && !row_is_empty),
+                    y: self.cursor.y,
+                };
+                let op_pos = self.doc.borrow().put_text(pos, &register.lines);
+                self.apply_op_pos(op_pos)
             }
         }
     }
 
-    pub fn do_op_rel(&mut self, op: Op, noun: Noun, rel: Rel) -> Result<Status> {
-        trace!("do_op_rel({:?}, {:?}, {:?})", op, noun, rel);
-        let end_pos: Option<Pos> = match (noun, rel) {
-            (Noun::Char, Rel::Prior) => Some(self.get_rel_cursor_pos(-1, 0)),
-            (Noun::Char, Rel::Next) => Some(self.get_rel_cursor_pos(1, 0)),
-            (Noun::Line, Rel::Prior) => Some(self.get_rel_cursor_pos(0, -1)),
-            (Noun::Line, Rel::Next) => Some(self.get_rel_cursor_pos(0, 1)),
-            (Noun::Word, Rel::Next) => self.doc.get_next_word_pos(self.cursor),
-            (Noun::Word, Rel::Prior) => self.doc.get_prior_word_pos(self.cursor),
-            (Noun::Word, Rel::End) => self.doc.get_word_end(self.cursor),
+    /// Where stepping `count` graphemes back or on along the cursor's row ends up, unclamped.
+    fn grapheme_steps(&self, rel: Rel, count: usize) -> Pos {
+        let mut pos = self.cursor;
+        if let Some(row) = self.doc.borrow().get_row(pos.y) {
+            for _ in 0..count {
+                pos.x = match rel {
+                    Rel::Prior => row.prev_grapheme(pos.x),
+                    _ => row.next_grapheme(pos.x),
+                };
+            }
+        }
+        pos
+    }
+    /// Where `count` repetitions of a relative motion from the cursor end up. Motions that go
+    /// to a line, like `G` and `H`, take the count as the line instead.
+    fn motion_target(&self, noun: Noun, rel: Rel, count: Option<usize>) -> Result<Option<Pos>> {
+        let given_count = count;
+        let count = count.unwrap_or(1);
+        let n = count as RelCoord;
+        let on_first_non_blank = |y: Coord| {
+            Some(Pos {
+                x: self.doc.borrow().first_non_blank(y),
+                y,
+            })
+        };
+        let last_row = self.doc.borrow().line_count().saturating_sub(1);
+        let mut pos = self.cursor;
+        for i in 0..count {
+            let next = match (noun, rel) {
+                (Noun::Char, Rel::Prior | Rel::Next) => {
+                    return Ok(Some(self.clamped_pos(self.grapheme_steps(rel, count))))
+                }
+                (Noun::Line, Rel::Prior) => return Ok(Some(self.get_rel_cursor_pos(0, -n))),
+                (Noun::Line, Rel::Next) => return Ok(Some(self.get_rel_cursor_pos(0, n))),
+                (Noun::Line, Rel::Begin) => return Ok(Some(Pos { x: 0, y: pos.y })),
+                (Noun::Line, Rel::End) => {
+                    // A count goes to the end of that many lines on.
+                    let y = (pos.y + count - 1).min(last_row);
+                    let len = self.doc.borrow().get_row(y).map_or(0, Row::len);
+                    return Ok(Some(Pos {
+                        x: len.saturating_sub(1),
+                        y,
+                    }));
+                }
+                (Noun::Text, Rel::Begin) => return Ok(on_first_non_blank(pos.y)),
+                (Noun::Doc, Rel::Begin | Rel::End) => {
+                    let y = match (given_count, rel) {
+                        (Some(line), _) => line - 1,
+                        (None, Rel::Begin) => 0,
+                        (None, _) => last_row,
+                    };
+                    return Ok(on_first_non_blank(y.min(last_row)));
+                }
+                (Noun::Paragraph, Rel::Prior | Rel::Next) => {
+                    return Ok(Some(self.doc.borrow().paragraph_boundary(pos, rel, count)))
+                }
+                (Noun::Bracket, Rel::Next) => return Ok(self.doc.borrow().matching_bracket(pos)),
+                (Noun::Screen, Rel::Begin | Rel::Middle | Rel::End) => {
+                    let top = self.scroll_offset.y.min(last_row);
+                    let bottom =
+                        (self.scroll_offset.y + self.text_height.max(1) - 1).clamp(top, last_row);
+                    let y = match rel {
+                        Rel::Begin => (top + count - 1).min(bottom),
+                        Rel::Middle => (top + bottom) / 2,
+                        _ => bottom.saturating_sub(count - 1).max(top),
+                    };
+                    return Ok(on_first_non_blank(y));
+                }
+                (Noun::Word | Noun::BigWord, Rel::Next) => {
+                    self.doc.borrow().get_next_word_pos(pos, noun)
+                }
+                (Noun::Word | Noun::BigWord, Rel::Prior) => {
+                    self.doc.borrow().get_prior_word_pos(pos, noun)
+                }
+                // Repeats carry on to the end of the following word.
+                (Noun::Word | Noun::BigWord, Rel::End) if i > 0 => self
+                    .doc
+                    .borrow()
+                    .get_next_word_pos(pos, noun)
+                    .and_then(|next| self.doc.borrow().get_word_end(next, noun)),
+                (Noun::Word | Noun::BigWord, Rel::End) => self.doc.borrow().get_word_end(pos, noun),
+                _ => {
+                    return Err(not_impl!(
+                        "DocView: Don't know how to handle relative motion for ({:?}, {:?}).",
+                        noun,
+                        rel
+                    ));
+                }
+            };
+            match next {
+                Some(next) => pos = next,
+                None => break,
+            }
+        }
+        Ok(Some(pos))
+    }
+    pub fn do_op_rel(
+        &mut self,
+        op: Op,
+        noun: Noun,
+        rel: Rel,
+        count: Option<usize>,
+    ) -> Result<Status> {
+        trace!("do_op_rel({:?}, {:?}, {:?}, {:?})", op, noun, rel, count);
+        let mut target = self.motion_target(noun, rel, count)?;
+        // An operator over the last word on a line stops at the end of that line, rather than
+        // taking the line break and the next line's indent too.
+        if let (Noun::Word | Noun::BigWord, Rel::Next, Some(pos)) = (noun, rel, target) {
+            if pos.y > self.cursor.y {
+                let y = pos.y - 1;
+                let x = self.doc.borrow().get_row(y).map_or(0, Row::len);
+                target = Some(Pos { x, y });
+            }
+        }
+        self.go_to(target, MotionKind::of(noun, rel))
+    }
+    /// Moves the cursor to `target`, or with an operator pending, operates on the text between
+    /// the cursor and `target` as `kind` says. A pending operator with nowhere to go is
+    /// cancelled.
+    fn go_to(&mut self, target: Option<Pos>, kind: MotionKind) -> Result<Status> {
+        let (op, target) = match (self.mode, target) {
+            (Mode::NormalWithOp(op), Some(target)) => (op, target),
+            (Mode::NormalWithOp(_), None) => {
+                self.switch_mode(Mode::Normal);
+                return Ok(Status::Ok);
+            }
             _ => {
-                return Err(not_impl!(
-                    "DocView: Don't know how to handle relative motion for ({:?}, {:?}).",
-                    noun,
-                    rel
-                ));
+                self.jump_cursor_pos(target);
+                return Ok(Status::Ok);
             }
         };
-        if let Some(end_pos) = end_pos {
-            self.do_op_to_range(op, self.cursor..=end_pos)
+        let (start, end) = if target < self.cursor {
+            (target, self.cursor)
+        } else {
+            (self.cursor, target)
+        };
+        let row_len = |y: Coord| self.doc.borrow().get_row(y).map_or(0, Row::len);
+        match kind {
+            MotionKind::Linewise => self.do_op_to_lines(op, start.y..end.y + 1),
+            MotionKind::Inclusive => {
+                let end_x = self
+                    .doc
+                    .borrow()
+                    .get_row(end.y)
+                    .map_or(0, |row| row.next_grapheme(end.x).min(row.len()));
+                self.do_op_to_range(op, start..Pos { x: end_x, y: end.y })
+            }
+            // An exclusive motion to the start of a later line stops at the end of the line
+            // before, or takes whole lines if it started in the indent.
+            MotionKind::Exclusive if end.x == 0 && end.y > start.y => {
+                if start.x <= self.doc.borrow().first_non_blank(start.y) {
+                    self.do_op_to_lines(op, start.y..end.y)
+                } else {
+                    let y = end.y - 1;
+                    self.do_op_to_range(op, start..Pos { x: row_len(y), y })
+                }
+            }
+            MotionKind::Exclusive => self.do_op_to_range(op, start..end),
+        }
+    }
+    /// Moves to, or operates up to, the char that `find` finds on the cursor's line.
+    fn find_char(&mut self, find: CharFind, count: Option<usize>, repeat: bool) -> Result<Status> {
+        let target =
+            self.doc.borrow().get_row(self.cursor.y).and_then(|row| {
+                find.target(row.as_slice(), self.cursor.x, count.unwrap_or(1), repeat)
+            });
+        let target = target.map(|x| Pos {
+            x,
+            y: self.cursor.y,
+        });
+        self.go_to(target, find.kind())
+    }
+    /// Remembers the cursor as a place jumped from, for `''` and Ctrl-o to go back to.
+    fn push_jump(&mut self) {
+        self.jumps.push(Jump {
+            buffer: self.buffer_id,
+            pos: self.cursor,
+        });
+        self.doc
+            .borrow_mut()
+            .marks_mut()
+            .set(LAST_JUMP, self.cursor);
+    }
+    /// Sets mark `name` at the cursor. Uppercase marks are kept with the buffer list, since
+    /// they can be jumped to from any buffer.
+    fn set_mark(&mut self, name: char) {
+        if Marks::is_global(name) {
+            self.buffers
+                .borrow_mut()
+                .set_file_mark(name, self.buffer_id, self.cursor);
         } else {
-            Err(error!("couldn't get an end pos?!"))
+            self.doc.borrow_mut().marks_mut().set(name, self.cursor);
+        }
+    }
+    /// Jumps to (or, with a pending operator, operates up to) mark `name`: to the first
+    /// non-blank of its line if `linewise`, as `'` does, or else to just where it is, as `` ` ``
+    /// does. A global mark in another file shows that file, opening it if need be.
+    fn goto_mark(&mut self, name: char, linewise: bool) -> Result<Status> {
+        let found = if Marks::is_global(name) {
+            self.buffers.borrow().file_mark(name)
+        } else {
+            self.doc.borrow().marks().get(name).map(|pos| FileMark {
+                buffer: self.buffer_id,
+                filename: None,
+                pos,
+            })
+        };
+        let op_pending = matches!(self.mode, Mode::NormalWithOp(_));
+        let mark = match found {
+            // An operator can't reach into another buffer.
+            Some(mark) if mark.buffer == self.buffer_id || !op_pending => mark,
+            _ => {
+                if op_pending {
+                    self.change.clear();
+                    self.switch_mode(Mode::Normal);
+                }
+                return Err(error!("E20: Mark not set"));
+            }
+        };
+        if !op_pending {
+            self.push_jump();
+        }
+        let mut status = Status::Ok;
+        if mark.buffer != self.buffer_id {
+            let is_open = self.buffers.borrow().get(mark.buffer).is_some();
+            status = match (is_open, &mark.filename) {
+                (true, _) => self.show_buffer(mark.buffer)?,
+                (false, Some(filename)) => self.open(filename.clone(), false)?,
+                (false, None) => return Err(error!("E20: Mark not set")),
+            };
+            // The file may have been opened into a new buffer, which the mark now belongs to.
+            if self.buffers.borrow().file_mark(name).as_ref() != Some(&mark) {
+                self.buffers
+                    .borrow_mut()
+                    .set_file_mark(name, self.buffer_id, mark.pos);
+            }
+        }
+        let last_row = self.doc.borrow().line_count().saturating_sub(1);
+        let y = mark.pos.y.min(last_row);
+        if linewise {
+            let x = self.doc.borrow().first_non_blank(y);
+            self.go_to(Some(Pos { x, y }), MotionKind::Linewise)?;
+        } else {
+            let pos = self.clamped_pos(Pos { x: mark.pos.x, y });
+            self.go_to(Some(pos), MotionKind::Exclusive)?;
+        }
+        Ok(status)
+    }
+    /// Goes `count` places back through the jump list, or on through it if `newer`, as Ctrl-o
+    /// and Ctrl-i do.
+    fn follow_jump(&mut self, newer: bool, count: usize) -> Result<Status> {
+        let jump = if newer {
+            self.jumps.forward(count)
+        } else {
+            let current = Jump {
+                buffer: self.buffer_id,
+                pos: self.cursor,
+            };
+            self.jumps.back(current, count)
+        };
+        let jump = match jump {
+            Some(jump) => jump,
+            None => return Ok(Status::Ok),
+        };
+        let mut status = Status::Ok;
+        if jump.buffer != self.buffer_id {
+            if self.buffers.borrow().get(jump.buffer).is_none() {
+                return Ok(status!("Buffer {} does not exist", jump.buffer));
+            }
+            status = self.show_buffer(jump.buffer)?;
         }
+        let last_row = self.doc.borrow().line_count().saturating_sub(1);
+        self.jump_cursor(Some(jump.pos.x), Some(jump.pos.y.min(last_row)));
+        Ok(status)
     }
-    pub fn move_cursor_rel(&mut self, noun: Noun, rel: Rel) -> Result<Status> {
-        trace!("move_cursor_rel({:?}, {:?})", noun, rel);
+    /// Plays the macro in register `name` `count` times, by dispatching its keys as if they'd
+    /// been typed. Macros can play macros, themselves included, up to a limit. Playing stops
+    /// early when a command fails or a motion can't move.
+    fn play_macro(&mut self, name: char, count: usize) -> Result<Status> {
+        let keys = match self.registers.borrow_mut().play(name) {
+            Ok(keys) => keys,
+            Err(error) => return Ok(status!("{}", error.message())),
+        };
+        let dks = std::iter::repeat_n(keys, count)
+            .flatten()
+            .map(DK::Key)
+            .collect();
+        Ok(Status::Dispatch(DK::Sequence(dks)))
+    }
+    /// Operates on, or in visual mode selects, the text object around the cursor.
+    fn select_text_object(&mut self, obj: TextObj, around: bool, count: usize) -> Result<Status> {
+        let found = self
+            .doc
+            .borrow()
+            .text_object(self.cursor, obj, around, count);
+
+        let (start, end) = match found {
+            Some(range) => range,
+            None if matches!(self.mode, Mode::NormalWithOp(_)) => {
+                self.switch_mode(Mode::Normal);
+                return Ok(Status::Ok);
+            }
+            None => return Ok(Status::Ok),
+        };
+        match self.mode {
+            Mode::NormalWithOp(op) if obj == TextObj::Paragraph => {
+                self.do_op_to_lines(op, start.y..end.y)
+            }
+            Mode::NormalWithOp(op) if start < end => self.do_op_to_range(op, start..end),
+            Mode::NormalWithOp(_) => {
+                self.switch_mode(Mode::Normal);
+                Ok(Status::Ok)
+            }
+            _ => {
+                // Paragraphs are whole lines, so they're selected linewise.
+                let last = if obj == TextObj::Paragraph {
+                    self.switch_mode(Mode::Visual(VisualMode::Line));
+                    Pos {
+                        x: 0,
+                        y: end.y.saturating_sub(1).max(start.y),
+                    }
+                } else if start >= end {
+                    start
+                } else if end.x > 0 {
+                    Pos {
+                        x: end.x - 1,
+                        y: end.y,
+                    }
+                } else {
+                    let y = end.y - 1;
+                    let len = self.doc.borrow().get_row(y).map_or(0, Row::len);
+                    Pos {
+                        x: len.saturating_sub(1),
+                        y,
+                    }
+                };
+                if let Some(sel) = self.sel.as_mut() {
+                    sel.start = start;
+                }
+                self.jump_cursor_pos(Some(last));
+                Ok(Status::Ok)
+            }
+        }
+    }
+    pub fn move_cursor_rel(
+        &mut self,
+        noun: Noun,
+        rel: Rel,
+        count: Option<usize>,
+    ) -> Result<Status> {
+        trace!("move_cursor_rel({:?}, {:?}, {:?})", noun, rel, count);
+        let n = count.unwrap_or(1) as RelCoord;
         match (noun, rel) {
-            (Noun::Char, Rel::Prior) => self.move_cursor(-1, 0),
-            (Noun::Char, Rel::Next) => self.move_cursor(1, 0),
-            (Noun::Line, Rel::Prior) => self.move_cursor(0, -1),
-            (Noun::Line, Rel::Next) => self.move_cursor(0, 1),
-            (Noun::Word, Rel::Next) => {
-                self.jump_cursor_pos(self.doc.get_next_word_pos(self.cursor));
+            (Noun::Char, Rel::Prior | Rel::Next) => {
+                self.jump_cursor_pos(Some(self.grapheme_steps(rel, count.unwrap_or(1))));
                 Ok(Status::Ok)
             }
-            (Noun::Word, Rel::Prior) => {
-                if let Some(pos) = self.doc.get_prior_word_pos(self.cursor) {
-                    self.jump_cursor(Some(pos.x), Some(pos.y));
+            (Noun::Line, Rel::Prior) => self.move_cursor(0, -n),
+            (Noun::Line, Rel::Next) => self.move_cursor(0, n),
+            _ => {
+                let pos = self.motion_target(noun, rel, count)?;
+                if pos.is_some() && motion::is_jump(noun) {
+                    self.push_jump();
                 }
+                self.jump_cursor_pos(pos);
                 Ok(Status::Ok)
             }
-            _ => Err(not_impl!(
-                "DocView: Don't know how to handle relative motion for ({:?}, {:?}).",
-                noun,
-                rel
-            )),
+        }
+    }
+
+    pub fn search(&mut self, pattern: &str, direction: Direction) -> Result<Status> {
+        let committed = self.search.borrow_mut().commit(pattern, direction);
+        if let Err(error) = committed {
+            self.switch_mode(Mode::Normal);
+            return Ok(status!("{}", error.message()));
+        }
+        self.search_next(false)
+    }
+    /// Jumps to (or, with a pending operator, operates up to) the next match of the last search.
+    /// `reverse` flips the direction of the last search, as `N` does.
+    pub fn search_next(&mut self, reverse: bool) -> Result<Status> {
+        let (regex, direction) = match self.search.borrow().get_last() {
+            Some((regex, direction)) => (regex, direction),
+            None => return Ok(status!("No previous regular expression")),
+        };
+        let direction = if reverse {
+            direction.reversed()
+        } else {
+            direction
+        };
+        let found = self.doc.borrow().search(&regex, self.cursor, direction);
+        let (pos, wrapped) = match found {
+            Some(found) => found,
+            None => {
+                if matches!(self.mode, Mode::NormalWithOp(_)) {
+                    self.switch_mode(Mode::Normal);
+                }
+                return Ok(status!("Pattern not found: {}", regex.as_str()));
+            }
+        };
+        let status = match self.mode {
+            Mode::NormalWithOp(op) => {
+                if pos < self.cursor {
+                    self.do_op_to_range(op, pos..self.cursor)?
+                } else {
+                    self.do_op_to_range(op, self.cursor..pos)?
+                }
+            }
+            _ => {
+                self.push_jump();
+                self.jump_cursor_pos(Some(pos));
+                Status::Ok
+            }
+        };
+        if wrapped {
+            Ok(match direction {
+                Direction::Forward => status!("search hit BOTTOM, continuing at TOP"),
+                Direction::Backward => status!("search hit TOP, continuing at BOTTOM"),
+            })
+        } else {
+            Ok(status)
         }
     }
 
     pub fn last_valid_row(&self) -> Coord {
-        self.doc.line_count()
+        self.doc.borrow().line_count().saturating_sub(1)
+    }
+    /// Whether the cursor must rest on a character rather than just past the end of the row.
+    fn keeps_cursor_on_char(&self) -> bool {
+        matches!(self.mode, Mode::Normal | Mode::Visual(_))
     }
     pub fn clamped_pos(&self, mut pos: Pos) -> Pos {
         pos.y = pos.y.clamp(0, self.last_valid_row());
-        if let Some(row) = self.doc.get_row(pos.y) {
-            pos.x = pos.x.clamp(
+        if let Some(row) = self.doc.borrow().get_row(pos.y) {
+            pos.x = row.grapheme_start(pos.x.clamp(
                 0,
-                row.len() - usize::from(!row.is_empty() && self.mode == Mode::Normal),
-            );
+                row.len() - usize::from(!row.is_empty() && self.keeps_cursor_on_char()),
+            ));
         } else {
             pos.x = 0;
         };
@@ -147,26 +692,67 @@ impl DocView {
     fn clamp_cursor(&mut self) {
         log::trace!("clamp_cursor starts at {:?}", self.cursor);
         self.cursor.y = self.cursor.y.clamp(0, self.last_valid_row());
-        if let Some(row) = self.doc.get_row(self.cursor.y) {
-            self.cursor.x = self.cursor.x.clamp(
+        let doc = self.doc.borrow();
+        if let Some(row) = doc.get_row(self.cursor.y) {
+            self.cursor.x = row.grapheme_start(self.cursor.x.clamp(
                 0,
-                row.len() - usize::from(!row.is_empty() && self.mode == Mode::Normal),
-            );
-            self.render_cursor_x = row.cursor_to_render_col(self.cursor.x);
+                row.len() - usize::from(!row.is_empty() && self.keeps_cursor_on_char()),
+            ));
+            self.render_cursor_x = row.cursor_to_render_col(self.cursor.x, doc.indent().tabstop);
         } else {
             self.cursor.x = 0;
             self.render_cursor_x = 0;
         };
+        if let (Mode::Visual(_), Some(sel)) = (self.mode, self.sel.as_mut()) {
+            sel.end = self.cursor;
+        }
         log::trace!("clamp_cursor ends at {:?}", self.cursor);
     }
     fn apply_op_pos(&mut self, op_pos: (ChangeOp, Pos)) -> Result<Status> {
         let (op, pos) = op_pos;
-        let mut change_tracker = self.doc.new_change_tracker(self.cursor);
-        change_tracker.add_op(op, pos);
-        let cursor = Some(change_tracker.commit());
+        let rows = op.range.start..op.range.start + op.rows.len();
+        let before = self.cursor;
+        let cursor = {
+            let mut doc = self.doc.borrow_mut();
+            let mut change_tracker = doc.new_change_tracker(self.cursor);
+            change_tracker.add_op(op, pos);
+            Some(change_tracker.commit())
+        };
         self.jump_cursor_pos(cursor);
+        self.mark_change(before, pos, rows);
         Ok(Status::Ok)
     }
+    /// Sets the `.`, `[` and `]` marks for a change that took the cursor from `before` to
+    /// `after`, leaving `rows` where the changed rows were. All the changes made in insert mode
+    /// count as one, so there `[` only ever moves back.
+    fn mark_change(&mut self, before: Pos, after: Pos, rows: Range<Coord>) {
+        let end = match rows.end.checked_sub(1) {
+            Some(last) => after.max(Pos { x: 0, y: last }),
+            None => after,
+        };
+        let start = before
+            .min(after)
+            .max(Pos {
+                x: 0,
+                y: rows.start,
+            })
+            .min(end);
+        let mut doc = self.doc.borrow_mut();
+        let marks = doc.marks_mut();
+        let start = match (self.mode, marks.get(CHANGE_START)) {
+            (Mode::Insert, Some(insert_start)) => insert_start.min(start),
+            _ => start,
+        };
+        marks.set(LAST_CHANGE, after);
+        marks.set(CHANGE_START, start);
+        marks.set(CHANGE_END, end);
+    }
+    /// Sets the `[` and `]` marks to the first and last chars yanked.
+    fn mark_yank(&mut self, first: Pos, last: Pos) {
+        let mut doc = self.doc.borrow_mut();
+        doc.marks_mut().set(CHANGE_START, first);
+        doc.marks_mut().set(CHANGE_END, last);
+    }
     pub fn jump_cursor_pos(&mut self, pos: Option<Pos>) {
         if let Some(pos) = pos {
             self.jump_cursor(Some(pos.x), Some(pos.y));
@@ -181,39 +767,339 @@ impl DocView {
         }
         self.clamp_cursor();
     }
-    pub fn open(&mut self, filename: String) -> Result<Status> {
-        self.doc = Doc::open(filename.clone())?;
+    /// Edits `filename`, switching to its buffer if it's already open. Reloading the file shown
+    /// in this view throws away unsaved changes, so that needs `force`.
+    pub fn open(&mut self, filename: String, force: bool) -> Result<Status> {
+        let existing = self.buffers.borrow().find_file(&filename);
+        if let Some(id) = existing {
+            if id != self.buffer_id {
+                return self.show_buffer(id);
+            }
+            if self.doc.borrow().is_dirty() && !force {
+                return Ok(status!("No write since last change (add ! to override)"));
+            }
+        }
+        let is_new = !Path::new(&filename).exists();
+        let (mut doc, restored) = match self.load(&filename) {
+            Ok(loaded) => loaded,
+            Err(error) => return Ok(status!("\"{}\" {}", filename, error.message())),
+        };
+        // Reloads, and files opened into the untouched buffer we start with, replace the doc in
+        // place rather than adding a buffer.
+        let untouched = {
+            let doc = self.doc.borrow();
+            doc.get_filename().is_none() && !doc.is_dirty()
+        };
+        if existing.is_some() || untouched {
+            // A reloaded doc keeps its marks.
+            *doc.marks_mut() = self.doc.borrow().marks().clone();
+            *self.doc.borrow_mut() = doc;
+            self.cursor = Pos::zero();
+            self.scroll_offset = Pos::zero();
+            self.clamp_cursor();
+        } else {
+            let id = self.buffers.borrow_mut().add(doc);
+            self.show_buffer(id)?;
+        }
         Ok(Status::Message {
-            message: format!("Opened '{}'.", filename),
+            message: if restored {
+                format!("Opened '{}' with its undo history.", filename)
+            } else if is_new {
+                format!("\"{}\" [New]", filename)
+            } else {
+                format!("Opened '{}'.", filename)
+            },
             expiry: Instant::now() + Duration::from_secs(2),
         })
     }
-    pub fn save_file(&mut self) -> Result<Status> {
-        Err(not_impl!("come back"))
-        /*
-        // TODO: write + rename.
-        let save_buffer = self.get_save_buffer();
-        if let Some(filename) = self.doc.get_filename() {
-            let mut f = OpenOptions::new().write(true).create(true).open(filename)?;
-            f.set_len(0)?;
-            f.seek(SeekFrom::Start(0))?;
-            let bytes = save_buffer.to_bytes();
-            f.write_all(bytes)?;
-            f.flush()?;
-            Ok(Status::Message {
-                message: format!("{} saved [{}b]!", filename, bytes.len()),
-                expiry: Instant::now() + Duration::from_secs(2),
+    /// Adds `filename` to the buffer list without showing it, as `:badd` does.
+    pub fn add_buffer(&mut self, filename: String) -> Result<Status> {
+        if self.buffers.borrow().find_file(&filename).is_none() {
+            let (doc, _) = match self.load(&filename) {
+                Ok(loaded) => loaded,
+                Err(error) => return Ok(status!("\"{}\" {}", filename, error.message())),
+            };
+            self.buffers.borrow_mut().add(doc);
+        }
+        Ok(Status::Ok)
+    }
+    /// Shows a new, empty buffer.
+    fn new_buffer(&mut self) -> Result<Status> {
+        let id = self.buffers.borrow_mut().add(self.empty_doc());
+        self.show_buffer(id)
+    }
+    /// Sets one of vim's options, by its name or abbreviation. The indent options belong to the
+    /// buffer, and `list` to the window.
+    fn set_option(&mut self, name: &str, value: &Variant) -> Result<Status> {
+        let mut indent = self.doc.borrow().indent();
+        let number = match value {
+            Variant::String(value) => value.parse::<Coord>().ok(),
+            _ => None,
+        };
+        match (name, number, value) {
+            ("tabstop" | "ts", Some(n), _) if n > 0 => indent.tabstop = n,
+            ("shiftwidth" | "sw", Some(n), _) => indent.shiftwidth = n,
+            ("expandtab" | "et", _, &Variant::Bool(on)) => indent.expandtab = on,
+            ("list", _, &Variant::Bool(on)) => self.list = on,
+            ("iskeyword" | "isk", _, Variant::String(chars)) => {
+                self.doc.borrow_mut().set_word_chars(WordChars::new(chars));
+            }
+            (
+                "tabstop" | "ts" | "shiftwidth" | "sw" | "expandtab" | "et" | "list" | "iskeyword"
+                | "isk",
+                _,
+                _,
+            ) => return Ok(status!("Invalid argument: {}", name)),
+            _ => return Ok(status!("Unknown option: {}", name)),
+        }
+        self.doc.borrow_mut().set_indent(indent);
+        self.clamp_cursor();
+        Ok(Status::Ok)
+    }
+    /// A new, empty doc indented as settings.toml says.
+    fn empty_doc(&self) -> Doc {
+        let mut doc = Doc::empty();
+        doc.set_indent(self.settings.indent);
+        doc
+    }
+    /// Reads `filename`, returning the doc and whether its undo history was restored. An undo
+    /// file that can't be read leaves the doc without history rather than failing the load.
+    fn load(&self, filename: &str) -> Result<(Doc, bool)> {
+        let mut doc = Doc::open(filename.to_string())?;
+        let restored = self.settings.undo.file
+            && doc.use_undo_file().unwrap_or_else(|error| {
+                log::warn!("ignoring the undo file for '{}': {}", filename, error);
+                false
+            });
+        doc.set_grammar(self.grammar_for(filename));
+        doc.set_indent(self.settings.indent);
+        doc.set_word_chars(self.word_chars_for(filename));
+        Ok((doc, restored))
+    }
+    /// The grammar to highlight `filename` with, going by its file type.
+    fn grammar_for(&self, filename: &str) -> Option<Rc<Grammar>> {
+        filetype::detect(filename)
+            .and_then(|file_type| self.settings.syntaxes.for_file_type(file_type))
+    }
+    /// The chars that words in `filename` are made of, going by its file type.
+    fn word_chars_for(&self, filename: &str) -> WordChars {
+        let extra = filetype::detect(filename)
+            .and_then(|file_type| self.settings.word_chars.get(file_type));
+        WordChars::new(extra.map_or("", String::as_str))
+    }
+    /// Shows buffer `id` in this view, leaving the cursor and scroll position of the buffer being
+    /// hidden with it for when it's shown again.
+    fn show_buffer(&mut self, id: BufferId) -> Result<Status> {
+        let mut buffers = self.buffers.borrow_mut();
+        if let Some(buffer) = buffers.get_mut(self.buffer_id) {
+            buffer.cursor = self.cursor;
+            buffer.scroll_offset = self.scroll_offset;
+        }
+        let buffer = match buffers.get(id) {
+            Some(buffer) => buffer,
+            None => return Ok(status!("Buffer {} does not exist", id)),
+        };
+        self.doc = buffer.doc.clone();
+        self.cursor = buffer.cursor;
+        self.scroll_offset = buffer.scroll_offset;
+        let status = status!(
+            "\"{}\"{} {} lines",
+            buffer.name(),
+            if buffer.is_dirty() { " [Modified]" } else { "" },
+            self.doc.borrow().line_count()
+        );
+        drop(buffers);
+        self.buffer_id = id;
+        self.clamp_cursor();
+        Ok(status)
+    }
+    /// Shows the buffer `count` places along the buffer list, as `:bnext` and `:bprevious` do.
+    fn cycle_buffer(&mut self, count: usize, forward: bool) -> Result<Status> {
+        let id = self.buffers.borrow().cycle(self.buffer_id, count, forward);
+        match id {
+            Some(id) => self.show_buffer(id),
+            None => Err(error!(
+                "buffer {} is missing from the buffer list",
+                self.buffer_id
+            )),
+        }
+    }
+    /// Shows the one buffer whose name contains `name`.
+    fn show_buffer_named(&mut self, name: &str) -> Result<Status> {
+        let ids: Vec<BufferId> = self
+            .buffers
+            .borrow()
+            .iter()
+            .filter(|buffer| buffer.name().contains(name))
+            .map(|buffer| buffer.id)
+            .collect();
+        match ids.as_slice() {
+            [id] => self.show_buffer(*id),
+            [] => Ok(status!("No matching buffer for {}", name)),
+            _ => Ok(status!("More than one match for {}", name)),
+        }
+    }
+    /// Drops buffer `id` (or this view's buffer) from the buffer list. A buffer with unsaved
+    /// changes is only dropped with `force`.
+    pub fn delete_buffer(&mut self, id: Option<BufferId>, force: bool) -> Result<Status> {
+        let id = id.unwrap_or(self.buffer_id);
+        let is_dirty = match self.buffers.borrow().get(id) {
+            Some(buffer) => buffer.is_dirty(),
+            None => return Ok(status!("Buffer {} does not exist", id)),
+        };
+        if is_dirty && !force {
+            return Ok(status!(
+                "No write since last change for buffer {} (add ! to override)",
+                id
+            ));
+        }
+        if id == self.buffer_id {
+            // Show the next buffer in its place, or a new empty one if it was the last.
+            let next = self
+                .buffers
+                .borrow()
+                .cycle(id, 1, true)
+                .filter(|&next| next != id);
+            let next = match next {
+                Some(next) => next,
+                None => self.buffers.borrow_mut().add(self.empty_doc()),
+            };
+            self.show_buffer(next)?;
+        }
+        self.buffers.borrow_mut().remove(id);
+        Ok(Status::Ok)
+    }
+    /// Describes the buffer list on one line, marking this view's buffer with `%` and buffers
+    /// with unsaved changes with `+`, as `:ls` does.
+    fn list_buffers(&self) -> Status {
+        let buffers = self.buffers.borrow();
+        let entries: Vec<String> = buffers
+            .iter()
+            .map(|buffer| {
+                format!(
+                    "{}{}{} \"{}\"",
+                    buffer.id,
+                    if buffer.id == self.buffer_id {
+                        " %"
+                    } else {
+                        ""
+                    },
+                    if buffer.is_dirty() { " +" } else { "" },
+                    buffer.name()
+                )
             })
-        } else {
-            Err(Error::new("no filename specified!"))
+            .collect();
+        status!("{}", entries.join(" | "))
+    }
+    /// Saves the doc, reporting a failure to save rather than giving up on the buffer. Writing to
+    /// another file only names the buffer after it when the buffer has no name yet.
+    pub fn save_file(&mut self, filename: Option<String>) -> Result<Status> {
+        let has_name = self.doc.borrow().get_filename().is_some();
+        let saved = match filename {
+            Some(filename) if has_name => self
+                .doc
+                .borrow()
+                .write_copy(&filename)
+                .map(|bytes| Saved {
+                    bytes,
+                    warnings: Vec::new(),
+                })
+                .map(|saved| (filename, saved)),
+            Some(filename) => {
+                let saved = self.doc.borrow_mut().save_as(filename.clone());
+                if saved.is_ok() {
+                    let grammar = self.grammar_for(&filename);
+                    self.doc.borrow_mut().set_grammar(grammar);
+                }
+                saved.map(|saved| (filename, saved))
+            }
+            None if has_name => {
+                let mut doc = self.doc.borrow_mut();
+                let filename = doc.get_filename().unwrap_or_default().to_string();
+                doc.save().map(|saved| (filename, saved))
+            }
+            None => return Err(error!("E32: No file name")),
+        };
+        let (filename, saved) = match saved {
+            Ok(saved) => saved,
+            Err(error) => return Ok(status!("{}", error.message())),
+        };
+        if !saved.warnings.is_empty() {
+            let warnings: Vec<&str> = saved.warnings.iter().map(Error::message).collect();
+            return Ok(status!(
+                "{} saved [{}b], but {}",
+                filename,
+                saved.bytes,
+                warnings.join("; ")
+            ));
+        }
+        Ok(Status::Message {
+            message: format!("{} saved [{}b]!", filename, saved.bytes),
+            expiry: Instant::now() + Duration::from_secs(2),
+        })
+    }
+    pub fn travel(&mut self, travel: Travel, later: bool) -> Result<Status> {
+        let pos = self.doc.borrow_mut().travel(travel, later);
+        self.jump_cursor_pos(pos);
+        let (seq, last_seq) = self.doc.borrow().undo_seq();
+        match pos {
+            Some(_) => Ok(status!("At change {} of {}", seq, last_seq)),
+            None if later => Ok(status!("Already at newest change")),
+            None => Ok(status!("Already at oldest change")),
         }
-        */
     }
     pub fn split_newline(&mut self) -> Result<Status> {
-        self.apply_op_pos(self.doc.split_newline(self.cursor))
+        let op_pos = self.doc.borrow().split_newline(self.cursor);
+        self.apply_op_pos(op_pos)
+    }
+    /// Records `dk` as part of the change under way, or as the start of a new change if `name`
+    /// and `args` begin one in `mode_before`. The change is complete once we're back in normal
+    /// mode.
+    fn record_change(&mut self, mode_before: Mode, dk: DK, name: &str, args: &[Variant]) {
+        if self.change.is_empty() && !(mode_before == Mode::Normal && starts_change(name, args)) {
+            return;
+        }
+        if matches!(mode_before, Mode::NormalWithOp(_)) && name == "switch-mode" {
+            // The operator was cancelled.
+            self.change.clear();
+            return;
+        }
+        self.change.push(dk);
+        if self.mode == Mode::Normal {
+            self.last_change = std::mem::take(&mut self.change);
+        }
+    }
+    /// Re-dispatches the last change. A count replaces the one the change was made with, or
+    /// for an insert, types its text that many times.
+    fn repeat_change(&mut self, count: Option<usize>) -> Result<Status> {
+        if self.last_change.is_empty() {
+            return Ok(status!("No change to repeat"));
+        }
+        let mut dks = self.last_change.clone();
+        if let Some(count) = count {
+            dks = dks.into_iter().map(DK::without_count).collect();
+            let starts_with = match &dks[0] {
+                DK::Dispatch(_, Message::Command { name, .. }) => name.as_str(),
+                _ => "",
+            };
+            match starts_with {
+                // Each repeat opens a line of its own.
+                "open-line" => dks = (0..count).flat_map(|_| dks.iter().cloned()).collect(),
+                "switch-mode" if dks.len() > 1 => {
+                    let leave = dks.pop().unwrap();
+                    let text = dks.split_off(1);
+                    for _ in 0..count {
+                        dks.extend(text.iter().cloned());
+                    }
+                    dks.push(leave);
+                }
+                _ => dks[0] = dks[0].clone().with_count(count as i64),
+            }
+        }
+        Ok(Status::Dispatch(DK::Sequence(dks)))
     }
     pub fn insert_newline_above(&mut self) -> Result<Status> {
-        let op = self.doc.insert_newline(self.cursor.y);
+        let op = self.doc.borrow().insert_newline(self.cursor.y);
         let pos = Pos {
             x: 0,
             y: self.cursor.y,
@@ -221,7 +1107,7 @@ impl DocView {
         self.apply_op_pos((op, pos))
     }
     pub fn insert_newline_below(&mut self) -> Result<Status> {
-        let op = self.doc.insert_newline(self.cursor.y + 1);
+        let op = self.doc.borrow().insert_newline(self.cursor.y + 1);
         let pos = Pos {
             x: 0,
             y: self.cursor.y + 1,
@@ -229,31 +1115,242 @@ impl DocView {
         self.apply_op_pos((op, pos))
     }
     pub fn insert_char(&mut self, ch: char) -> Result<Status> {
-        self.apply_op_pos(self.doc.insert_char(self.cursor, ch))
+        let op_pos = self.doc.borrow().insert_char(self.cursor, ch);
+        self.apply_op_pos(op_pos)
     }
-    pub fn delete_sel(&mut self) -> Result<Status> {
-        if let Some(sel) = self.sel {
-            let ret = self.delete_range(sel.start..=sel.end);
-            self.switch_mode(Mode::Normal);
-            ret
-        } else {
-            Err(error!("invalid sel?!"))
+    /// Returns the selection's corners in document order.
+    fn sel_bounds(&self) -> Option<(Pos, Pos)> {
+        self.sel.map(|sel| {
+            if sel.start > sel.end {
+                (sel.end, sel.start)
+            } else {
+                (sel.start, sel.end)
+            }
+        })
+    }
+    /// Returns the rows and screen columns covered by a block selection. The block takes in
+    /// the whole of the graphemes at its corners, however wide they are.
+    fn sel_block(&self) -> Option<(Range<Coord>, Range<Coord>)> {
+        let sel = self.sel?;
+        let doc = self.doc.borrow();
+        let tabstop = doc.indent().tabstop;
+        let cols = |pos: Pos| match doc.get_row(pos.y) {
+            Some(row) => {
+                let start = row.cursor_to_render_col(pos.x, tabstop);
+                let end = row.cursor_to_render_col(row.next_grapheme(pos.x), tabstop);
+                start..end.max(start + 1)
+            }
+            None => 0..1,
+        };
+        let (start, end) = (cols(sel.start), cols(sel.end));
+        let top = sel.start.y.min(sel.end.y);
+        let bottom = sel.start.y.max(sel.end.y);
+        Some((
+            top..bottom + 1,
+            start.start.min(end.start)..start.end.max(end.end),
+        ))
+    }
+    /// The command that selects as much text from the cursor as the selection covers: the same
+    /// number of lines and, for a selection within a line or a block, the same number of
+    /// columns.
+    fn reselect_dk(&self) -> Option<DK> {
+        let visual = match self.mode {
+            Mode::Visual(visual) => visual,
+            _ => return None,
+        };
+        let (start, end) = self.sel_bounds()?;
+        let extent = match visual {
+            VisualMode::Char if start.y == end.y => Pos {
+                x: end.x - start.x,
+                y: 0,
+            },
+            VisualMode::Char => Pos {
+                x: end.x,
+                y: end.y - start.y,
+            },
+            VisualMode::Line => Pos {
+                x: 0,
+                y: end.y - start.y,
+            },
+            VisualMode::Block => {
+                let (rows, cols) = self.sel_block()?;
+                Pos {
+                    x: cols.len() - 1,
+                    y: rows.len() - 1,
+                }
+            }
+        };
+        Some(
+            command("select-extent")
+                .arg(visual.mode_name())
+                .arg(Variant::Pos(extent))
+                .at_view(self.key),
+        )
+    }
+    /// Selects from the cursor as far as an extent measured by `reselect_dk` reaches. A
+    /// selection over several lines ends at the same column as the one it was measured from.
+    fn select_extent(&mut self, visual: VisualMode, extent: Pos) {
+        let start = self.cursor;
+        let end = match visual {
+            VisualMode::Char if extent.y == 0 => Pos {
+                x: start.x + extent.x,
+                y: start.y,
+            },
+            VisualMode::Char => Pos {
+                x: extent.x,
+                y: start.y + extent.y,
+            },
+            VisualMode::Line => Pos {
+                x: start.x,
+                y: start.y + extent.y,
+            },
+            VisualMode::Block => {
+                let y = start.y + extent.y;
+                let col = self.render_cursor_x + extent.x;
+                Pos {
+                    x: self.block_chars(y, col..col + 1).start,
+                    y,
+                }
+            }
+        };
+        self.switch_mode(Mode::Visual(visual));
+        self.jump_cursor(Some(end.x), Some(end.y));
+    }
+    pub fn do_op_to_sel(&mut self, op: Op) -> Result<Status> {
+        let (start, end) = match self.sel_bounds() {
+            Some(bounds) => bounds,
+            None => return Err(error!("invalid sel?!")),
+        };
+        match self.mode {
+            Mode::Visual(VisualMode::Char) => {
+                let end_x = self
+                    .doc
+                    .borrow()
+                    .get_row(end.y)
+                    .map_or(end.x + 1, |row| row.selection_end(end.x));
+                self.do_op_to_range(op, start..Pos { x: end_x, y: end.y })
+            }
+            Mode::Visual(VisualMode::Line) => self.do_op_to_lines(op, start.y..end.y + 1),
+            Mode::Visual(VisualMode::Block) => {
+                let (rows, cols) = self.sel_block().unwrap();
+                self.do_op_to_block(op, rows, cols)
+            }
+            _ => Err(error!("not in visual mode")),
         }
     }
+    pub fn do_op_to_block(
+        &mut self,
+        op: Op,
+        rows: Range<Coord>,
+        cols: Range<Coord>,
+    ) -> Result<Status> {
+        let register =
+            Register::blockwise(self.doc.borrow().get_block_text(rows.clone(), cols.clone()));
+        let name = self.register.take();
+        match op {
+            Op::Delete => {
+                self.registers.borrow_mut().delete(name, register);
+                self.switch_mode(Mode::Normal);
+                let op_pos = self.doc.borrow().delete_block(rows, cols);
+                self.apply_op_pos(op_pos)
+            }
+            Op::Change => {
+                self.registers.borrow_mut().delete(name, register);
+                self.switch_mode(Mode::Normal);
+                self.doc.borrow_mut().begin_group();
+                let op_pos = self.doc.borrow().delete_block(rows.clone(), cols.clone());
+                self.apply_op_pos(op_pos)?;
+                self.begin_block_insert(rows, cols.start);
+                self.doc.borrow_mut().end_group();
+                Ok(Status::Ok)
+            }
+            Op::Yank => {
+                self.registers.borrow_mut().yank(name, register);
+                self.switch_mode(Mode::Normal);
+                let start = self.block_chars(rows.start, cols.clone()).start;
+                let end = self.block_chars(rows.end - 1, cols).end;
+                self.mark_yank(
+                    Pos {
+                        x: start,
+                        y: rows.start,
+                    },
+                    Pos {
+                        x: end.saturating_sub(1),
+                        y: rows.end - 1,
+                    },
+                );
+                self.jump_cursor(Some(start), Some(rows.start));
+                Ok(Status::Ok)
+            }
+            Op::ShiftLeft | Op::ShiftRight => self.shift_lines(op, rows),
+        }
+    }
+    /// The chars of row `y` shown in screen columns `cols`.
+    fn block_chars(&self, y: Coord, cols: Range<Coord>) -> Range<Coord> {
+        let doc = self.doc.borrow();
+        doc.get_row(y).map_or(0..0, |row| {
+            row.render_cols_to_range(cols, doc.indent().tabstop)
+        })
+    }
+    /// Enters insert mode at screen column `col` of the top row, arranging for the typed text to
+    /// be copied into the remaining `rows` once insert mode ends.
+    fn begin_block_insert(&mut self, rows: Range<Coord>, col: Coord) {
+        self.switch_mode(Mode::Insert);
+        let x = self.block_chars(rows.start, col..col + 1).start;
+        self.jump_cursor(Some(x), Some(rows.start));
+        let row_len = self
+            .doc
+            .borrow()
+            .get_row(rows.start)
+            .map_or(0, |row| row.len());
+        self.block_insert = Some(BlockInsert {
+            rows: rows.start + 1..rows.end,
+            x: self.cursor.x,
+            col,
+            y: rows.start,
+            row_len,
+        });
+    }
+    fn finish_block_insert(&mut self) {
+        let block_insert = match self.block_insert.take() {
+            Some(block_insert) => block_insert,
+            None => return,
+        };
+        let mut doc = self.doc.borrow_mut();
+        let row = match doc.get_row(block_insert.y) {
+            Some(row) => row,
+            None => return,
+        };
+        // Only text typed on the top row, without breaking it, gets copied.
+        if self.cursor.y != block_insert.y || row.len() <= block_insert.row_len {
+            return;
+        }
+        let inserted = row.len() - block_insert.row_len;
+        let text: String = row
+            .get_slice(block_insert.x..block_insert.x + inserted)
+            .iter()
+            .collect();
+        let op = doc.insert_block_text(block_insert.rows, block_insert.col, &text);
+        let mut change_tracker = doc.new_change_tracker(self.cursor);
+        change_tracker.add_op(op, self.cursor);
+        self.cursor = change_tracker.commit();
+    }
     pub fn delete_rel(&mut self, noun: Noun, rel: Rel) -> Result<Status> {
-        let (start, end) = self.doc.find_range(self.cursor, noun, rel);
+        let (start, end) = self.doc.borrow().find_range(self.cursor, noun, rel);
         self.delete_range(start..end)
     }
     fn delete_range(&mut self, range: impl RangeBounds<Pos>) -> Result<Status> {
-        match self.doc.delete_range(range) {
+        let op_pos = self.doc.borrow().delete_range(range);
+        match op_pos {
             Some(op_pos) => self.apply_op_pos(op_pos),
             None => Ok(Status::Ok),
         }
     }
     pub fn join_line(&mut self) -> Result<Status> {
+        let x = self.doc.borrow().get_row(self.cursor.y).unwrap().len();
         self.delete_range(
             Pos {
-                x: self.doc.get_row(self.cursor.y).unwrap().len(),
+                x,
                 y: self.cursor.y,
             }..Pos {
                 x: 0,
@@ -267,132 +1364,236 @@ impl DocView {
         W: Write,
     {
         let mut buf = Buf::default();
-        for row in self.doc.iter_lines() {
+        for row in self.doc.borrow().iter_lines() {
             buf.append(row);
             buf.append("\n");
         }
         buf
     }
     */
+    fn resolve_line_spec(&self, line_spec: &str) -> Result<Coord> {
+        Ok(LineSpec::from_str(line_spec)?.resolve(self.cursor.y, self.doc.borrow().line_count()))
+    }
     fn switch_mode(&mut self, mode: Mode) {
+        // Everything typed in insert mode undoes as one change.
+        if self.mode != Mode::Insert && mode == Mode::Insert {
+            let mut doc = self.doc.borrow_mut();
+            doc.begin_group();
+            doc.marks_mut().set(CHANGE_START, self.cursor);
+            doc.marks_mut().set(CHANGE_END, self.cursor);
+        }
+        if self.mode == Mode::Insert && mode != Mode::Insert {
+            self.finish_block_insert();
+            let mut doc = self.doc.borrow_mut();
+            doc.end_group();
+            doc.marks_mut().set(LAST_INSERT, self.cursor);
+        }
+        let was_visual = matches!(self.mode, Mode::Visual(_));
         self.mode = mode;
-        if matches!(self.mode, Mode::Visual(VisualMode::Char)) {
-            self.sel = Some(Sel::from_pos(self.cursor));
-        } else {
-            self.sel = None;
+        match self.mode {
+            // Switching between visual kinds keeps the selection.
+            Mode::Visual(_) if was_visual && self.sel.is_some() => {}
+            Mode::Visual(_) => self.sel = Some(Sel::from_pos(self.cursor)),
+            _ => self.sel = None,
         }
         self.clamp_cursor();
     }
     fn get_line_fmt_spans(
         &self,
-        mut screen_pos: Pos,
-        mut render_start: Pos,
+        screen_pos: Pos,
+        render_start: Pos,
         width: usize,
+        highlight: Option<&Regex>,
     ) -> Vec<Span> {
-        let mut spans = Vec::new();
-        if let Some(sel) = self.sel {
-            let (mut start, mut end) = (sel.start, sel.end);
-            if start > end {
-                std::mem::swap(&mut start, &mut end);
-                end.x += 1;
-            }
-            if start.y == render_start.y {
-                if end.y == render_start.y {
-                    if start.x > render_start.x {
-                        let chars = self.doc.render_line_slice(
-                            render_start,
-                            std::cmp::min(start.x - render_start.x, width - screen_pos.x),
-                        );
-                        spans.push(Span {
-                            screen_pos,
-                            chars,
-                            format: Format::none(),
-                        });
-                        screen_pos.x += chars.len();
-                        render_start.x += chars.len();
-                    }
-                    if screen_pos.x < width && end.x > render_start.x {
-                        // Span on a single line.
-                        let chars = self.doc.render_line_slice(
-                            render_start,
-                            std::cmp::min(end.x - render_start.x, width - screen_pos.x),
-                        );
-                        spans.push(Span {
-                            screen_pos,
-                            chars,
-                            format: Format::selected(),
-                        });
-                        screen_pos.x += chars.len();
-                        render_start.x += chars.len();
+        let doc = self.doc.borrow();
+        let row = match doc.get_row(render_start.y) {
+            Some(row) => row,
+            None => return Vec::new(),
+        };
+        let tabstop = doc.indent().tabstop;
+        let cols = render_start.x..render_start.x + width;
+        let clusters: Vec<RenderCluster> = row.render_clusters(cols.clone(), tabstop);
+        let visible = match (clusters.first(), clusters.last()) {
+            (Some(first), Some(last)) => first.x..last.x + last.chars.len(),
+            _ => 0..0,
+        };
+        let mut formats = vec![Format::none(); visible.len()];
+        let mut paint = |range: Range<Coord>, format: Format| {
+            for x in range.start.max(visible.start)..range.end.min(visible.end) {
+                formats[x - visible.start] = format;
+            }
+        };
+        let colors = &self.settings.colors;
+        for token in doc.syntax_tokens(render_start.y) {
+            if let Some(group) = scope_group(&token.scope) {
+                paint(token.range.clone(), colors.format(group));
+            }
+        }
+        if let Some(regex) = highlight {
+            for range in doc.find_in_line(render_start.y, regex) {
+                paint(range, colors.format(Group::Search));
+            }
+        }
+        if let Some((start, end)) = self.sel_bounds() {
+            let y = render_start.y;
+            if (start.y..=end.y).contains(&y) {
+                let columns = match self.mode {
+                    Mode::Visual(VisualMode::Line) => 0..Coord::MAX,
+                    Mode::Visual(VisualMode::Block) => {
+                        row.render_cols_to_range(self.sel_block().unwrap().1, tabstop)
                     }
-                    if screen_pos.x < width {
-                        // Span on a single line.
-                        let chars = self
-                            .doc
-                            .render_line_slice(render_start, width - screen_pos.x);
-                        spans.push(Span {
-                            screen_pos,
-                            chars,
-                            format: Format::none(),
-                        });
-                        screen_pos.x += chars.len();
-                        render_start.x += chars.len();
+                    _ => {
+                        let from = if y == start.y { start.x } else { 0 };
+                        let to = if y == end.y {
+                            row.selection_end(end.x)
+                        } else {
+                            Coord::MAX
+                        };
+                        from..to
                     }
-                }
-                return spans;
+                };
+                paint(columns, colors.format(Group::Visual));
+            }
+        }
+        // Each grapheme takes the format of its first char, and runs of graphemes formatted alike
+        // make spans.
+        let mut spans: Vec<Span> = Vec::new();
+        let mut push = |col: Coord, chars: Vec<char>, format: Format| match spans.last_mut() {
+            Some(span) if span.format == format => span.chars.extend(chars),
+            _ => spans.push(Span {
+                screen_pos: Pos {
+                    x: screen_pos.x + col - cols.start,
+                    y: screen_pos.y,
+                },
+                chars,
+                format,
+            }),
+        };
+        let list = &self.settings.list;
+        let trailing = row.len()
+            - row
+                .as_slice()
+                .iter()
+                .rev()
+                .take_while(|&&ch| ch == ' ')
+                .count();
+        for cluster in clusters {
+            let mut format = formats[cluster.x - visible.start];
+            let col = cluster.col.max(cols.start);
+            let end = (cluster.col + cluster.width).min(cols.end);
+            let is_tab = cluster.chars == ['\t'];
+            let is_trail = cluster.x >= trailing;
+            if self.list && (is_tab || is_trail) && format == Format::none() {
+                format = colors.format(Group::Whitespace);
             }
+            // A wide char cut off by either edge of the window shows as blanks.
+            let chars = if end - col < cluster.width {
+                BLANKS[..end - col].to_vec()
+            } else if self.list && is_tab {
+                list_tab(&list.tab, cluster.width)
+            } else if self.list && is_trail && !list.trail.is_empty() {
+                list.trail.chars().take(1).collect()
+            } else {
+                cluster.display_chars()
+            };
+            push(col, chars, format);
+        }
+        let eol = row.render_width(tabstop);
+        if self.list && !list.eol.is_empty() && cols.contains(&eol) {
+            push(
+                eol,
+                list.eol.chars().take(1).collect(),
+                colors.format(Group::NonText),
+            );
         }
-        vec![Span {
-            screen_pos,
-            chars: self.doc.render_line_slice(render_start, width),
-            format: Format::none(),
-        }]
+        spans
     }
 }
-struct Span<'a> {
+/// A tab `width` cells wide as list mode draws it with `tab`, the chars from settings.toml.
+fn list_tab(tab: &str, width: Coord) -> Vec<char> {
+    let tab: Vec<char> = tab.chars().collect();
+    let mut chars = vec![tab.get(1).copied().unwrap_or(' '); width];
+    chars[0] = tab.first().copied().unwrap_or(' ');
+    if let Some(&last) = tab.get(2) {
+        chars[width - 1] = last;
+    }
+    chars
+}
+
+struct Span {
     screen_pos: Pos,
-    chars: &'a [char],
+    chars: Vec<char>,
     format: Format,
 }
 
 impl View for DocView {
     fn get_doc_text(&self, _view_map: &ViewMap) -> Option<String> {
-        Some(self.doc.to_string())
+        Some(self.doc.borrow().to_string())
     }
     fn install_plugins(&mut self, plugin: PluginRef) {
         self._plugin = plugin;
     }
     fn layout(&mut self, _view_map: &ViewMap, size: Size) -> Vec<(ViewKey, Rect)> {
         log::trace!("docview size is {:?}", size);
-        self.scroll(size);
+        // Another window may have deleted this view's buffer, or changed the doc under the
+        // cursor.
+        let replacement = self.buffers.borrow().replacement_for(self.buffer_id);
+        if let Some(replacement) = replacement {
+            let _ = self.show_buffer(replacement);
+        }
+        self.clamp_cursor();
+        self.window_size = size;
+        let text_size = self.text_size(size);
+        self.text_height = text_size.height;
+        self.scroll(text_size);
+        self.doc
+            .borrow_mut()
+            .highlight_through(self.scroll_offset.y + text_size.height);
         vec![]
     }
-    fn display(&self, _view_map: &ViewMap, bmp: &mut BitmapView) {
+    fn display(&self, view_map: &ViewMap, bmp: &mut BitmapView) {
+        let highlight = self.search.borrow().get_highlight();
         let mut y = 0;
-        let size = bmp.get_size();
-        let offset_line_count = if self.scroll_offset.y >= self.doc.line_count() {
-            self.doc.line_count()
+        let size = self.text_size(bmp.get_size());
+        let gutter_width = self.gutter_width();
+        if size.height < bmp.get_size().height {
+            self.display_status_line(view_map, bmp, size.height);
+        }
+        let offset_line_count = if self.scroll_offset.y >= self.doc.borrow().line_count() {
+            self.doc.borrow().line_count()
         } else {
-            self.doc.line_count() - self.scroll_offset.y
+            self.doc.borrow().line_count() - self.scroll_offset.y
         };
         loop {
             if y >= size.height || y >= offset_line_count {
                 break;
             }
             let spans = self.get_line_fmt_spans(
-                Pos { x: 0, y },
+                Pos { x: gutter_width, y },
                 Pos {
                     x: self.scroll_offset.x,
                     y: self.scroll_offset.y + y,
                 },
                 size.width,
+                highlight.as_ref(),
             );
 
             for span in spans {
-                bmp.append_chars_at(span.screen_pos, span.chars.iter().copied(), span.format);
+                bmp.append_chars_at(span.screen_pos, span.chars.into_iter(), span.format);
             }
             y += 1;
         }
+        {
+            let doc = self.doc.borrow();
+            self.gutter.display(
+                bmp,
+                doc.signs(),
+                self.scroll_offset.y..self.scroll_offset.y + y,
+                self.cursor.y,
+                doc.line_count(),
+                &self.settings.colors,
+            );
+        }
         loop {
             if y >= size.height {
                 break;
@@ -408,19 +1609,44 @@ impl View for DocView {
     }
     fn get_cursor_pos(&self) -> Option<Pos> {
         Some(Pos {
-            x: self.render_cursor_x - self.scroll_offset.x,
+            x: self.gutter_width() + self.render_cursor_x - self.scroll_offset.x,
             y: self.cursor.y - self.scroll_offset.y,
         })
     }
+    fn clone_view(&self, view_key: ViewKey) -> Option<ViewRef> {
+        let mut docview = DocView::new(
+            view_key,
+            self._plugin.clone(),
+            self.registers.clone(),
+            self.buffers.clone(),
+            self.search.clone(),
+            self.settings.clone(),
+            self.buffer_id,
+        );
+        docview.cursor = self.cursor;
+        docview.scroll_offset = self.scroll_offset;
+        docview.gutter = self.gutter.clone();
+        docview.list = self.list;
+        docview.jumps = self.jumps.clone();
+        docview.clamp_cursor();
+        Some(viewref(docview))
+    }
 }
 impl DispatchTarget for DocView {
     fn get_key_bindings(&self) -> Bindings {
         let vk = self.get_view_key();
         let mut builder = BindingsBuilder::new(vk);
+        if self.awaiting_find.is_some() {
+            // The next key is the char to find, whatever it's bound to.
+            return builder.get_bindings();
+        }
+        if matches!(
+            self.mode,
+            Mode::Normal | Mode::Visual { .. } | Mode::NormalWithOp(_)
+        ) {
+            builder.accept_counts();
+        }
         if matches!(self.mode, Mode::Normal | Mode::Visual { .. }) {
-            builder.insert("c", command("motion").arg("change"));
-            builder.insert("d", command("motion").arg("delete"));
-            builder.insert("y", command("motion").arg("yank"));
             builder.insert("h", command("move").arg("left"));
             builder.insert("j", command("move").arg("down"));
             builder.insert("k", command("move").arg("up"));
@@ -429,11 +1655,80 @@ impl DispatchTarget for DocView {
             builder.insert("w", command("move-rel").arg("word").arg("next"));
             builder.insert("J", command("join-lines"));
             builder.insert("b", command("move-rel").arg("word").arg("prior"));
+            builder.insert("E", command("move-rel").arg("WORD").arg("end"));
+            builder.insert("W", command("move-rel").arg("WORD").arg("next"));
+            builder.insert("B", command("move-rel").arg("WORD").arg("prior"));
+            builder.insert("n", command("search-next").arg("forward"));
+            builder.insert("N", command("search-next").arg("backward"));
+            for name in Registers::names() {
+                builder.insert(
+                    format!("\"{}", name).as_str(),
+                    command("select-register").arg(name.to_string().as_str()),
+                );
+            }
+        }
+
+        if matches!(
+            self.mode,
+            Mode::Normal | Mode::Visual { .. } | Mode::NormalWithOp(_)
+        ) {
+            builder.insert("/", prompt_command_line(Direction::Forward.prompt()));
+            builder.insert("?", prompt_command_line(Direction::Backward.prompt()));
+            for (keys, noun, rel) in [
+                ("0", "line", "begin"),
+                ("^", "text", "begin"),
+                ("$", "line", "end"),
+                ("gg", "doc", "begin"),
+                ("G", "doc", "end"),
+                ("{", "paragraph", "prior"),
+                ("}", "paragraph", "next"),
+                ("%", "bracket", "next"),
+                ("H", "screen", "begin"),
+                ("M", "screen", "middle"),
+                ("L", "screen", "end"),
+            ] {
+                builder.insert(keys, command("move-rel").arg(noun).arg(rel));
+            }
+            for (key, rel, till) in [
+                ("f", "next", "find"),
+                ("t", "next", "till"),
+                ("F", "prior", "find"),
+                ("T", "prior", "till"),
+            ] {
+                builder.insert(key, command("find-char").arg(rel).arg(till));
+            }
+            builder.insert(";", command("repeat-find").arg("same"));
+            builder.insert(",", command("repeat-find").arg("reverse"));
+            for name in Marks::names() {
+                for (prefix, to) in [('\'', "line"), ('`', "char")] {
+                    builder.insert(
+                        format!("{}{}", prefix, name).as_str(),
+                        command("goto-mark").arg(name.to_string().as_str()).arg(to),
+                    );
+                }
+            }
         }
 
         if matches!(self.mode, Mode::Visual { .. } | Mode::NormalWithOp(_)) {
-            builder.insert("i", command("inner"));
-            builder.insert("a", command("a"));
+            for (prefix, obj_mod) in [("i", "inner"), ("a", "a")] {
+                for name in [
+                    "w", "W", "s", "p", "t", "\"", "'", "`", "(", ")", "b", "[", "]", "{", "}",
+                    "B", "<", ">",
+                ] {
+                    let obj = match name {
+                        "w" => "word",
+                        "W" => "WORD",
+                        "s" => "sentence",
+                        "p" => "paragraph",
+                        "t" => "tag",
+                        bracket_or_quote => bracket_or_quote,
+                    };
+                    builder.insert(
+                        format!("{}{}", prefix, name).as_str(),
+                        command("text-object").arg(obj_mod).arg(obj),
+                    );
+                }
+            }
         }
 
         match self.mode {
@@ -444,26 +1739,56 @@ impl DispatchTarget for DocView {
                 builder.insert("j", command("move-rel").arg("line").arg("next"));
                 builder.insert("k", command("move-rel").arg("line").arg("prior"));
                 builder.insert("l", command("move-rel").arg("char").arg("next"));
+                // `cw` changes to the end of the word, as `ce` does.
+                let word_rel = if op == Op::Change { "end" } else { "next" };
                 builder.insert("e", command("move-rel").arg("word").arg("end"));
-                builder.insert("w", command("move-rel").arg("word").arg("end"));
+                builder.insert("w", command("move-rel").arg("word").arg(word_rel));
                 builder.insert("b", command("move-rel").arg("word").arg("prior"));
+                builder.insert("E", command("move-rel").arg("WORD").arg("end"));
+                builder.insert("W", command("move-rel").arg("WORD").arg(word_rel));
+                builder.insert("B", command("move-rel").arg("WORD").arg("prior"));
                 builder.insert("J", command("join-lines"));
+                builder.insert("n", command("search-next").arg("forward"));
+                builder.insert("N", command("search-next").arg("backward"));
             }
-            Mode::NormalWithOpObjMode(_op, _obj_mode) => {
-                builder.insert("w", command("word"));
-            }
-            Mode::Visual { .. } => {
+            Mode::Visual(visual_mode) => {
                 builder.insert(Key::Esc, command("switch-mode").arg("normal"));
-                builder.insert("x", command("delete"));
+                builder.insert("c", command("visual-op").arg("change"));
+                builder.insert("d", command("visual-op").arg("delete"));
+                builder.insert("x", command("visual-op").arg("delete"));
+                builder.insert("y", command("visual-op").arg("yank"));
+                builder.insert("<", command("visual-op").arg("shift-left"));
+                builder.insert(">", command("visual-op").arg("shift-right"));
+                for (key, kind) in [
+                    (Key::Utf8('v'), VisualMode::Char),
+                    (Key::Utf8('V'), VisualMode::Line),
+                    (Key::Ctrl('v'), VisualMode::Block),
+                ] {
+                    // Repeating the key that started the selection ends it.
+                    let mode = if kind == visual_mode {
+                        "normal"
+                    } else {
+                        kind.mode_name()
+                    };
+                    builder.insert(key, command("switch-mode").arg(mode));
+                }
+                if visual_mode == VisualMode::Block {
+                    builder.insert("I", command("block-insert"));
+                }
             }
             Mode::Insert => {
                 builder.insert(Key::Esc, command("switch-mode").arg("normal"));
                 builder.insert("jk", DK::Key(Key::Esc));
                 builder.insert(Key::Backspace, command("delete-backwards"));
                 builder.insert(Key::Enter, command("newline"));
+                builder.insert(Key::Ctrl('i'), command("insert-tab"));
             }
             Mode::Normal => {
                 builder.insert("u", command("undo"));
+                builder.insert("g-", command("earlier"));
+                builder.insert("g+", command("later"));
+                builder.insert("gt", command("focus-tab").arg("next").at_view_map());
+                builder.insert("gT", command("focus-tab").arg("prior").at_view_map());
                 builder.insert(Key::Ctrl('r'), command("redo"));
                 builder.insert(
                     Key::Ctrl('u'),
@@ -474,36 +1799,84 @@ impl DispatchTarget for DocView {
                     command("move-rel").arg("line").arg("next").arg(44),
                 );
                 builder.insert("s", command("save"));
+                builder.insert("c", command("motion").arg("change"));
+                builder.insert("d", command("motion").arg("delete"));
+                builder.insert("y", command("motion").arg("yank"));
+                builder.insert("<", command("motion").arg("shift-left"));
+                builder.insert(">", command("motion").arg("shift-right"));
                 builder.insert("v", command("switch-mode").arg("visual"));
+                builder.insert("V", command("switch-mode").arg("visual-line"));
+                builder.insert(Key::Ctrl('v'), command("switch-mode").arg("visual-block"));
                 builder.insert("i", command("switch-mode").arg("insert"));
-                builder.insert(
-                    ":",
-                    command("focus")
-                        .arg(Target::Named("command-line".to_string()))
-                        .at_view_map(),
-                );
-                builder.insert(
-                    "o",
-                    DK::Sequence(vec![
-                        command("newline").arg("below").at_view(vk),
-                        command("switch-mode").arg("insert").at_view(vk),
-                    ]),
-                );
-                builder.insert(
-                    "O",
-                    DK::Sequence(vec![
-                        command("newline").arg("above").at_view(vk),
-                        command("move-rel").arg("line").arg("begin").at_view(vk),
-                        command("switch-mode").arg("insert").at_view(vk),
-                    ]),
-                );
+                builder.insert(":", prompt_command_line(':'));
+                builder.insert("o", command("open-line").arg("below"));
+                builder.insert("O", command("open-line").arg("above"));
+                builder.insert(".", command("repeat-change"));
                 builder.insert("x", command("delete-rel").arg("char").arg("next"));
                 builder.insert("X", command("delete-rel").arg("char").arg("prior"));
+                builder.insert("p", command("put").arg("after"));
+                builder.insert("P", command("put").arg("before"));
+                for name in Marks::names().filter(|&name| Marks::is_settable(name)) {
+                    builder.insert(
+                        format!("m{}", name).as_str(),
+                        command("set-mark").arg(name.to_string().as_str()),
+                    );
+                }
+                builder.insert(Key::Ctrl('o'), command("jump").arg("older"));
+                builder.insert(Key::Ctrl('i'), command("jump").arg("newer"));
+                // While a macro is being recorded, `q` stops recording.
+                let recording = self.registers.borrow().recording().is_some();
+                for name in Registers::names().filter(|&name| Registers::is_macro_name(name)) {
+                    if !recording {
+                        builder.insert(
+                            format!("q{}", name).as_str(),
+                            command("record-macro").arg(name.to_string().as_str()),
+                        );
+                    }
+                    builder.insert(
+                        format!("@{}", name).as_str(),
+                        command("play-macro").arg(name.to_string().as_str()),
+                    );
+                }
+                if recording {
+                    builder.insert("q", command("record-macro"));
+                }
+                builder.insert("@@", command("play-macro").arg("@"));
+                for (keys, dk) in window_bindings() {
+                    builder.insert([vec![Key::Ctrl('w')], keys.parse_keys()].concat(), dk);
+                }
             }
         }
         builder.get_bindings()
     }
     fn send_key(&mut self, key: Key) -> Result<Status> {
+        if let Some(mut args) = self.awaiting_find.take() {
+            return match key {
+                Key::Utf8(ch) => {
+                    args.push(Variant::String(ch.to_string()));
+                    Ok(Status::Dispatch(DK::Dispatch(
+                        Target::View(self.key),
+                        Message::Command {
+                            name: "find-char".to_string(),
+                            args,
+                        },
+                    )))
+                }
+                _ => {
+                    // Any other key cancels the find, along with an operator waiting on it.
+                    self.pending_count = None;
+                    if matches!(self.mode, Mode::NormalWithOp(_)) {
+                        self.change.clear();
+                        self.switch_mode(Mode::Normal);
+                    }
+                    Ok(Status::Ok)
+                }
+            };
+        }
+        if self.mode == Mode::Insert && !self.change.is_empty() {
+            self.change
+                .push(DK::Dispatch(Target::View(self.key), Message::SendKey(key)));
+        }
         match self.mode {
             Mode::Normal | Mode::Visual { .. } => Ok(Status::Message {
                 message: format!("No mapping found for {:?} in {:?} mode.", key, self.mode),
@@ -522,7 +1895,36 @@ impl DispatchTarget for DocView {
             )),
         }
     }
-    fn execute_command(&mut self, name: String, mut args: Vec<Variant>) -> Result<Status> {
+    fn execute_command(&mut self, name: String, args: Vec<Variant>) -> Result<Status> {
+        let mode_before = self.mode;
+        let dk = DK::Dispatch(
+            Target::View(self.key),
+            Message::Command {
+                name: name.clone(),
+                args: args.clone(),
+            },
+        );
+        let recorded_args = args.clone();
+        // `.` repeats a visual operator over as much text from wherever it's used, so the change
+        // begins by selecting that much again.
+        let reselect = match (name.as_str(), args.first()) {
+            ("visual-op", Some(Variant::String(op))) if op == "yank" => None,
+            ("visual-op" | "block-insert", _) => self.reselect_dk(),
+            _ => None,
+        };
+        let status = self.run_command(name.clone(), args)?;
+        if let Some(reselect) = reselect {
+            self.change = vec![reselect];
+        }
+        self.record_change(mode_before, dk, &name, &recorded_args);
+        Ok(status)
+    }
+}
+
+impl DocView {
+    fn run_command(&mut self, name: String, mut args: Vec<Variant>) -> Result<Status> {
+        let count = pull_count(&mut args, self.pending_count.take());
+        let n = count.unwrap_or(1);
         if name.as_str() == "switch-mode" {
             ensure!(args.len() == 1);
             if let Variant::String(arg) = args.remove(0) {
@@ -539,11 +1941,40 @@ impl DispatchTarget for DocView {
             (Mode::Normal, "motion") => {
                 if let Some(Variant::String(op)) = args.first() {
                     self.mode = Mode::NormalWithOp(Op::from_str(op)?);
+                    self.pending_count = count;
                 }
                 Ok(Status::Ok)
             }
+            (Mode::Normal, "repeat-change") => {
+                ensure!(args.is_empty());
+                self.repeat_change(count)
+            }
+            (Mode::Normal, "open-line") => match args.as_slice() {
+                [Variant::String(arg)] => {
+                    if !matches!(arg.as_str(), "above" | "below") {
+                        return Err(error!("'open-line' expects one of {{above,below}}"));
+                    }
+                    // The new line belongs to the change made by the text typed into it.
+                    self.doc.borrow_mut().begin_group();
+                    if arg == "above" {
+                        self.insert_newline_above()?;
+                    } else {
+                        self.insert_newline_below()?;
+                    }
+                    self.switch_mode(Mode::Insert);
+                    self.doc.borrow_mut().end_group();
+                    Ok(Status::Ok)
+                }
+                _ => Err(error!("'open-line' expects a direction")),
+            },
             (Mode::Normal, "undo") => {
-                let pos = self.doc.undo_change();
+                let mut pos = None;
+                for _ in 0..n {
+                    match self.doc.borrow_mut().undo_change() {
+                        Some(undone) => pos = Some(undone),
+                        None => break,
+                    }
+                }
                 self.jump_cursor_pos(pos);
                 if pos.is_none() {
                     Ok(status!("Nothing left to undo."))
@@ -551,8 +1982,51 @@ impl DispatchTarget for DocView {
                     Ok(Status::Ok)
                 }
             }
+            (Mode::Normal, "save") => match args.as_slice() {
+                [] => self.save_file(None),
+                [Variant::String(filename)] => self.save_file(Some(filename.clone())),
+                _ => Err(error!("'save' expects an optional filename")),
+            },
+            (Mode::Normal, "goto-line") => match args.as_slice() {
+                [Variant::String(line)] => {
+                    let y = self.resolve_line_spec(line)?;
+                    self.push_jump();
+                    self.jump_cursor(None, Some(y));
+                    Ok(Status::Ok)
+                }
+                _ => Err(error!("'goto-line' expects a line address")),
+            },
+            (Mode::Normal, "delete-lines") => match args.as_slice() {
+                [Variant::String(start), Variant::String(end)] => {
+                    let start = self.resolve_line_spec(start)?;
+                    let end = self.resolve_line_spec(end)?;
+                    if start > end {
+                        return Ok(status!("Backwards range given"));
+                    }
+                    let op_pos = self.doc.borrow().delete_lines(start..end + 1);
+                    self.apply_op_pos(op_pos)
+                }
+                _ => Err(error!("'delete-lines' expects a pair of line addresses")),
+            },
+            (Mode::Normal, "earlier" | "later") => {
+                let travel = match args.as_slice() {
+                    [] => Travel::Steps(n),
+                    [Variant::String(amount)] => match Travel::from_str(amount) {
+                        Ok(travel) => travel,
+                        Err(error) => return Ok(status!("{}", error.message())),
+                    },
+                    _ => return Err(error!("'{}' expects an optional amount", name)),
+                };
+                self.travel(travel, name == "later")
+            }
             (Mode::Normal, "redo") => {
-                let pos = self.doc.redo_change();
+                let mut pos = None;
+                for _ in 0..n {
+                    match self.doc.borrow_mut().redo_change() {
+                        Some(redone) => pos = Some(redone),
+                        None => break,
+                    }
+                }
                 self.jump_cursor_pos(pos);
                 if pos.is_none() {
                     Ok(status!("Nothing left to redo."))
@@ -571,7 +2045,12 @@ impl DispatchTarget for DocView {
             }
             (_, "join-lines") => {
                 ensure!(args.is_empty());
-                self.join_line()
+                // Like vim, a count is the number of lines to join, so anything under two joins
+                // two.
+                for _ in 1..n.max(2) {
+                    self.join_line()?;
+                }
+                Ok(Status::Ok)
             }
             (_, "delete-backwards") => {
                 ensure!(args.is_empty());
@@ -581,25 +2060,143 @@ impl DispatchTarget for DocView {
                 ensure!(args.is_empty());
                 self.delete_rel(Noun::Char, Rel::Next)
             }
-            (Mode::Visual(VisualMode::Char), "delete") => self.delete_sel(),
-            (Mode::Normal, "open") => {
-                ensure!(args.len() == 1);
-                if let Variant::String(arg) = args.remove(0) {
-                    self.open(arg)
-                } else {
-                    Err(error!("'open' expects a filename"))
+            (Mode::Visual(_), "visual-op") => match args.as_slice() {
+                [Variant::String(op)] => self.do_op_to_sel(Op::from_str(op)?),
+                _ => Err(error!("'visual-op' expects an operator")),
+            },
+            (Mode::Normal, "select-extent") => match args.as_slice() {
+                [Variant::String(mode), Variant::Pos(extent)] => match Mode::from_str(mode)? {
+                    Mode::Visual(visual) => {
+                        self.select_extent(visual, *extent);
+                        Ok(Status::Ok)
+                    }
+                    mode => Err(error!(
+                        "'select-extent' expects a visual mode, not {:?}",
+                        mode
+                    )),
+                },
+                _ => Err(error!(
+                    "'select-extent' expects a visual mode and an extent"
+                )),
+            },
+            (Mode::Visual(VisualMode::Block), "block-insert") => {
+                ensure!(args.is_empty());
+                let (rows, cols) = self.sel_block().unwrap();
+                self.begin_block_insert(rows, cols.start);
+                Ok(Status::Ok)
+            }
+            (Mode::Normal, "open") => match args.as_slice() {
+                [Variant::String(filename)] => self.open(filename.clone(), false),
+                [Variant::String(filename), Variant::Bool(force)] => {
+                    self.open(filename.clone(), *force)
+                }
+                _ => Err(error!("'open' expects a filename")),
+            },
+            (Mode::Normal, "new-buffer") => {
+                ensure!(args.is_empty());
+                self.new_buffer()
+            }
+            (Mode::Insert, "insert-tab") => {
+                ensure!(args.is_empty());
+                let tab = self.doc.borrow().indent().tab(self.render_cursor_x);
+                for ch in tab.chars() {
+                    self.insert_char(ch)?;
                 }
+                Ok(Status::Ok)
             }
-            (Mode::Normal | Mode::Visual(VisualMode::Char), "move") => {
+            (_, "set-option") => match args.as_slice() {
+                [Variant::String(name), value] => self.set_option(name, value),
+                _ => Err(error!("'set-option' expects an option and a value")),
+            },
+            (_, "line-numbers") => match args.as_slice() {
+                [Variant::String(line_numbers)] => {
+                    self.gutter
+                        .set_line_numbers(LineNumbers::from_str(line_numbers)?);
+                    Ok(Status::Ok)
+                }
+                _ => Err(error!("'line-numbers' expects how to number lines")),
+            },
+            // Whatever puts signs in the gutter registers its own column for them, which is as
+            // wide as the count (two cells by default).
+            (_, "register-sign-column") => match args.as_slice() {
+                [Variant::String(column)] => {
+                    self.doc
+                        .borrow_mut()
+                        .signs_mut()
+                        .register(column, count.unwrap_or(2));
+                    Ok(Status::Ok)
+                }
+                _ => Err(error!("'register-sign-column' expects a column name")),
+            },
+            // Signs go beside the line given by the count.
+            (_, "place-sign") => match (args.as_slice(), count) {
+                ([Variant::String(column), Variant::String(text)], Some(line)) => {
+                    let sign = Sign {
+                        text: text.clone(),
+                        format: Format::none(),
+                    };
+                    self.doc
+                        .borrow_mut()
+                        .signs_mut()
+                        .place(column, line - 1, sign)?;
+                    Ok(Status::Ok)
+                }
+                _ => Err(error!("'place-sign' expects a column, the sign and a line")),
+            },
+            (_, "unplace-sign") => match args.as_slice() {
+                [Variant::String(column)] => {
+                    self.doc
+                        .borrow_mut()
+                        .signs_mut()
+                        .unplace(column, count.map(|line| line - 1))?;
+                    Ok(Status::Ok)
+                }
+                _ => Err(error!("'unplace-sign' expects a column")),
+            },
+            (Mode::Normal, "add-buffer") => match args.as_slice() {
+                [Variant::String(filename)] => self.add_buffer(filename.clone()),
+                _ => Err(error!("'add-buffer' expects a filename")),
+            },
+            (Mode::Normal, "buffer") => match (args.as_slice(), count) {
+                ([], Some(id)) => self.show_buffer(id),
+                ([], None) => Ok(Status::Ok),
+                ([Variant::String(name)], None) => self.show_buffer_named(name),
+                _ => Err(error!("'buffer' expects a buffer number or name")),
+            },
+            (Mode::Normal, "buffer-next" | "buffer-prior") => {
+                ensure!(args.is_empty());
+                self.cycle_buffer(n, name == "buffer-next")
+            }
+            (Mode::Normal, "delete-buffer") => match args.as_slice() {
+                [Variant::Bool(force)] => self.delete_buffer(count, *force),
+                _ => Err(error!("'delete-buffer' expects whether to force")),
+            },
+            (Mode::Normal, "list-buffers") => {
+                ensure!(args.is_empty());
+                Ok(self.list_buffers())
+            }
+            (Mode::Normal | Mode::Visual(_), "move") => {
                 ensure!(args.len() == 1);
                 if let Variant::String(arg) = args.remove(0) {
-                    match arg.as_str() {
-                        "up" => self.move_cursor(0, -1),
-                        "down" => self.move_cursor(0, 1),
-                        "left" => self.move_cursor(-1, 0),
-                        "right" => self.move_cursor(1, 0),
+                    let before = self.cursor;
+                    let status = match arg.as_str() {
+                        "up" => self.move_cursor(0, -(n as RelCoord)),
+                        "down" => self.move_cursor(0, n as RelCoord),
+                        // Sideways steps go a grapheme at a time, over any combining marks.
+                        "left" | "right" => {
+                            let rel = if arg == "left" { Rel::Prior } else { Rel::Next };
+                            self.cursor = self.grapheme_steps(rel, n);
+                            self.clamp_cursor();
+                            Ok(Status::Ok)
+                        }
                         _ => Err(error!("'move' expects one of {{up,down,left,right}}")),
-                    }
+                    }?;
+                    // Running into the edge of the doc stops a macro, as in vim.
+                    Ok(if self.cursor == before {
+                        Status::Failed
+                    } else {
+                        status
+                    })
                 } else {
                     Err(error!("'move' expects a direction"))
                 }
@@ -617,28 +2214,167 @@ impl DispatchTarget for DocView {
                 )),
             },
             (Mode::NormalWithOp(op), "move-rel") => {
-                ensure!((2..=3).contains(&args.len()));
-                let (noun, rel, count) = pull_noun_rel_count(args)?;
-                for _ in 0..count {
-                    self.do_op_rel(op, noun, rel)?;
-                }
-                Ok(Status::Ok)
+                let (noun, rel) = pull_noun_rel(args)?;
+                self.do_op_rel(op, noun, rel, count)
             }
             (_, "move-rel") => {
-                ensure!((2..=3).contains(&args.len()));
-                let (noun, rel, count) = pull_noun_rel_count(args)?;
-                for _ in 0..count {
-                    self.move_cursor_rel(noun, rel)?;
-                }
-                Ok(Status::Ok)
+                let (noun, rel) = pull_noun_rel(args)?;
+                self.move_cursor_rel(noun, rel, count)
             }
             (Mode::Normal, "delete-rel") => {
-                let (noun, rel, count) = pull_noun_rel_count(args)?;
-                for _ in 0..count {
-                    self.delete_rel(noun, rel)?;
+                let (noun, rel) = pull_noun_rel(args)?;
+                let (mut start, mut end) = self.doc.borrow().find_range(self.cursor, noun, rel);
+                for _ in 1..n {
+                    match rel {
+                        Rel::Prior => start = self.doc.borrow().find_range(start, noun, rel).0,
+                        _ => end = self.doc.borrow().find_range(end, noun, rel).1,
+                    }
                 }
-                Ok(Status::Ok)
+                self.do_op_to_range(Op::Delete, start..end)
+            }
+            (Mode::NormalWithOp(_) | Mode::Visual(_), "text-object") => match args.as_slice() {
+                [Variant::String(obj_mod), Variant::String(obj)] => self.select_text_object(
+                    TextObj::from_str(obj)?,
+                    ObjMod::from_str(obj_mod)? == ObjMod::A,
+                    n,
+                ),
+                _ => Err(error!("'text-object' expects a modifier and an object")),
+            },
+            (_, "find-char") => match args.as_slice() {
+                [Variant::String(rel), Variant::String(till)] => {
+                    Rel::from_str(rel)?;
+                    ensure!(till == "find" || till == "till");
+                    self.awaiting_find = Some(args);
+                    self.pending_count = count;
+                    Ok(Status::Ok)
+                }
+                [Variant::String(rel), Variant::String(till), Variant::String(ch)] => {
+                    self.awaiting_find = None;
+                    let find = CharFind {
+                        ch: ch
+                            .chars()
+                            .next()
+                            .ok_or_else(|| error!("'find-char' needs a char"))?,
+                        rel: Rel::from_str(rel)?,
+                        till: till == "till",
+                    };
+                    self.last_find = Some(find);
+                    self.find_char(find, count, false)
+                }
+                _ => Err(error!(
+                    "'find-char' expects a direction, find or till, and a char"
+                )),
+            },
+            (_, "repeat-find") => match args.as_slice() {
+                [Variant::String(which)] => {
+                    let find = match which.as_str() {
+                        "same" => self.last_find,
+                        "reverse" => self.last_find.map(CharFind::reversed),
+                        _ => return Err(error!("'repeat-find' expects one of {{same,reverse}}")),
+                    };
+                    match find {
+                        Some(find) => self.find_char(find, count, true),
+                        None => self.go_to(None, MotionKind::Exclusive),
+                    }
+                }
+                _ => Err(error!("'repeat-find' expects one of {{same,reverse}}")),
+            },
+            (Mode::Normal, "set-mark") => match args.as_slice() {
+                [Variant::String(name)] => match name.chars().next() {
+                    Some(ch) if name.chars().count() == 1 && Marks::is_settable(ch) => {
+                        self.set_mark(ch);
+                        Ok(Status::Ok)
+                    }
+                    _ => Err(error!("'{}' is not a valid mark", name)),
+                },
+                _ => Err(error!("'set-mark' expects a mark name")),
+            },
+            (_, "goto-mark") => match args.as_slice() {
+                [Variant::String(name), Variant::String(to)] => {
+                    ensure!(to == "line" || to == "char");
+                    let name = name
+                        .chars()
+                        .next()
+                        .ok_or_else(|| error!("'goto-mark' needs a mark name"))?;
+                    self.goto_mark(name, to == "line")
+                }
+                _ => Err(error!(
+                    "'goto-mark' expects a mark name and one of {{line,char}}"
+                )),
+            },
+            (Mode::Normal, "jump") => match args.as_slice() {
+                [Variant::String(which)] => match which.as_str() {
+                    "older" => self.follow_jump(false, n),
+                    "newer" => self.follow_jump(true, n),
+                    _ => Err(error!("'jump' expects one of {{older,newer}}")),
+                },
+                _ => Err(error!("'jump' expects one of {{older,newer}}")),
+            },
+            (Mode::Normal, "record-macro") => match args.as_slice() {
+                [] => {
+                    self.registers.borrow_mut().stop_recording();
+                    Ok(Status::Ok)
+                }
+                [Variant::String(name)] => match name.chars().next() {
+                    Some(ch) if name.chars().count() == 1 && Registers::is_macro_name(ch) => {
+                        self.registers.borrow_mut().start_recording(ch);
+                        Ok(status!("recording @{}", ch))
+                    }
+                    _ => Err(error!("'{}' is not a valid register", name)),
+                },
+                _ => Err(error!("'record-macro' expects an optional register name")),
+            },
+            (Mode::Normal, "play-macro") => match args.as_slice() {
+                [Variant::String(name)] => {
+                    let name = name
+                        .chars()
+                        .next()
+                        .ok_or_else(|| error!("'play-macro' needs a register name"))?;
+                    self.play_macro(name, n)
+                }
+                _ => Err(error!("'play-macro' expects a register name")),
+            },
+            (Mode::NormalWithOp(op), "line") => {
+                ensure!(args.is_empty());
+                self.do_op_to_lines(op, self.cursor.y..self.cursor.y + n)
             }
+            (Mode::Normal | Mode::Visual(_), "select-register") => match args.as_slice() {
+                [Variant::String(name)] => match name.chars().next() {
+                    Some(ch) if name.chars().count() == 1 && Registers::is_valid_name(ch) => {
+                        self.register = Some(ch);
+                        self.pending_count = count;
+                        Ok(Status::Ok)
+                    }
+                    _ => Err(error!("'{}' is not a valid register", name)),
+                },
+                _ => Err(error!("'select-register' expects a register name")),
+            },
+            (_, "search") => match args.as_slice() {
+                [Variant::String(pattern), Variant::String(direction)] => {
+                    let direction = Direction::from_str(direction)?;
+                    self.search(&pattern.clone(), direction)
+                }
+                _ => Err(error!("'search' expects a pattern and a direction")),
+            },
+            (_, "search-next") => match args.as_slice() {
+                [Variant::String(direction)] => {
+                    let reverse = Direction::from_str(direction)? == Direction::Backward;
+                    let mut status = Status::Ok;
+                    for _ in 0..n {
+                        status = self.search_next(reverse)?;
+                    }
+                    Ok(status)
+                }
+                _ => Err(error!("'search-next' expects a direction")),
+            },
+            (Mode::Normal, "put") => match args.as_slice() {
+                [Variant::String(arg)] => match arg.as_str() {
+                    "after" => self.put(true, n),
+                    "before" => self.put(false, n),
+                    _ => Err(error!("'put' expects one of {{after,before}}")),
+                },
+                _ => Err(error!("'put' expects a direction")),
+            },
             _ => Err(not_impl!(
                 "DocView::execute_command needs to handle {:?} {:?} in mode {:?}.",
                 name,
@@ -649,15 +2385,87 @@ impl DispatchTarget for DocView {
     }
 }
 
-fn pull_noun_rel_count(args: Vec<Variant>) -> Result<(Noun, Rel, i64)> {
-    let (noun, rel, count) = match args.as_slice() {
-        [Variant::String(noun), Variant::String(rel), Variant::Int(count)] => (noun, rel, *count),
-        [Variant::String(noun), Variant::String(rel)] => (noun, rel, 1),
-        _ => return Err(error!("'delete-rel' expects a pair (noun, rel)")),
+/// Focuses the command line, showing `prompt` as the kind of text being entered.
+fn prompt_command_line(prompt: char) -> DK {
+    let command_line = Target::Named("command-line".to_string());
+    DK::Sequence(vec![
+        command("prompt")
+            .arg(prompt.to_string().as_str())
+            .at_target(command_line.clone()),
+        command("focus").arg(command_line).at_view_map(),
+    ])
+}
+
+/// The keys that follow `Ctrl-w` to work with windows.
+fn window_bindings() -> Vec<(Key, DK)> {
+    let window = |name: &str, arg: &str| command(name).arg(arg).at_view_map();
+    let resize = |dimension: &str, delta: i64| {
+        command("resize-window")
+            .arg(dimension)
+            .arg(delta)
+            .at_view_map()
+    };
+    vec![
+        (Key::Utf8('s'), window("split-window", "above")),
+        (Key::Utf8('S'), window("split-window", "above")),
+        (Key::Ctrl('s'), window("split-window", "above")),
+        (Key::Utf8('v'), window("split-window", "left")),
+        (Key::Ctrl('v'), window("split-window", "left")),
+        (Key::Utf8('h'), window("focus-window", "left")),
+        (Key::Utf8('j'), window("focus-window", "down")),
+        (Key::Utf8('k'), window("focus-window", "up")),
+        (Key::Utf8('l'), window("focus-window", "right")),
+        (Key::Utf8('w'), window("focus-window", "next")),
+        (Key::Ctrl('w'), window("focus-window", "next")),
+        (Key::Utf8('W'), window("focus-window", "prior")),
+        (Key::Utf8('c'), command("close-window").at_view_map()),
+        (
+            Key::Utf8('q'),
+            command("quit-window").arg(false).at_view_map(),
+        ),
+        (Key::Utf8('o'), command("only-window").at_view_map()),
+        (Key::Utf8('+'), resize("height", 1)),
+        (Key::Utf8('-'), resize("height", -1)),
+        (Key::Utf8('>'), resize("width", 1)),
+        (Key::Utf8('<'), resize("width", -1)),
+        (Key::Utf8('='), command("equalize-windows").at_view_map()),
+    ]
+}
+
+/// Whether a normal mode command begins a change that `.` can repeat.
+fn starts_change(name: &str, args: &[Variant]) -> bool {
+    match (name, args.first()) {
+        ("motion", Some(Variant::String(op))) => op != "yank",
+        ("switch-mode", Some(Variant::String(mode))) => mode == "insert",
+        ("delete-rel" | "put" | "join-lines" | "open-line", _) => true,
+        _ => false,
+    }
+}
+
+/// Pops the count the key dispatch appends to a command's arguments, combined with any count
+/// typed before an operator or register prefix (so `2d3w` deletes six words).
+fn pull_count(args: &mut Vec<Variant>, pending: Option<usize>) -> Option<usize> {
+    let count = match args.last() {
+        Some(&Variant::Int(count)) => {
+            args.pop();
+            Some(count.max(1) as usize)
+        }
+        _ => None,
+    };
+    match (count, pending) {
+        (Some(count), Some(pending)) => Some(count.saturating_mul(pending)),
+        (count, pending) => count.or(pending),
+    }
+}
+
+fn pull_noun_rel(args: Vec<Variant>) -> Result<(Noun, Rel)> {
+    let (noun, rel) = match args.as_slice() {
+        [Variant::String(noun), Variant::String(rel)] => (noun, rel),
+        _ => return Err(error!("expected a pair (noun, rel) [args={:?}]", args)),
     };
 
     match (Noun::from_str(noun), Rel::from_str(rel)) {
-        (Ok(noun), Ok(rel)) => Ok((noun, rel, count)),
+        (Ok(noun), Ok(rel)) => Ok((noun, rel)),
         _ => Err(error!("'move-rel' expects a pair (noun, rel)")),
     }
 }
@@ -665,20 +2473,36 @@ fn pull_noun_rel_count(args: Vec<Variant>) -> Result<(Noun, Rel, i64)> {
 impl ViewContext for DocView {
     fn get_property(&self, property: &str) -> Option<Variant> {
         if property == PROP_DOC_IS_MODIFIED {
-            Some(Variant::Bool(self.doc.is_dirty()))
+            Some(Variant::Bool(self.doc.borrow().is_dirty()))
         } else if property == PROP_DOC_FILENAME {
             self.doc
+                .borrow()
                 .get_filename()
                 .map(|filename| Variant::String(filename.to_string()))
         } else if property == PROP_DOCVIEW_CURSOR_POS {
             Some(Variant::Pos(self.cursor))
-        } else if property == PROP_DOCVIEW_STATUS {
-            Some(Variant::String(format!(
-                "▐ {:?} ▐ {}:{} ",
-                self.mode,
-                self.cursor.y + 1,
-                self.cursor.x + 1
-            )))
+        } else if property == PROP_DOCVIEW_MODE {
+            Some(Variant::String(self.mode.name().to_string()))
+        } else if property == PROP_DOCVIEW_BUFFER_ID {
+            Some(Variant::Int(self.buffer_id as i64))
+        } else if property == PROP_DOCVIEW_WIDTH {
+            Some(Variant::Int(self.window_size.width as i64))
+        } else if property == PROP_DOCVIEW_HEIGHT {
+            Some(Variant::Int(self.window_size.height as i64))
+        } else if property == PROP_DOCVIEW_MODIFIED_BUFFER {
+            self.buffers
+                .borrow()
+                .iter()
+                .find(|buffer| buffer.is_dirty())
+                .map(|buffer| Variant::String(buffer.name()))
+        } else if property == PROP_DOC_LINE_COUNT {
+            Some(Variant::Int(self.doc.borrow().line_count() as i64))
+        } else if property == PROP_DOC_FILE_TYPE {
+            self.doc
+                .borrow()
+                .get_filename()
+                .and_then(filetype::detect)
+                .map(|file_type| Variant::String(file_type.to_string()))
         } else {
             log::trace!("DocView::get_property unhandled request for '{}'", property);
             None
@@ -687,27 +2511,103 @@ impl ViewContext for DocView {
 }
 
 impl DocView {
-    pub fn new(view_key: ViewKey, plugin: PluginRef) -> Self {
+    fn gutter_width(&self) -> Coord {
+        let doc = self.doc.borrow();
+        self.gutter.width(doc.signs(), doc.line_count())
+    }
+    /// The part of a window of `size` left for text once the gutter is drawn down the left and
+    /// the status line along the bottom. Windows too short for both go without the status line.
+    fn text_size(&self, size: Size) -> Size {
+        Size {
+            width: size.width.saturating_sub(self.gutter_width()),
+            height: if size.height > 1 {
+                size.height - 1
+            } else {
+                size.height
+            },
+        }
+    }
+    /// Draws the status line in row `y`, in the colors for the focused window if this is it.
+    fn display_status_line(&self, view_map: &ViewMap, bmp: &mut BitmapView, y: Coord) {
+        let group = if view_map.focused_window_key() == Some(self.key) {
+            Group::StatusLine
+        } else {
+            Group::StatusLineNC
+        };
+        let format = self.settings.colors.format(group);
+        let text = self
+            .settings
+            .status_line
+            .format
+            .render(self, bmp.get_size().width);
+        bmp.append_chars_at(Pos { x: 0, y }, text.chars(), format);
+    }
+    pub fn new(
+        view_key: ViewKey,
+        plugin: PluginRef,
+        registers: RegistersRef,
+        buffers: BuffersRef,
+        search: SearchRef,
+        settings: Rc<Settings>,
+        buffer_id: BufferId,
+    ) -> Self {
+        let doc = buffers.borrow().get(buffer_id).unwrap().doc.clone();
         Self {
             _plugin: plugin,
             key: view_key,
             cursor: Default::default(),
             sel: None,
             render_cursor_x: 0,
-            doc: Doc::empty(),
+            doc,
             scroll_offset: Default::default(),
             mode: Mode::Normal,
+            registers,
+            buffers,
+            buffer_id,
+            register: None,
+            search,
+            gutter: Gutter::new(settings.gutter.line_numbers),
+            list: settings.list.enabled,
+            block_insert: None,
+            pending_count: None,
+            awaiting_find: None,
+            last_find: None,
+            text_height: 0,
+            window_size: Size {
+                width: 0,
+                height: 0,
+            },
+            jumps: Default::default(),
+            change: Vec::new(),
+            last_change: Vec::new(),
+            settings,
         }
     }
 }
 
 mod mode {
+    /// Whether a text object takes just the inside of something, or all of it.
     #[derive(Copy, Clone, Debug, Eq, PartialEq)]
-    #[allow(dead_code)]
     pub enum ObjMod {
         Inner,
         A,
     }
+
+    impl std::str::FromStr for ObjMod {
+        type Err = crate::error::Error;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "inner" => Ok(Self::Inner),
+                "a" => Ok(Self::A),
+                _ => Err(Self::Err::new(format!(
+                    "{} is not a valid text object modifier",
+                    s
+                ))),
+            }
+        }
+    }
+
     #[allow(dead_code)]
     #[derive(Copy, Clone, Debug, Eq, PartialEq)]
     pub enum Mode {
@@ -715,13 +2615,8 @@ mod mode {
         Visual(VisualMode),
         Normal,
         NormalWithOp(Op),
-        #[allow(clippy::enum_variant_names)]
-        NormalWithOpObjMode(Op, ObjMod),
     }
 
-    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
-    pub enum TextObj {}
-
     impl std::str::FromStr for Mode {
         type Err = crate::error::Error;
 
@@ -745,6 +2640,21 @@ mod mode {
         Change,
         Delete,
         Yank,
+        ShiftLeft,
+        ShiftRight,
+    }
+
+    impl Mode {
+        /// The mode's name as the status line shows it.
+        pub fn name(&self) -> &'static str {
+            match self {
+                Self::Insert => "INSERT",
+                Self::Visual(VisualMode::Char) => "VISUAL",
+                Self::Visual(VisualMode::Line) => "V-LINE",
+                Self::Visual(VisualMode::Block) => "V-BLOCK",
+                Self::Normal | Self::NormalWithOp(_) => "NORMAL",
+            }
+        }
     }
 
     impl Op {
@@ -753,6 +2663,8 @@ mod mode {
                 Self::Change => "c",
                 Self::Delete => "d",
                 Self::Yank => "y",
+                Self::ShiftLeft => "<",
+                Self::ShiftRight => ">",
             }
         }
     }
@@ -765,6 +2677,8 @@ mod mode {
                 "change" => Ok(Self::Change),
                 "delete" => Ok(Self::Delete),
                 "yank" => Ok(Self::Yank),
+                "shift-left" => Ok(Self::ShiftLeft),
+                "shift-right" => Ok(Self::ShiftRight),
                 missing => Err(Self::Err::new(format!("{} is not a valid Op", missing))),
             }
         }
@@ -777,6 +2691,17 @@ mod mode {
         Block,
     }
 
+    impl VisualMode {
+        /// The name of the `Mode` that selects this kind of text.
+        pub fn mode_name(self) -> &'static str {
+            match self {
+                Self::Char => "visual",
+                Self::Line => "visual-line",
+                Self::Block => "visual-block",
+            }
+        }
+    }
+
     impl std::str::FromStr for VisualMode {
         type Err = crate::error::Error;
 
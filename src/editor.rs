@@ -1,12 +1,21 @@
 use crate::bindings::Bindings;
+use crate::bitmap::bmp_fmt_at;
+use crate::buffers::{Buffers, BuffersRef};
 use crate::commandline::CommandLine;
+use crate::doc::Doc;
 use crate::docview::DocView;
 use crate::error::Result;
 use crate::plugin::PluginRef;
 use crate::prelude::*;
+use crate::registers::RegistersRef;
+use crate::search::Search;
 use crate::status::Status;
+use crate::theme::Group;
 use crate::types::{Pos, Rect};
 use crate::view::ViewContext;
+use crate::view_map::TabPage;
+use crate::vstack::VStack;
+use std::path::Path;
 
 #[allow(dead_code)]
 pub struct Editor {
@@ -14,8 +23,10 @@ pub struct Editor {
     should_quit: bool,
     last_key: Option<Key>,
     view_key: ViewKey,
-    top_view_key: Option<ViewKey>,
     command_line_key: ViewKey,
+    registers: RegistersRef,
+    buffers: BuffersRef,
+    settings: Rc<Settings>,
 }
 
 impl ViewContext for Editor {
@@ -29,41 +40,64 @@ impl ViewContext for Editor {
 }
 impl View for Editor {
     fn get_doc_text(&self, view_map: &ViewMap) -> Option<String> {
-        self.top_view_key
+        view_map
+            .focused_window_key()
             .and_then(|vk| view_map.get_view(vk).get_doc_text(view_map))
     }
     fn install_plugins(&mut self, plugin: PluginRef) {
         self.plugin = plugin;
     }
-    fn layout(&mut self, _view_map: &ViewMap, size: Size) -> Vec<(ViewKey, Rect)> {
+    fn layout(&mut self, view_map: &ViewMap, size: Size) -> Vec<(ViewKey, Rect)> {
         let mut ret = Vec::new();
 
-        if let Some(top_view_key) = self.top_view_key {
+        if let Some(tab_root) = view_map.current_tab_root() {
+            // Like vim, the tabline only shows once there's more than one tab page.
+            let tabline_height = usize::from(view_map.tab_count() > 1);
             ret.push((
-                top_view_key,
+                tab_root,
                 Rect {
                     x: 0,
-                    y: 0,
+                    y: tabline_height,
                     width: size.width,
-                    height: size.height - 2,
+                    height: size.height - 1 - tabline_height,
                 },
             ));
         } else {
-            trace!("there is no top_view_key in the editor");
+            trace!("there is no tab page in the editor");
         }
         ret.push((
             self.command_line_key,
             Rect {
                 x: 0,
-                y: size.height - 2,
+                y: size.height - 1,
                 width: size.width,
-                height: 2,
+                height: 1,
             },
         ));
         ret
     }
 
-    fn display(&self, _view_map: &ViewMap, _bmp: &mut BitmapView) {}
+    fn display(&self, view_map: &ViewMap, bmp: &mut BitmapView) {
+        if view_map.tab_count() < 2 {
+            return;
+        }
+        for x in 0..bmp.get_size().width {
+            bmp.set_glyph(
+                Pos { x, y: 0 },
+                Glyph::new(' ', self.settings.colors.format(Group::TabLine)),
+            );
+        }
+        let mut pos = Pos::zero();
+        for tab_page in view_map.tab_pages() {
+            let group = if tab_page.is_current {
+                Group::TabLineSel
+            } else {
+                Group::TabLine
+            };
+            let format = self.settings.colors.format(group);
+            bmp_fmt_at!(bmp, pos, format, "{}", tab_label(view_map, &tab_page));
+        }
+    }
 
     fn get_view_key(&self) -> ViewKey {
         self.view_key
@@ -97,23 +131,55 @@ impl Editor {
     pub fn get_should_quit(&self) -> bool {
         self.should_quit
     }
-    pub fn install(plugin: PluginRef, view_map: &mut ViewMap) -> ViewKey {
+    /// Installs the editor along with its command line and first window. `registers` are shared
+    /// with the main loop, which records macros into them.
+    pub fn install(
+        plugin: PluginRef,
+        view_map: &mut ViewMap,
+        settings: Rc<Settings>,
+        registers: RegistersRef,
+    ) -> ViewKey {
         let command_line_key = view_map.get_next_key();
-        let command_line = viewref(CommandLine::new(plugin.clone(), command_line_key));
+        let search = Search::new();
+        let command_line = viewref(CommandLine::new(
+            plugin.clone(),
+            command_line_key,
+            search.clone(),
+            settings.clone(),
+        ));
         let editor_view_key = view_map.get_next_key();
-        let docview = viewref(DocView::new(view_map.get_next_key(), plugin.clone()));
+        let buffers = Buffers::new();
+        let mut doc = Doc::empty();
+        doc.set_indent(settings.indent);
+        let buffer_id = buffers.borrow_mut().add(doc);
+        let docview = viewref(DocView::new(
+            view_map.get_next_key(),
+            plugin.clone(),
+            registers.clone(),
+            buffers.clone(),
+            search,
+            settings.clone(),
+            buffer_id,
+        ));
         let focused_view_key = docview.get_view_key();
+        // Windows are split within this stack, which stays put as the root of the first tab.
+        let windows = viewref(VStack::new(view_map.get_next_key(), vec![focused_view_key]));
+        let windows_view_key = windows.get_view_key();
         let slf = Self {
             plugin,
             view_key: editor_view_key,
             should_quit: false,
             last_key: None,
-            top_view_key: Some(focused_view_key),
             command_line_key,
+            registers,
+            buffers,
+            settings,
         };
         let vk = slf.view_key;
         view_map.insert(viewref(slf), None, Some("editor".to_string()));
-        view_map.insert(docview, Some(editor_view_key), None);
+        view_map.insert(windows, Some(editor_view_key), None);
+        view_map.insert(docview, Some(windows_view_key), None);
+        view_map.add_tab(windows_view_key);
         view_map.insert(
             command_line,
             Some(editor_view_key),
@@ -123,10 +189,6 @@ impl Editor {
         view_map.set_root_view_key(editor_view_key);
         vk
     }
-    pub fn set_top_view_key(&mut self, top_view_key: Option<ViewKey>) {
-        self.top_view_key = top_view_key;
-    }
-
     pub fn set_last_key(&mut self, key: Option<Key>) {
         self.last_key = key;
     }
@@ -152,6 +214,37 @@ impl Editor {
     }*/
 }
 
+/// A tab page's label, as vim shows it: the number of windows if there's more than one, a `+` if
+/// any of them is modified, then the name of the focused window's file.
+fn tab_label(view_map: &ViewMap, tab_page: &TabPage) -> String {
+    let modified = tab_page.windows.iter().any(|&vk| {
+        view_map
+            .get_view(vk)
+            .get_property_bool(PROP_DOC_IS_MODIFIED, false)
+    });
+    let mut flags = String::new();
+    if tab_page.windows.len() > 1 {
+        flags += &tab_page.windows.len().to_string();
+    }
+    if modified {
+        flags.push('+');
+    }
+    let filename = tab_page
+        .focused
+        .and_then(|vk| view_map.get_view(vk).get_property_string(PROP_DOC_FILENAME));
+    let name = match filename {
+        Some(ref filename) => Path::new(filename)
+            .file_name()
+            .map_or(filename.clone(), |name| name.to_string_lossy().into_owned()),
+        None => "[No Name]".to_string(),
+    };
+    if flags.is_empty() {
+        format!(" {} ", name)
+    } else {
+        format!(" {} {} ", flags, name)
+    }
+}
+
 impl Drop for Editor {
     fn drop(&mut self) {
         println!("Thanks for wimming.\r\n");
@@ -30,6 +30,13 @@ impl Error {
     {
         Self::NotImplemented { message: m.into() }
     }
+    pub fn message(&self) -> &str {
+        match self {
+            Self::General { message } | Self::IO { message } | Self::NotImplemented { message } => {
+                message
+            }
+        }
+    }
 }
 
 impl From<io::Error> for Error {
@@ -60,6 +67,15 @@ impl<T> ErrorContext<T> for std::result::Result<T, toml::de::Error> {
     }
 }
 
+impl<T> ErrorContext<T> for std::result::Result<T, toml::ser::Error> {
+    fn context(self, message: &str) -> Result<T> {
+        match self {
+            Ok(t) => Ok(t),
+            Err(e) => Err(error!("{}: (toml encoding error: {})", message, e)),
+        }
+    }
+}
+
 impl ErrorContext<()> for std::io::Result<()> {
     fn context(self, message: &str) -> Result<()> {
         match self {
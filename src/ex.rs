@@ -0,0 +1,554 @@
+use crate::error::{Error, Result};
+use crate::prelude::*;
+
+/// A single line address in an ex command, e.g. `10`, `.`, `$` or `.+3`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LineSpec {
+    /// A 1-based absolute line number.
+    Number(usize, isize),
+    Current(isize),
+    Last(isize),
+}
+
+impl LineSpec {
+    /// Resolve this address into a 0-based row index given the cursor row and the number of rows
+    /// in the document.
+    pub fn resolve(self, current: Coord, line_count: usize) -> Coord {
+        let last = line_count.saturating_sub(1) as isize;
+        let (base, offset) = match self {
+            Self::Number(n, offset) => (isize::try_from(n).unwrap_or(isize::MAX) - 1, offset),
+            Self::Current(offset) => (current as isize, offset),
+            Self::Last(offset) => (last, offset),
+        };
+        base.saturating_add(offset).clamp(0, last.max(0)) as Coord
+    }
+}
+
+impl std::fmt::Display for LineSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let offset = match self {
+            Self::Number(n, offset) => {
+                write!(f, "{}", n)?;
+                offset
+            }
+            Self::Current(offset) => {
+                write!(f, ".")?;
+                offset
+            }
+            Self::Last(offset) => {
+                write!(f, "$")?;
+                offset
+            }
+        };
+        match offset.cmp(&0) {
+            std::cmp::Ordering::Less => write!(f, "{}", offset),
+            std::cmp::Ordering::Greater => write!(f, "+{}", offset),
+            std::cmp::Ordering::Equal => Ok(()),
+        }
+    }
+}
+
+impl FromStr for LineSpec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parser = Parser::new(s);
+        match parser.line_spec()? {
+            Some(line_spec) if parser.is_done() => Ok(line_spec),
+            _ => Err(error!("'{}' is not a valid line address", s)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ExRange {
+    None,
+    Single(LineSpec),
+    Span(LineSpec, LineSpec),
+}
+
+impl ExRange {
+    /// The whole document, as in `:%`.
+    pub fn whole() -> Self {
+        Self::Span(LineSpec::Number(1, 0), LineSpec::Last(0))
+    }
+    fn endpoints(self) -> Option<(LineSpec, LineSpec)> {
+        match self {
+            Self::None => None,
+            Self::Single(line_spec) => Some((line_spec, line_spec)),
+            Self::Span(start, end) => Some((start, end)),
+        }
+    }
+}
+
+/// The ex commands we know about, along with the shortest abbreviation we accept for each.
+static EX_COMMANDS: &[(&str, usize)] = &[
+    ("badd", 3),
+    ("bdelete", 2),
+    ("bnext", 2),
+    ("bprevious", 2),
+    ("buffer", 1),
+    ("buffers", 7),
+    ("close", 3),
+    ("delete", 1),
+    ("earlier", 2),
+    ("edit", 1),
+    ("later", 3),
+    ("ls", 2),
+    ("only", 2),
+    ("quit", 1),
+    ("set", 2),
+    ("split", 2),
+    ("tabclose", 4),
+    ("tabnew", 6),
+    ("tabnext", 4),
+    ("tabonly", 4),
+    ("tabprevious", 4),
+    ("vsplit", 2),
+    ("write", 1),
+    ("wq", 2),
+    ("xit", 1),
+];
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ExCommand {
+    pub range: ExRange,
+    /// The full (unabbreviated) command name, or None for a bare range like `:10`.
+    pub name: Option<&'static str>,
+    pub bang: bool,
+    pub count: Option<usize>,
+    pub arg: Option<String>,
+}
+
+impl ExCommand {
+    /// Translates the parsed command into the DKs that carry it out on the focused view.
+    pub fn to_dks(&self) -> Result<Vec<DK>> {
+        let lines = self.count_adjusted_range();
+        match self.name {
+            None => match lines.endpoints() {
+                Some((_, end)) => Ok(vec![command("goto-line")
+                    .arg(end.to_string().as_str())
+                    .at_focused()]),
+                None => Ok(vec![]),
+            },
+            Some("delete") => {
+                let (start, end) = lines
+                    .endpoints()
+                    .unwrap_or((LineSpec::Current(0), LineSpec::Current(0)));
+                Ok(vec![command("delete-lines")
+                    .arg(start.to_string().as_str())
+                    .arg(end.to_string().as_str())
+                    .at_focused()])
+            }
+            Some("edit") => match self.arg {
+                Some(ref filename) => Ok(vec![command("open")
+                    .arg(filename.as_str())
+                    .arg(self.bang)
+                    .at_focused()]),
+                None => Err(error!("Argument required")),
+            },
+            Some(name @ ("earlier" | "later")) => {
+                // The amount may be a bare count (`:earlier 3`) or a count with a time unit
+                // (`:earlier 10s`), which parses as a count followed by an argument.
+                let amount = format!(
+                    "{}{}",
+                    self.count
+                        .map(|count| count.to_string())
+                        .unwrap_or_default(),
+                    self.arg.as_deref().unwrap_or("")
+                );
+                Ok(vec![command(name).arg(amount.as_str()).at_focused()])
+            }
+            Some("badd") => match self.arg {
+                Some(ref filename) => Ok(vec![command("add-buffer")
+                    .arg(filename.as_str())
+                    .at_focused()]),
+                None => Err(error!("Argument required")),
+            },
+            Some("bdelete") => Ok(vec![self
+                .with_count(command("delete-buffer").arg(self.bang))
+                .at_focused()]),
+            Some("bnext") => Ok(vec![self.with_count(command("buffer-next")).at_focused()]),
+            Some("bprevious") => Ok(vec![self.with_count(command("buffer-prior")).at_focused()]),
+            Some("buffer") => match self.arg {
+                Some(ref name) => Ok(vec![command("buffer").arg(name.as_str()).at_focused()]),
+                None => Ok(vec![self.with_count(command("buffer")).at_focused()]),
+            },
+            Some("buffers" | "ls") => Ok(vec![command("list-buffers").at_focused()]),
+            Some("close") => Ok(vec![command("close-window").at_view_map()]),
+            Some("only") => Ok(vec![command("only-window").at_view_map()]),
+            // Quitting closes the window, and only leaves the editor from the last one.
+            Some("quit") => Ok(vec![command("quit-window").arg(self.bang).at_view_map()]),
+            Some(name @ ("split" | "vsplit")) => {
+                let side = if name == "split" { "above" } else { "left" };
+                let mut dks = vec![command("split-window").arg(side).at_view_map()];
+                if let Some(ref filename) = self.arg {
+                    dks.push(command("open").arg(filename.as_str()).at_focused());
+                }
+                Ok(dks)
+            }
+            Some("tabnew") => Ok(vec![
+                command("new-tab").at_view_map(),
+                match self.arg {
+                    Some(ref filename) => command("open").arg(filename.as_str()).at_focused(),
+                    None => command("new-buffer").at_focused(),
+                },
+            ]),
+            Some("set") => match self.arg {
+                Some(ref arg) => Ok(arg.split_whitespace().map(set_option).collect()),
+                None => Err(error!("Argument required")),
+            },
+            Some("tabclose") => Ok(vec![command("close-tab").at_view_map()]),
+            Some("tabonly") => Ok(vec![command("only-tab").at_view_map()]),
+            Some("tabnext") => Ok(vec![self
+                .with_count(command("focus-tab").arg("next"))
+                .at_view_map()]),
+            Some("tabprevious") => Ok(vec![self
+                .with_count(command("focus-tab").arg("prior"))
+                .at_view_map()]),
+            Some("write") => Ok(vec![self.write_command().at_focused()]),
+            Some("wq") | Some("xit") => Ok(vec![
+                self.write_command().at_focused(),
+                command("quit-window").arg(self.bang).at_view_map(),
+            ]),
+            Some(name) => Err(not_impl!("ex command '{}' has no translation", name)),
+        }
+    }
+    fn write_command(&self) -> crate::command::CommandBuilder {
+        match self.arg {
+            Some(ref filename) => command("save").arg(filename.as_str()),
+            None => command("save"),
+        }
+    }
+    /// Passes a trailing count (as in `:b 2` or `:bnext 3`) on the way counts typed in normal
+    /// mode are.
+    fn with_count(
+        &self,
+        builder: crate::command::CommandBuilder,
+    ) -> crate::command::CommandBuilder {
+        match self.count {
+            Some(count) => builder.arg(count as i64),
+            None => builder,
+        }
+    }
+    /// Applies a trailing count (as in `:d 3`) which extends the range from its last line.
+    fn count_adjusted_range(&self) -> ExRange {
+        match (self.count, self.range.endpoints()) {
+            (Some(count), Some((_, end))) => ExRange::Span(end, offset_line_spec(end, count)),
+            (Some(count), None) => ExRange::Span(
+                LineSpec::Current(0),
+                offset_line_spec(LineSpec::Current(0), count),
+            ),
+            (None, _) => self.range,
+        }
+    }
+}
+
+/// Translates one argument of `:set`: `name=value` to give an option a value, `name` to turn
+/// one on, or `noname` to turn it off. Values go as strings, since a trailing number would be
+/// taken for a count.
+fn set_option(arg: &str) -> DK {
+    let builder = match arg.split_once('=') {
+        Some((name, value)) => command("set-option").arg(name).arg(value),
+        None => match arg.strip_prefix("no") {
+            Some(name) => command("set-option").arg(name).arg(false),
+            None => command("set-option").arg(arg).arg(true),
+        },
+    };
+    builder.at_focused()
+}
+
+fn offset_line_spec(line_spec: LineSpec, count: usize) -> LineSpec {
+    let delta = isize::try_from(count.saturating_sub(1)).unwrap_or(isize::MAX);
+    match line_spec {
+        LineSpec::Number(n, offset) => LineSpec::Number(n, offset.saturating_add(delta)),
+        LineSpec::Current(offset) => LineSpec::Current(offset.saturating_add(delta)),
+        LineSpec::Last(offset) => LineSpec::Last(offset.saturating_add(delta)),
+    }
+}
+
+impl FromStr for ExCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parser = Parser::new(s);
+        parser.command()
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    chars: Vec<char>,
+    index: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            text,
+            chars: text.chars().collect(),
+            index: 0,
+        }
+    }
+    fn is_done(&self) -> bool {
+        self.index >= self.chars.len()
+    }
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(ch) if ch.is_whitespace()) {
+            self.index += 1;
+        }
+    }
+    fn eat(&mut self, ch: char) -> bool {
+        if self.peek() == Some(ch) {
+            self.index += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn number(&mut self) -> Result<Option<usize>> {
+        let start = self.index;
+        while matches!(self.peek(), Some(ch) if ch.is_ascii_digit()) {
+            self.index += 1;
+        }
+        if start == self.index {
+            return Ok(None);
+        }
+        self.chars[start..self.index]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .map(Some)
+            .map_err(|_| error!("E16: Invalid range"))
+    }
+    fn offset(&mut self) -> Result<isize> {
+        let mut offset = 0;
+        loop {
+            let sign = if self.eat('+') {
+                1
+            } else if self.eat('-') {
+                -1
+            } else {
+                return Ok(offset);
+            };
+            let n = isize::try_from(self.number()?.unwrap_or(1)).ok();
+            offset = n
+                .and_then(|n| offset.checked_add(sign * n))
+                .ok_or_else(|| error!("E16: Invalid range"))?;
+        }
+    }
+    fn line_spec(&mut self) -> Result<Option<LineSpec>> {
+        let line_spec = if let Some(n) = self.number()? {
+            LineSpec::Number(n, self.offset()?)
+        } else if self.eat('.') {
+            LineSpec::Current(self.offset()?)
+        } else if self.eat('$') {
+            LineSpec::Last(self.offset()?)
+        } else if matches!(self.peek(), Some('+' | '-')) {
+            LineSpec::Current(self.offset()?)
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(line_spec))
+    }
+    fn range(&mut self) -> Result<ExRange> {
+        if self.eat('%') {
+            return Ok(ExRange::whole());
+        }
+        let start = self.line_spec()?;
+        if self.eat(',') {
+            let start = start.unwrap_or(LineSpec::Current(0));
+            match self.line_spec()? {
+                Some(end) => Ok(ExRange::Span(start, end)),
+                None => Ok(ExRange::Span(start, LineSpec::Current(0))),
+            }
+        } else {
+            Ok(start.map_or(ExRange::None, ExRange::Single))
+        }
+    }
+    fn name(&mut self) -> Result<Option<&'static str>> {
+        let start = self.index;
+        while matches!(self.peek(), Some(ch) if ch.is_ascii_alphabetic()) {
+            self.index += 1;
+        }
+        if start == self.index {
+            return Ok(None);
+        }
+        let typed: String = self.chars[start..self.index].iter().collect();
+        EX_COMMANDS
+            .iter()
+            .find(|(name, min_len)| typed.len() >= *min_len && name.starts_with(typed.as_str()))
+            .map(|(name, _)| Some(*name))
+            .ok_or_else(|| error!("Not an editor command: {}", self.text.trim()))
+    }
+    fn command(&mut self) -> Result<ExCommand> {
+        self.skip_whitespace();
+        while self.eat(':') {
+            self.skip_whitespace();
+        }
+        let range = self.range()?;
+        self.skip_whitespace();
+        let name = self.name()?;
+        let bang = name.is_some() && self.eat('!');
+        self.skip_whitespace();
+        let count = if name.is_some() { self.number()? } else { None };
+        self.skip_whitespace();
+        let rest: String = self.chars[self.index..].iter().collect();
+        let arg = if rest.is_empty() { None } else { Some(rest) };
+        if name.is_none() && arg.is_some() {
+            return Err(error!("Trailing characters: {}", self.text.trim()));
+        }
+        if arg.is_some()
+            && !matches!(
+                name,
+                Some(
+                    "badd"
+                        | "buffer"
+                        | "earlier"
+                        | "edit"
+                        | "later"
+                        | "set"
+                        | "split"
+                        | "tabnew"
+                        | "vsplit"
+                        | "write"
+                        | "wq"
+                        | "xit"
+                )
+            )
+        {
+            return Err(error!("Trailing characters: {}", self.text.trim()));
+        }
+        Ok(ExCommand {
+            range,
+            name,
+            bang,
+            count,
+            arg,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> ExCommand {
+        ExCommand::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn parse_abbreviations() {
+        assert_eq!(parse("q").name, Some("quit"));
+        assert_eq!(parse("qui").name, Some("quit"));
+        assert_eq!(parse("quit").name, Some("quit"));
+        assert_eq!(parse("w").name, Some("write"));
+        assert_eq!(parse("wq").name, Some("wq"));
+        assert_eq!(parse("x").name, Some("xit"));
+        assert_eq!(parse("sp").name, Some("split"));
+        assert_eq!(parse("vs").name, Some("vsplit"));
+        assert_eq!(parse("clo").name, Some("close"));
+        assert_eq!(parse("on").name, Some("only"));
+        assert_eq!(parse("tabc").name, Some("tabclose"));
+        assert_eq!(parse("tabn").name, Some("tabnext"));
+        assert_eq!(parse("tabnew").name, Some("tabnew"));
+        assert_eq!(parse("tabo").name, Some("tabonly"));
+        assert_eq!(parse("tabp 2").count, Some(2));
+        assert!(ExCommand::from_str("tab").is_err());
+        assert!(ExCommand::from_str("quitx").is_err());
+        assert!(ExCommand::from_str("frobnicate").is_err());
+    }
+
+    #[test]
+    fn parse_bang_and_args() {
+        let cmd = parse("q!");
+        assert_eq!(cmd.name, Some("quit"));
+        assert!(cmd.bang);
+        let cmd = parse("w other.txt");
+        assert_eq!(cmd.name, Some("write"));
+        assert_eq!(cmd.arg.as_deref(), Some("other.txt"));
+        let cmd = parse("e src/main.rs");
+        assert_eq!(cmd.name, Some("edit"));
+        assert_eq!(cmd.arg.as_deref(), Some("src/main.rs"));
+        assert!(ExCommand::from_str("q now").is_err());
+    }
+
+    #[test]
+    fn parse_buffer_commands() {
+        assert_eq!(parse("b").name, Some("buffer"));
+        assert_eq!(parse("b 2").count, Some(2));
+        assert_eq!(parse("b main").arg.as_deref(), Some("main"));
+        assert_eq!(parse("buffers").name, Some("buffers"));
+        assert_eq!(parse("bn").name, Some("bnext"));
+        assert_eq!(parse("bp").name, Some("bprevious"));
+        assert_eq!(parse("ls").name, Some("ls"));
+        let cmd = parse("bd! 3");
+        assert_eq!(cmd.name, Some("bdelete"));
+        assert!(cmd.bang);
+        assert_eq!(cmd.count, Some(3));
+        assert!(ExCommand::from_str("bd foo").is_err());
+    }
+
+    #[test]
+    fn translates_set() -> Result<()> {
+        let dks = parse("se ts=8 noet list").to_dks()?;
+        let expected = vec![
+            command("set-option").arg("ts").arg("8").at_focused(),
+            command("set-option").arg("et").arg(false).at_focused(),
+            command("set-option").arg("list").arg(true).at_focused(),
+        ];
+        assert_eq!(format!("{:?}", dks), format!("{:?}", expected));
+        assert!(parse("set").to_dks().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_ranges() {
+        let cmd = parse("10,20d");
+        assert_eq!(cmd.name, Some("delete"));
+        assert_eq!(
+            cmd.range,
+            ExRange::Span(LineSpec::Number(10, 0), LineSpec::Number(20, 0))
+        );
+        assert_eq!(parse("%d").range, ExRange::whole());
+        assert_eq!(
+            parse(".,$-1d").range,
+            ExRange::Span(LineSpec::Current(0), LineSpec::Last(-1))
+        );
+        let cmd = parse("42");
+        assert_eq!(cmd.name, None);
+        assert_eq!(cmd.range, ExRange::Single(LineSpec::Number(42, 0)));
+    }
+
+    #[test]
+    fn parse_counts() {
+        let cmd = parse("5d 3");
+        assert_eq!(cmd.count, Some(3));
+        // Too big to be a line number or a count.
+        assert!(ExCommand::from_str("99999999999999999999d").is_err());
+        assert!(ExCommand::from_str("d 99999999999999999999").is_err());
+        assert!(ExCommand::from_str(".+9223372036854775807+1d").is_err());
+        assert_eq!(
+            cmd.count_adjusted_range(),
+            ExRange::Span(LineSpec::Number(5, 0), LineSpec::Number(5, 2))
+        );
+    }
+
+    #[test]
+    fn resolve_line_specs() {
+        assert_eq!(LineSpec::Number(1, 0).resolve(5, 10), 0);
+        assert_eq!(LineSpec::Current(2).resolve(5, 10), 7);
+        assert_eq!(LineSpec::Last(0).resolve(5, 10), 9);
+        assert_eq!(LineSpec::Number(100, 0).resolve(5, 10), 9);
+        assert_eq!(LineSpec::Number(usize::MAX, 0).resolve(5, 10), 9);
+        assert_eq!(LineSpec::Current(isize::MAX).resolve(5, 10), 9);
+        let line_spec = LineSpec::Last(-3);
+        assert_eq!(
+            LineSpec::from_str(&line_spec.to_string()).unwrap(),
+            line_spec
+        );
+    }
+}
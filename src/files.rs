@@ -1,7 +1,14 @@
+use crate::error::{Error, Result};
 use crate::utils::Errno;
 use libc::c_int;
 use std::ffi::CString;
 use std::fmt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Tells apart the temp files this process writes, along with its pid.
+static TEMP_FILE_COUNT: AtomicUsize = AtomicUsize::new(0);
 
 pub struct FileDescriptor {
     fd: c_int,
@@ -9,8 +16,8 @@ pub struct FileDescriptor {
 
 #[allow(dead_code)]
 impl FileDescriptor {
-    pub fn open(filename: &str, flags: c_int, mode: c_int) -> Result<Self, Errno> {
-        println!("opening {}...", filename);
+    pub fn open(filename: &str, flags: c_int, mode: c_int) -> std::result::Result<Self, Errno> {
+        log::trace!("opening {}...", filename);
         match CString::new(filename) {
             Ok(cstr_filename) => {
                 let ret = unsafe { libc::open(cstr_filename.as_ptr(), flags, mode) };
@@ -41,6 +48,34 @@ impl FileDescriptor {
     pub unsafe fn read(&self, buf: *mut libc::c_void, count: libc::size_t) -> libc::ssize_t {
         libc::read(self.fd, buf, count)
     }
+    pub fn write_all(&self, mut bytes: &[u8]) -> std::result::Result<(), Errno> {
+        while !bytes.is_empty() {
+            let ret = unsafe { self.write(bytes.as_ptr() as *const libc::c_void, bytes.len()) };
+            if ret == -1 {
+                let errno = Errno::latest();
+                if errno.is_eintr() {
+                    continue;
+                }
+                return Err(errno);
+            }
+            bytes = &bytes[ret as usize..];
+        }
+        Ok(())
+    }
+    pub fn fchmod(&self, mode: libc::mode_t) -> std::result::Result<(), Errno> {
+        if unsafe { libc::fchmod(self.fd, mode) } == -1 {
+            Err(Errno::latest())
+        } else {
+            Ok(())
+        }
+    }
+    pub fn fsync(&self) -> std::result::Result<(), Errno> {
+        if unsafe { libc::fsync(self.fd) } == -1 {
+            Err(Errno::latest())
+        } else {
+            Ok(())
+        }
+    }
 }
 
 impl Drop for FileDescriptor {
@@ -55,7 +90,6 @@ impl fmt::Display for FileDescriptor {
     }
 }
 
-#[allow(dead_code)]
 fn rename(old: String, new: String) -> c_int {
     match CString::new(old) {
         Ok(old) => match CString::new(new) {
@@ -69,3 +103,148 @@ fn rename(old: String, new: String) -> c_int {
         }
     }
 }
+
+fn unlink(filename: &str) -> c_int {
+    match CString::new(filename) {
+        Ok(filename) => unsafe { libc::unlink(filename.as_ptr()) },
+        Err(error) => {
+            panic!("error: {:?}", error);
+        }
+    }
+}
+
+/// Names a hidden file beside `filename`, as in `dir/.name.{suffix}`.
+pub fn hidden_sibling(filename: &str, suffix: &str) -> Result<String> {
+    let path = Path::new(filename);
+    match path.file_name() {
+        Some(basename) => Ok(path
+            .with_file_name(format!(".{}.{}", basename.to_string_lossy(), suffix))
+            .to_string_lossy()
+            .to_string()),
+        None => Err(Error::new(format!("'{}' is not a file name", filename))),
+    }
+}
+
+/// Creates a new temp file beside `filename`, with a name no other file has, so that two
+/// editors saving the same file don't write over each other's temp files. It's never a symlink
+/// that was already there. A file replacing one with `mode` gets exactly that mode, whatever
+/// the umask; a new one gets the umask's say.
+fn create_temp_file(filename: &str, mode: Option<c_int>) -> Result<(String, FileDescriptor)> {
+    loop {
+        let suffix = format!(
+            "wim-tmp-{}-{}",
+            std::process::id(),
+            TEMP_FILE_COUNT.fetch_add(1, Ordering::Relaxed)
+        );
+        let temp_filename = hidden_sibling(filename, &suffix)?;
+        match FileDescriptor::open(
+            &temp_filename,
+            libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+            mode.unwrap_or(0o644),
+        ) {
+            Ok(fd) => {
+                if let Some(mode) = mode {
+                    if let Err(errno) = fd.fchmod(mode as libc::mode_t) {
+                        drop(fd);
+                        unlink(&temp_filename);
+                        return Err(Error::new_io_error(format!(
+                            "unable to set the mode of '{}': {}",
+                            temp_filename, errno
+                        )));
+                    }
+                }
+                return Ok((temp_filename, fd));
+            }
+            // Left behind by an earlier process with our pid.
+            Err(errno) if errno.is_eexist() => continue,
+            Err(errno) => {
+                return Err(Error::new_io_error(format!(
+                    "unable to open '{}': {}",
+                    temp_filename, errno
+                )))
+            }
+        }
+    }
+}
+
+/// Syncs the directory holding `path`, so that a rename into it survives a crash.
+pub fn sync_parent_dir(path: &Path) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_string_lossy().to_string(),
+        _ => ".".to_string(),
+    };
+    FileDescriptor::open(
+        &dir,
+        libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+        0,
+    )
+    .and_then(|fd| fd.fsync())
+    .map_err(|errno| Error::new_io_error(format!("unable to sync '{}': {}", dir, errno)))
+}
+
+/// Writes `bytes` to a temp file beside `filename`, syncs it to disk and then renames it over
+/// `filename`, so that readers never observe a partially written file.
+pub fn write_atomically(filename: &str, bytes: &[u8]) -> Result<()> {
+    replace_file(filename, bytes)?;
+    sync_parent_dir(Path::new(filename))
+}
+
+/// Does what `write_atomically` does short of syncing the directory, for callers that want to
+/// know whether the file itself was replaced.
+pub fn replace_file(filename: &str, bytes: &[u8]) -> Result<()> {
+    let path = Path::new(filename);
+    // Keep the permissions of the file we are replacing.
+    let mode = std::fs::metadata(path)
+        .ok()
+        .map(|metadata| (metadata.permissions().mode() & 0o7777) as c_int);
+    let (temp_filename, fd) = create_temp_file(filename, mode)?;
+    if let Err(errno) = fd.write_all(bytes).and_then(|()| fd.fsync()) {
+        drop(fd);
+        unlink(&temp_filename);
+        return Err(Error::new_io_error(format!(
+            "unable to write '{}': {}",
+            temp_filename, errno
+        )));
+    }
+    drop(fd);
+    if rename(temp_filename.clone(), filename.to_string()) == -1 {
+        let errno = Errno::latest();
+        unlink(&temp_filename);
+        return Err(Error::new_io_error(format!(
+            "unable to rename '{}' to '{}': {}",
+            temp_filename, filename, errno
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    #[test]
+    fn write_atomically_replaces_contents() -> Result<()> {
+        let dir = TempDir::new("files")?;
+        let filename = dir.join("atomic.txt").to_string_lossy().to_string();
+        std::fs::write(&filename, "old contents\n")?;
+        write_atomically(&filename, b"new contents\n")?;
+        assert_eq!(std::fs::read_to_string(&filename)?, "new contents\n");
+        // The temp file should have been renamed away.
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn write_atomically_keeps_the_mode() -> Result<()> {
+        let dir = TempDir::new("mode")?;
+        let filename = dir.join("mode.txt").to_string_lossy().to_string();
+        std::fs::write(&filename, "old contents\n")?;
+        // Wider than the usual umask lets new files be.
+        std::fs::set_permissions(&filename, std::fs::Permissions::from_mode(0o666))?;
+        write_atomically(&filename, b"new contents\n")?;
+        let mode = std::fs::metadata(&filename)?.permissions().mode() & 0o7777;
+        assert_eq!(mode, 0o666);
+        Ok(())
+    }
+}
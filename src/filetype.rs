@@ -0,0 +1,30 @@
+use std::path::Path;
+
+/// File types by the extensions that mark them.
+static FILE_TYPES: &[(&str, &[&str])] = &[
+    ("c", &["c", "h"]),
+    ("cpp", &["cc", "cpp", "cxx", "hh", "hpp"]),
+    ("css", &["css"]),
+    ("diff", &["diff", "patch"]),
+    ("go", &["go"]),
+    ("html", &["htm", "html"]),
+    ("javascript", &["js", "mjs"]),
+    ("json", &["json"]),
+    ("markdown", &["md", "markdown"]),
+    ("python", &["py"]),
+    ("rust", &["rs"]),
+    ("sh", &["bash", "sh"]),
+    ("text", &["txt"]),
+    ("toml", &["toml"]),
+    ("typescript", &["ts"]),
+    ("yaml", &["yaml", "yml"]),
+];
+
+/// The type of the file named `filename`, going by its extension.
+pub fn detect(filename: &str) -> Option<&'static str> {
+    let extension = Path::new(filename).extension()?.to_str()?;
+    FILE_TYPES
+        .iter()
+        .find(|(_, extensions)| extensions.contains(&extension))
+        .map(|(file_type, _)| *file_type)
+}
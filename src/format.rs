@@ -1,9 +1,74 @@
-use crate::color::{BgColor, FgColor};
+use crate::color::{BgColor, FgColor, UnderlineColor};
+use crate::error::{error, Error, Result};
+use serde_derive::Deserialize;
+
+/// A set of text attributes, written in themes as a list of their names.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize)]
+#[serde(try_from = "Vec<String>")]
+pub struct Attrs(u8);
+
+#[allow(dead_code)]
+impl Attrs {
+    pub const NONE: Self = Self(0);
+    pub const BOLD: Self = Self(1);
+    pub const ITALIC: Self = Self(1 << 1);
+    pub const UNDERLINE: Self = Self(1 << 2);
+    /// A wavy underline, as for spelling mistakes. Terminals that can't draw one draw a straight
+    /// underline.
+    pub const UNDERCURL: Self = Self(1 << 3);
+    /// Swaps the foreground and background colors.
+    pub const REVERSE: Self = Self(1 << 4);
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl std::ops::BitOr for Attrs {
+    type Output = Self;
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+static ATTR_NAMES: &[(&str, Attrs)] = &[
+    ("bold", Attrs::BOLD),
+    ("italic", Attrs::ITALIC),
+    ("underline", Attrs::UNDERLINE),
+    ("undercurl", Attrs::UNDERCURL),
+    ("reverse", Attrs::REVERSE),
+];
+
+impl TryFrom<Vec<String>> for Attrs {
+    type Error = Error;
+
+    fn try_from(names: Vec<String>) -> Result<Self> {
+        let mut attrs = Self::NONE;
+        for name in names {
+            match ATTR_NAMES.iter().find(|(other, _)| *other == name) {
+                Some((_, attr)) => attrs = attrs | *attr,
+                None => {
+                    return Err(error!(
+                        "attributes are {{bold,italic,underline,undercurl,reverse}}, not '{}'",
+                        name
+                    ))
+                }
+            }
+        }
+        Ok(attrs)
+    }
+}
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub struct Format {
     pub fg: FgColor,
     pub bg: BgColor,
+    pub attrs: Attrs,
+    /// The color of any underline or undercurl, if not the text's.
+    pub underline: UnderlineColor,
 }
 
 impl Format {
@@ -11,12 +76,8 @@ impl Format {
         Self {
             fg: FgColor::None,
             bg: BgColor::None,
-        }
-    }
-    pub const fn selected() -> Self {
-        Self {
-            fg: FgColor::Black,
-            bg: BgColor::White,
+            attrs: Attrs::NONE,
+            underline: UnderlineColor::None,
         }
     }
 }
@@ -1,53 +1,221 @@
 use crate::buf::Buf;
+use crate::color::ColorDepth;
 use crate::error::{ErrorContext, Result};
-use crate::format::Format;
+use crate::format::{Attrs, Format};
 use std::fmt::Write;
 
+/// The most zero-width marks, such as accents, that a cell keeps on its char. Any more are
+/// dropped.
+const MAX_MARKS: usize = 4;
+
+/// What `ch` is in the cell to the right of a wide glyph, which the wide glyph covers.
+const CONTINUATION: char = '\0';
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub struct Glyph {
     pub ch: char,
+    /// Marks that combine with `ch`, padded out with NULs.
+    pub marks: [char; MAX_MARKS],
     pub format: Format,
 }
 
 impl Glyph {
-    #[allow(dead_code)]
-    pub fn with(&self, ch: char) -> Self {
+    pub fn new(ch: char, format: Format) -> Self {
         Self {
             ch,
-            format: self.format,
+            marks: ['\0'; MAX_MARKS],
+            format,
         }
     }
+    /// The right half of a wide glyph.
+    pub fn continuation(format: Format) -> Self {
+        Self::new(CONTINUATION, format)
+    }
+    pub fn is_continuation(&self) -> bool {
+        self.ch == CONTINUATION
+    }
+    #[allow(dead_code)]
+    pub fn with(&self, ch: char) -> Self {
+        Self::new(ch, self.format)
+    }
     #[allow(dead_code)]
     pub fn from_char(ch: char) -> Self {
-        Self {
-            ch,
-            format: Format::none(),
+        Self::new(ch, Format::none())
+    }
+    pub fn add_mark(&mut self, mark: char) {
+        if let Some(slot) = self.marks.iter_mut().find(|slot| **slot == '\0') {
+            *slot = mark;
         }
     }
+    /// Clears the glyph's char and marks, keeping its format.
+    pub fn blank(&mut self) {
+        *self = Self::new(' ', self.format);
+    }
+    /// Writes the glyph to `buf`, preceded by whatever SGR sequence it takes to get from the
+    /// `last` format written to this one on a terminal with `depth` colors. Returns the format
+    /// the terminal is left in. Continuations write nothing, since the terminal fills them in
+    /// when it draws the wide glyph before.
     pub fn encode_utf8_to_buf(
         &self,
         buf: &mut Buf,
-        Format {
-            fg: last_fg,
-            bg: last_bg,
-        }: Format,
+        last: Format,
+        depth: ColorDepth,
     ) -> Result<Format> {
-        if last_fg != self.format.fg {
-            write!(buf, "{}", self.format.fg).context("write fg")?
+        if self.is_continuation() {
+            return Ok(last);
         }
-        if last_bg != self.format.bg {
-            write!(buf, "{}", self.format.bg).context("write bg")?
+        if last != self.format {
+            // Turning a few things off can take more than starting over from a reset.
+            let changes = sgr_params(last, self.format, depth);
+            let mut reset = vec!["0".to_string()];
+            reset.extend(sgr_params(Format::none(), self.format, depth));
+            let params = if reset.join(";").len() < changes.join(";").len() {
+                reset
+            } else {
+                changes
+            };
+            write!(buf, "\x1b[{}m", params.join(";")).context("write sgr")?
         }
         buf.push_char(self.ch);
+        for &mark in self.marks.iter().take_while(|&&mark| mark != '\0') {
+            buf.push_char(mark);
+        }
         Ok(self.format)
     }
 }
 
+/// Attributes with their own SGR codes to turn them on and off.
+static ATTR_CODES: &[(Attrs, &str, &str)] = &[
+    (Attrs::BOLD, "1", "22"),
+    (Attrs::ITALIC, "3", "23"),
+    (Attrs::REVERSE, "7", "27"),
+];
+
+/// The SGR code for the style of underline in `attrs`, which shares one slot in the terminal.
+fn underline_code(attrs: Attrs) -> &'static str {
+    if attrs.contains(Attrs::UNDERCURL) {
+        "4:3"
+    } else if attrs.contains(Attrs::UNDERLINE) {
+        "4"
+    } else {
+        "24"
+    }
+}
+
+/// The SGR parameters that change the terminal's rendition from `from` to `to`.
+fn sgr_params(from: Format, to: Format, depth: ColorDepth) -> Vec<String> {
+    let mut params = Vec::new();
+    for &(attr, on, off) in ATTR_CODES {
+        if from.attrs.contains(attr) != to.attrs.contains(attr) {
+            params.push(if to.attrs.contains(attr) { on } else { off }.to_string());
+        }
+    }
+    if underline_code(from.attrs) != underline_code(to.attrs) {
+        params.push(underline_code(to.attrs).to_string());
+    }
+    if from.fg != to.fg {
+        params.push(to.fg.sgr(depth));
+    }
+    if from.bg != to.bg {
+        params.push(to.bg.sgr(depth));
+    }
+    if from.underline != to.underline {
+        params.push(to.underline.sgr(depth));
+    }
+    params
+}
+
 impl From<char> for Glyph {
     fn from(ch: char) -> Self {
-        Self {
-            ch,
-            format: Format::none(),
+        Self::from_char(ch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{BgColor, FgColor, UnderlineColor};
+
+    fn encode(last: Format, format: Format) -> String {
+        let mut buf = Buf::default();
+        Glyph::new('x', format)
+            .encode_utf8_to_buf(&mut buf, last, ColorDepth::TrueColor)
+            .unwrap();
+        String::from_utf8(buf.as_bytes().to_vec()).unwrap()
+    }
+
+    fn attrs(attrs: Attrs) -> Format {
+        Format {
+            attrs,
+            ..Format::none()
         }
     }
+
+    #[test]
+    fn emits_attribute_changes() {
+        let bold = attrs(Attrs::BOLD);
+        let bold_italic = attrs(Attrs::BOLD | Attrs::ITALIC);
+        assert_eq!(encode(bold, bold), "x");
+        assert_eq!(encode(Format::none(), bold), "\x1b[1mx");
+        assert_eq!(encode(bold, bold_italic), "\x1b[3mx");
+        assert_eq!(encode(bold_italic, attrs(Attrs::ITALIC)), "\x1b[22mx");
+        assert_eq!(
+            encode(Format::none(), attrs(Attrs::REVERSE | Attrs::UNDERLINE)),
+            "\x1b[7;4mx"
+        );
+        assert_eq!(
+            encode(attrs(Attrs::UNDERLINE), attrs(Attrs::UNDERCURL)),
+            "\x1b[4:3mx"
+        );
+        assert_eq!(
+            encode(attrs(Attrs::UNDERCURL), attrs(Attrs::UNDERLINE)),
+            "\x1b[4mx"
+        );
+    }
+
+    #[test]
+    fn resets_when_shorter() {
+        let red = Format {
+            fg: FgColor::Red,
+            ..Format::none()
+        };
+        let red_bold = Format {
+            attrs: Attrs::BOLD | Attrs::ITALIC,
+            ..red
+        };
+        assert_eq!(encode(red_bold, Format::none()), "\x1b[0mx");
+        assert_eq!(encode(red_bold, red), "\x1b[0;31mx");
+        assert_eq!(
+            encode(
+                red_bold,
+                Format {
+                    attrs: Attrs::ITALIC,
+                    bg: BgColor::Blue,
+                    ..red
+                }
+            ),
+            "\x1b[22;44mx"
+        );
+    }
+
+    #[test]
+    fn emits_underline_colors() {
+        let curl = Format {
+            attrs: Attrs::UNDERCURL,
+            underline: UnderlineColor::Rgb { r: 1, g: 2, b: 3 },
+            ..Format::none()
+        };
+        assert_eq!(encode(Format::none(), curl), "\x1b[4:3;58;2;1;2;3mx");
+        assert_eq!(
+            encode(
+                curl,
+                Format {
+                    underline: UnderlineColor::Red,
+                    ..curl
+                }
+            ),
+            "\x1b[58;5;1mx"
+        );
+        assert_eq!(encode(curl, attrs(Attrs::UNDERCURL)), "\x1b[59mx");
+    }
 }
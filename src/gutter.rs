@@ -0,0 +1,205 @@
+use crate::error::Result;
+use crate::prelude::*;
+use crate::theme::{Group, Theme};
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+
+/// How the gutter numbers lines, as set by `line_numbers` under `[gutter]` in settings.toml.
+#[derive(Deserialize, Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LineNumbers {
+    #[default]
+    None,
+    /// Each line's own number.
+    Absolute,
+    /// How far each line is from the cursor.
+    Relative,
+    /// The cursor line's own number, and how far every other line is from it.
+    Hybrid,
+}
+
+impl FromStr for LineNumbers {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Self::None),
+            "absolute" => Ok(Self::Absolute),
+            "relative" => Ok(Self::Relative),
+            "hybrid" => Ok(Self::Hybrid),
+            _ => Err(error!(
+                "line numbers are one of {{none,absolute,relative,hybrid}}, not '{}'",
+                s
+            )),
+        }
+    }
+}
+
+/// A mark shown beside a line, such as a diagnostic or a version control change.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Sign {
+    pub text: String,
+    pub format: Format,
+}
+
+/// A column of the gutter registered by something that puts signs beside lines.
+#[derive(Clone, Debug)]
+struct SignColumn {
+    name: String,
+    width: Coord,
+    /// Signs by the (0-based) line they're beside.
+    signs: BTreeMap<Coord, Sign>,
+}
+
+/// The sign columns registered for a doc, drawn left to right in the order they were
+/// registered.
+#[derive(Clone, Debug, Default)]
+pub struct Signs {
+    columns: Vec<SignColumn>,
+}
+
+impl Signs {
+    /// Adds a column `width` cells wide for signs from `name`, e.g. "diagnostics". Registering
+    /// a column again just changes its width.
+    pub fn register(&mut self, name: &str, width: Coord) {
+        match self.columns.iter_mut().find(|column| column.name == name) {
+            Some(column) => column.width = width,
+            None => self.columns.push(SignColumn {
+                name: name.to_string(),
+                width,
+                signs: Default::default(),
+            }),
+        }
+    }
+    pub fn place(&mut self, column: &str, line: Coord, sign: Sign) -> Result<()> {
+        self.column_mut(column)?.signs.insert(line, sign);
+        Ok(())
+    }
+    /// Takes the sign beside `line` out of `column`, or with no line, all of its signs.
+    pub fn unplace(&mut self, column: &str, line: Option<Coord>) -> Result<()> {
+        let column = self.column_mut(column)?;
+        match line {
+            Some(line) => {
+                column.signs.remove(&line);
+            }
+            None => column.signs.clear(),
+        }
+        Ok(())
+    }
+    fn width(&self) -> Coord {
+        self.columns.iter().map(|column| column.width).sum()
+    }
+    fn column_mut(&mut self, name: &str) -> Result<&mut SignColumn> {
+        match self.columns.iter_mut().find(|column| column.name == name) {
+            Some(column) => Ok(column),
+            None => Err(error!("no sign column is registered as '{}'", name)),
+        }
+    }
+}
+
+/// The columns down the left of a window: any sign columns, then the line numbers.
+#[derive(Clone, Debug)]
+pub struct Gutter {
+    line_numbers: LineNumbers,
+}
+
+impl Gutter {
+    pub fn new(line_numbers: LineNumbers) -> Self {
+        Self { line_numbers }
+    }
+    pub fn set_line_numbers(&mut self, line_numbers: LineNumbers) {
+        self.line_numbers = line_numbers;
+    }
+    /// How many columns the gutter takes for a doc with `line_count` lines. Line numbers get
+    /// room for at least three digits, like vim, and a space before the text.
+    pub fn width(&self, signs: &Signs, line_count: usize) -> Coord {
+        let numbers = match self.line_numbers {
+            LineNumbers::None => 0,
+            _ => line_count.to_string().len().max(3) + 1,
+        };
+        signs.width() + numbers
+    }
+    /// The number shown beside `line` when the cursor is on `cursor_line`, padded to `width`.
+    fn line_number(&self, line: Coord, cursor_line: Coord, width: Coord) -> String {
+        let distance = line.abs_diff(cursor_line);
+        match self.line_numbers {
+            LineNumbers::None => String::new(),
+            LineNumbers::Absolute => format!("{:>width$} ", line + 1, width = width - 1),
+            LineNumbers::Relative => format!("{:>width$} ", distance, width = width - 1),
+            // As in vim, the cursor line's number sits at the left.
+            LineNumbers::Hybrid if distance == 0 => {
+                format!("{:<width$} ", line + 1, width = width - 1)
+            }
+            LineNumbers::Hybrid => format!("{:>width$} ", distance, width = width - 1),
+        }
+    }
+    /// Draws the gutter beside `lines`, which fill the rows of `bmp` from the top.
+    pub fn display(
+        &self,
+        bmp: &mut BitmapView,
+        signs: &Signs,
+        lines: Range<Coord>,
+        cursor_line: Coord,
+        line_count: usize,
+        colors: &Theme,
+    ) {
+        let numbers_width = self.width(signs, line_count) - signs.width();
+        for (y, line) in lines.enumerate() {
+            let mut pos = Pos { x: 0, y };
+            for column in signs.columns.iter() {
+                let (text, format) = match column.signs.get(&line) {
+                    Some(sign) => (sign.text.as_str(), sign.format),
+                    None => ("", Format::none()),
+                };
+                let text = format!("{:<width$}", text, width = column.width);
+                bmp.append_chars_at(pos, text.chars().take(column.width), format);
+                pos.x += column.width;
+            }
+            if numbers_width > 0 {
+                let format = colors.format(if line == cursor_line {
+                    Group::CursorLineNr
+                } else {
+                    Group::LineNr
+                });
+                let number = self.line_number(line, cursor_line, numbers_width);
+                bmp.append_chars_at(pos, number.chars(), format);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_lines() {
+        let numbers = |line_numbers, line| Gutter::new(line_numbers).line_number(line, 5, 4);
+        assert_eq!(numbers(LineNumbers::Absolute, 2), "  3 ");
+        assert_eq!(numbers(LineNumbers::Relative, 2), "  3 ");
+        assert_eq!(numbers(LineNumbers::Relative, 5), "  0 ");
+        assert_eq!(numbers(LineNumbers::Hybrid, 5), "6   ");
+        assert_eq!(numbers(LineNumbers::Hybrid, 7), "  2 ");
+    }
+
+    #[test]
+    fn sizes_to_line_count_and_signs() {
+        let mut signs = Signs::default();
+        let gutter = Gutter::new(LineNumbers::Absolute);
+        assert_eq!(gutter.width(&signs, 10), 4);
+        assert_eq!(gutter.width(&signs, 12345), 6);
+        signs.register("diagnostics", 2);
+        assert_eq!(gutter.width(&signs, 10), 6);
+        assert_eq!(Gutter::new(LineNumbers::None).width(&signs, 10), 2);
+        assert!(signs
+            .place(
+                "git",
+                0,
+                Sign {
+                    text: "+".to_string(),
+                    format: Format::none(),
+                }
+            )
+            .is_err());
+    }
+}
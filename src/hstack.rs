@@ -0,0 +1,66 @@
+use crate::bindings::Bindings;
+use crate::error::Result;
+use crate::plugin::PluginRef;
+use crate::prelude::*;
+use crate::stack::{Axis, Stack};
+use crate::status::Status;
+use crate::types::{Pos, Rect};
+use crate::view::{ViewContext, ViewKey};
+
+/// Lays out windows side by side, with a border between each.
+pub struct HStack {
+    view_key: ViewKey,
+    stack: Stack,
+}
+
+impl HStack {
+    pub fn new(view_key: ViewKey, view_keys: Vec<ViewKey>) -> Self {
+        Self {
+            view_key,
+            stack: Stack::new(Axis::Horizontal, view_keys),
+        }
+    }
+}
+
+impl ViewContext for HStack {
+    fn get_property(&self, property: &str) -> Option<Variant> {
+        panic!("not implemented: property: {}", property);
+    }
+}
+impl View for HStack {
+    fn get_doc_text(&self, _view_map: &ViewMap) -> Option<String> {
+        panic!("not sure what to do with hstack get_doc_text call...");
+    }
+    fn install_plugins(&mut self, _plugin: PluginRef) {}
+    fn layout(&mut self, _view_map: &ViewMap, size: Size) -> Vec<(ViewKey, Rect)> {
+        self.stack.layout(size)
+    }
+    fn get_view_key(&self) -> ViewKey {
+        self.view_key
+    }
+    fn display(&self, _view_map: &ViewMap, bmp: &mut BitmapView) {
+        for x in self.stack.gap_offsets() {
+            for y in 0..bmp.get_size().height {
+                bmp.set_glyph(Pos { x, y }, '│'.into());
+            }
+        }
+    }
+    fn get_cursor_pos(&self) -> Option<Pos> {
+        panic!("HStack should not be focused!");
+    }
+    fn get_stack(&self) -> Option<(Axis, Vec<ViewKey>)> {
+        Some((self.stack.axis(), self.stack.view_keys().to_vec()))
+    }
+}
+
+impl DispatchTarget for HStack {
+    fn get_key_bindings(&self) -> Bindings {
+        Default::default()
+    }
+    fn execute_command(&mut self, name: String, args: Vec<Variant>) -> Result<Status> {
+        self.stack.execute_command(&name, &args)
+    }
+    fn send_key(&mut self, key: Key) -> Result<Status> {
+        panic!("why is the hstack receiving send_keys? [key={:?}]", key);
+    }
+}
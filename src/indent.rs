@@ -0,0 +1,144 @@
+use crate::consts::TAB_STOP_SIZE;
+use crate::types::Coord;
+use serde::{de, Deserialize as _, Deserializer};
+use serde_derive::Deserialize;
+
+/// How a buffer's lines are indented, as vim's options of the same names. New buffers start with
+/// the `[indent]` section of settings.toml, and `:set` changes them for one buffer.
+#[derive(Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Indent {
+    /// How many columns apart tab stops are, at least one.
+    #[serde(deserialize_with = "deserialize_tabstop")]
+    pub tabstop: Coord,
+    /// How many columns `>>` and `<<` shift lines by, or zero to shift by `tabstop`.
+    pub shiftwidth: Coord,
+    /// Whether to indent with spaces rather than tabs.
+    pub expandtab: bool,
+}
+
+impl Default for Indent {
+    fn default() -> Self {
+        Self {
+            tabstop: TAB_STOP_SIZE,
+            shiftwidth: TAB_STOP_SIZE,
+            expandtab: false,
+        }
+    }
+}
+
+/// Reads `tabstop` from settings.toml, turning away a zero that would leave tabs with no width.
+fn deserialize_tabstop<'de, D>(deserializer: D) -> Result<Coord, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Coord::deserialize(deserializer)? {
+        0 => Err(de::Error::custom("tabstop must be at least 1")),
+        tabstop => Ok(tabstop),
+    }
+}
+
+impl Indent {
+    pub fn shift_width(&self) -> Coord {
+        if self.shiftwidth == 0 {
+            self.tabstop
+        } else {
+            self.shiftwidth
+        }
+    }
+    /// The whitespace that indents to column `width`: as many tabs as fit and then spaces, or
+    /// only spaces with `expandtab`.
+    pub fn whitespace(&self, width: Coord) -> String {
+        let tabs = if self.expandtab {
+            0
+        } else {
+            width / self.tabstop
+        };
+        "\t".repeat(tabs) + &" ".repeat(width - tabs * self.tabstop)
+    }
+    /// What pressing tab at column `col` inserts: a tab, or with `expandtab`, enough spaces to
+    /// reach the next tab stop.
+    pub fn tab(&self, col: Coord) -> String {
+        if self.expandtab {
+            " ".repeat(self.tabstop - col % self.tabstop)
+        } else {
+            "\t".to_string()
+        }
+    }
+    /// `line` with its indent made a shift width deeper, or shallower if `right` is false. Blank
+    /// lines stay as they are.
+    pub fn shifted(&self, line: &[char], right: bool) -> Vec<char> {
+        let leading = line
+            .iter()
+            .take_while(|&&ch| ch == ' ' || ch == '\t')
+            .count();
+        if leading == line.len() {
+            return line.to_vec();
+        }
+        let width = line[..leading].iter().fold(0, |col, &ch| match ch {
+            '\t' => col + self.tabstop - col % self.tabstop,
+            _ => col + 1,
+        });
+        let width = if right {
+            width + self.shift_width()
+        } else {
+            width.saturating_sub(self.shift_width())
+        };
+        self.whitespace(width)
+            .chars()
+            .chain(line[leading..].iter().copied())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shift(indent: Indent, line: &str, right: bool) -> String {
+        let line: Vec<char> = line.chars().collect();
+        indent.shifted(&line, right).into_iter().collect()
+    }
+
+    #[test]
+    fn shifts_by_shift_width() {
+        let tabs = Indent {
+            tabstop: 8,
+            shiftwidth: 4,
+            expandtab: false,
+        };
+        assert_eq!(shift(tabs, "x", true), "    x");
+        assert_eq!(shift(tabs, "    x", true), "\tx");
+        assert_eq!(shift(tabs, "\t  x", false), "      x");
+        assert_eq!(shift(tabs, "  x", false), "x");
+        assert_eq!(shift(tabs, "   ", true), "   ");
+        let spaces = Indent {
+            expandtab: true,
+            ..tabs
+        };
+        assert_eq!(shift(spaces, "\tx", true), "            x");
+        let by_tabstop = Indent {
+            shiftwidth: 0,
+            ..tabs
+        };
+        assert_eq!(shift(by_tabstop, "x", true), "\tx");
+    }
+
+    #[test]
+    fn tabs_reach_the_next_stop() {
+        let indent = Indent {
+            expandtab: true,
+            ..Indent::default()
+        };
+        assert_eq!(indent.tab(1), "   ");
+        assert_eq!(indent.tab(4), "    ");
+        assert_eq!(Indent::default().tab(1), "\t");
+    }
+
+    #[test]
+    fn tabstop_is_at_least_one() {
+        let indent: Indent = toml::from_str("tabstop = 2").unwrap();
+        assert_eq!(indent.tabstop, 2);
+        assert!(toml::from_str::<Indent>("tabstop = 0").is_err());
+    }
+}
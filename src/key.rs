@@ -1,3 +1,5 @@
+use crate::read::{decode_ctrl_key, is_ctrl_key};
+
 #[derive(Hash, Copy, Clone, Eq, PartialEq)]
 pub enum Key {
     Esc,
@@ -52,3 +54,102 @@ impl std::fmt::Debug for Key {
         write!(f, "{}", self)
     }
 }
+
+impl Key {
+    /// The text this key is kept as in a register when a macro is recorded: the char the
+    /// terminal sends for it where there's just one, and otherwise its name, as in `<Up>`.
+    pub fn to_macro_text(self) -> String {
+        match self {
+            Key::Utf8(ch) => ch.to_string(),
+            Key::Esc => "\x1b".to_string(),
+            Key::Enter => "\r".to_string(),
+            Key::Backspace => "\x7f".to_string(),
+            Key::Ctrl(ch) => char::from((ch as u8).wrapping_sub(b'a' - 1)).to_string(),
+            Key::None => String::new(),
+            key => key.to_string(),
+        }
+    }
+    /// The keys of a macro kept in a register as `text`. Line breaks play as Enter.
+    pub fn parse_macro_text(text: &str) -> Vec<Key> {
+        let mut keys = Vec::new();
+        let mut rest = text;
+        while let Some(ch) = rest.chars().next() {
+            // A name in angle brackets is a key if it names one, and otherwise just text.
+            let named = rest.strip_prefix('<').and_then(|after| {
+                let (name, tail) = after.split_once('>')?;
+                Some((named_key(name)?, tail))
+            });
+            if let Some((key, tail)) = named {
+                keys.push(key);
+                rest = tail;
+                continue;
+            }
+            keys.push(match ch {
+                '\x1b' => Key::Esc,
+                '\r' | '\n' => Key::Enter,
+                '\x7f' => Key::Backspace,
+                ch if ch.is_ascii() && is_ctrl_key(ch as u8) && ch != '\0' => {
+                    Key::Ctrl(decode_ctrl_key(ch as u8))
+                }
+                ch => Key::Utf8(ch),
+            });
+            rest = &rest[ch.len_utf8()..];
+        }
+        keys
+    }
+}
+
+/// The key named `name` between angle brackets in macro text, as `Display` writes it.
+fn named_key(name: &str) -> Option<Key> {
+    Some(match name {
+        "Up" => Key::Up,
+        "Down" => Key::Down,
+        "Left" => Key::Left,
+        "Right" => Key::Right,
+        "Home" => Key::Home,
+        "End" => Key::End,
+        "Del" => Key::Del,
+        "PageUp" => Key::PageUp,
+        "PageDown" => Key::PageDown,
+        "PrintScreen" => Key::PrintScreen,
+        _ => {
+            if let Some(number) = name.strip_prefix('F') {
+                Key::Function(number.parse().ok()?)
+            } else {
+                let bytes = name.strip_prefix("Esc-")?.as_bytes();
+                match bytes {
+                    [a] => Key::EscSeq1(*a),
+                    [a, b'-', b] => Key::EscSeq2(*a, *b),
+                    _ => return None,
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn macro_text_round_trips() {
+        let keys = vec![
+            Key::Utf8('i'),
+            Key::Utf8('<'),
+            Key::Enter,
+            Key::Esc,
+            Key::Ctrl('o'),
+            Key::Ctrl('i'),
+            Key::Backspace,
+            Key::Up,
+            Key::Function(12),
+            Key::EscSeq1(b'x'),
+        ];
+        let text: String = keys.iter().map(|key| key.to_macro_text()).collect();
+        assert_eq!(Key::parse_macro_text(&text), keys);
+        assert_eq!(
+            Key::parse_macro_text("<b>\n"),
+            vec![Key::Utf8('<'), Key::Utf8('b'), Key::Utf8('>'), Key::Enter]
+        );
+    }
+}
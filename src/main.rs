@@ -1,3 +1,4 @@
+use crate::color::ColorDepth;
 use crate::editor::Editor;
 use crate::error::{ErrorContext, Result};
 use crate::layout::recursive_layout;
@@ -5,7 +6,9 @@ use crate::plugin::{Plugin, PluginRef};
 use crate::prelude::*;
 use crate::read::read_key;
 use crate::read::read_u8;
+use crate::registers::Registers;
 use crate::termios::Termios;
+use crate::theme::Group;
 use crate::types::Rect;
 use crate::view_map::{HandleKey, ViewMap};
 use log::LevelFilter;
@@ -24,6 +27,7 @@ extern crate quickcheck_macros;
 mod bindings;
 mod bitmap;
 mod buf;
+mod buffers;
 mod classify;
 mod color;
 mod command;
@@ -35,25 +39,38 @@ mod doc;
 mod docview;
 mod editor;
 mod error;
+mod ex;
 mod files;
+mod filetype;
 mod format;
 mod glyph;
+mod gutter;
+mod hstack;
+mod indent;
 mod key;
 mod keygen;
 mod layout;
+mod marks;
 mod message;
+mod motion;
 mod noun;
 mod plugin;
 mod prelude;
 mod read;
+mod registers;
 mod rel;
 mod row;
+mod search;
 mod sel;
 mod settings;
 mod size;
+mod stack;
 mod status;
+mod statusline;
+mod syntax;
 mod target;
 mod termios;
+mod theme;
 mod trie;
 mod types;
 mod undo;
@@ -110,10 +127,11 @@ fn write_bmp_diff(
     bmp_last: &mut Bitmap,
     bmp: &mut Bitmap,
     fd: libc::c_int,
+    depth: ColorDepth,
 ) -> Result<()> {
     buf.truncate(0);
     buf.extend(b"\x1b[?25l");
-    Bitmap::diff(bmp_last, bmp, buf)?;
+    Bitmap::diff(bmp_last, bmp, buf, depth)?;
     if let Some(cursor) = bmp.get_cursor() {
         trace!("cursor is at {:?}", cursor);
         write!(buf, "\x1b[{};{}H", cursor.y + 1, cursor.x + 1).context("write-cursor")?
@@ -142,21 +160,25 @@ fn run_app(
     let args: Vec<String> = env::args().collect();
     trace!("wim run with args: {:?}", args);
 
-    let editor_view_key = Editor::install(plugin, view_map);
+    let settings = Rc::new(settings);
+    let registers = Registers::new();
+    let editor_view_key = Editor::install(plugin, view_map, settings.clone(), registers.clone());
     let editor: ViewRef = view_map.get_view(editor_view_key);
     let mut should_refresh = true;
     let should_resize = Arc::new(AtomicBool::new(false));
     signal_flag::register(SIGWINCH, Arc::clone(&should_resize))?;
 
-    let default_glyph = Glyph {
-        ch: ' ',
-        format: settings.display.bg + settings.display.fg,
-    };
+    let color_depth = settings
+        .terminal
+        .color_depth
+        .unwrap_or_else(ColorDepth::from_env);
+    let default_glyph = Glyph::new(' ', settings.colors.format(Group::Normal));
     let mut dks: VecDeque<DK> = Default::default();
     let mut key_timeout: Option<Instant> = None;
-    if args.len() > 1 {
-        let filename = args[1].as_ref();
-        dks.push_back(command("open").arg(filename).at_focused());
+    // Like vim, show the first file and put the rest in the buffer list.
+    for (i, filename) in args.iter().skip(1).enumerate() {
+        let name = if i == 0 { "open" } else { "add-buffer" };
+        dks.push_back(command(name).arg(filename.as_str()).at_focused());
     }
     let mut layout_rects: HashMap<ViewKey, Rect> = Default::default();
     let mut terminal_size: Size = get_window_size();
@@ -174,28 +196,9 @@ fn run_app(
         }
 
         if should_refresh {
-            layout_rects.clear();
-            recursive_layout(
-                view_map,
-                view_map.get_root_view_key(),
-                terminal_size.into(),
-                &mut layout_rects,
-            );
-
-            // Render the composite bitmap.
-            bmp.clear();
-            for (&vk, &frame) in layout_rects.iter() {
-                let mut bmp_view = BitmapView::new(&mut bmp, frame);
-                let view = view_map.get_view(vk);
-                view.display(view_map, &mut bmp_view);
-                if vk == view_map.focused_view_key() {
-                    if let Some(cursor_pos) = view.get_cursor_pos() {
-                        bmp_view.set_cursor(cursor_pos);
-                    }
-                }
-            }
+            draw(view_map, terminal_size, &mut bmp, &mut layout_rects);
             // Rasterize the bitmap to the terminal and swap the write buffers..
-            write_bmp_diff(&mut buf, &mut bmp_last, &mut bmp, stdin)?;
+            write_bmp_diff(&mut buf, &mut bmp_last, &mut bmp, stdin, color_depth)?;
             if settings.debug.write_writes {
                 buf.write_to_file("last_write.bin")?;
             }
@@ -206,6 +209,8 @@ fn run_app(
             if let Some(key) = read_key(&mut reader) {
                 // trace!("read key '{:?}'", key);
                 key_timeout = Some(Instant::now() + Duration::from_secs(1));
+                // Only typed keys are recorded into macros, not the keys that macros play.
+                registers.borrow_mut().key_typed(key);
                 dks.push_back(DK::Key(key));
             } else if let Some(next_key_timeout) = key_timeout {
                 if dks.front().is_none() {
@@ -236,6 +241,37 @@ fn run_app(
     Ok(())
 }
 
+/// Lays out every view to fit `size` and renders them all into `bmp`.
+fn draw(
+    view_map: &ViewMap,
+    size: Size,
+    bmp: &mut Bitmap,
+    layout_rects: &mut HashMap<ViewKey, Rect>,
+) {
+    layout_rects.clear();
+    recursive_layout(
+        view_map,
+        view_map.get_root_view_key(),
+        size.into(),
+        layout_rects,
+    );
+    bmp.clear();
+    for (&vk, &frame) in layout_rects.iter() {
+        // A window squeezed out by a terminal too small for it has nothing to draw into.
+        if frame.width == 0 || frame.height == 0 {
+            continue;
+        }
+        let mut bmp_view = BitmapView::new(bmp, frame);
+        let view = view_map.get_view(vk);
+        view.display(view_map, &mut bmp_view);
+        if vk == view_map.focused_view_key() {
+            if let Some(cursor_pos) = view.get_cursor_pos() {
+                bmp_view.set_cursor(cursor_pos);
+            }
+        }
+    }
+}
+
 enum PumpResult {
     Continue,
     Quit,
@@ -273,10 +309,24 @@ fn pump(view_map: &mut ViewMap, dks: &mut VecDeque<DK>) -> Result<PumpResult> {
                         Ok(Status::Quit) => {
                             return Ok(PumpResult::Quit);
                         }
+                        Ok(Status::Dispatch(dk)) => {
+                            dks.push_front(dk);
+                        }
+                        Ok(Status::Failed) => {
+                            dks.clear();
+                            return Ok(PumpResult::Continue);
+                        }
                         Ok(status) => {
                             cmdline.set_status(status);
                         }
-                        Err(err) => return Err(err),
+                        Err(err) => {
+                            // A command that fails stops whatever sequence it was part of, as a
+                            // failing mapping does in vim, but the editor carries on.
+                            log::warn!("command failed: {}", err);
+                            cmdline.set_status(Status::Error(err.message().to_string()));
+                            dks.clear();
+                            return Ok(PumpResult::Continue);
+                        }
                     }
                 }
                 DK::Sequence(next_dks) => {
@@ -298,7 +348,7 @@ fn pump(view_map: &mut ViewMap, dks: &mut VecDeque<DK>) -> Result<PumpResult> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::utils::open_dev_null;
+    use crate::utils::{open_dev_null, TempDir};
 
     #[allow(unused_macros)]
     macro_rules! check {
@@ -323,9 +373,19 @@ mod test {
     }
 
     fn run_text(text: &str) -> Result<String> {
+        run_views(text).map(|view_map| {
+            view_map
+                .get_root_view()
+                .unwrap()
+                .get_doc_text(&view_map)
+                .unwrap()
+        })
+    }
+
+    fn run_views(text: &str) -> Result<ViewMap> {
         let fd = open_dev_null();
         let mut view_map = ViewMap::new();
-        let result = run_app(
+        run_app(
             Plugin::new(),
             &mut view_map,
             Settings::default(),
@@ -336,32 +396,473 @@ mod test {
                 height: 100,
             },
             text.as_bytes().into_iter().copied(),
-        );
-        result.map(|()| {
-            view_map
-                .get_root_view()
-                .unwrap()
-                .get_doc_text(&view_map)
-                .unwrap()
-        })
+        )?;
+        Ok(view_map)
+    }
+
+    /// Runs `text` and draws a frame of the views it leaves, returning the format each char of
+    /// the first row showing `line` is drawn in.
+    fn formats_of_line(text: &str, line: &str) -> Result<Vec<Format>> {
+        let view_map = run_views(text)?;
+        let size = Size {
+            width: 100,
+            height: 100,
+        };
+        let mut bmp = Bitmap::new(size, Glyph::new(' ', Format::none()));
+        draw(&view_map, size, &mut bmp, &mut HashMap::new());
+        for y in 0..size.height {
+            let glyphs: Vec<&Glyph> = (0..size.width)
+                .map(|x| bmp.get_glyph(Pos { x, y }))
+                .collect();
+            let row: String = glyphs.iter().map(|glyph| glyph.ch).collect();
+            if let Some(x) = row.find(line) {
+                let x = row[..x].chars().count();
+                let formats = glyphs[x..x + line.chars().count()]
+                    .iter()
+                    .map(|glyph| glyph.format)
+                    .collect();
+                return Ok(formats);
+            }
+        }
+        Err(error!("no row shows '{}'", line))
     }
 
     #[test]
     fn delete_char() -> Result<()> {
         // simple_logging::log_to_stderr(LevelFilter::Info);
-        check_doc!("iHello world.\x1b\0\0bbx:quit\x0d", "ello world.\n");
+        check_doc!("iHello world.\x1b\0\0bbx:quit!\x0d", "ello world.\n");
+        Ok(())
+    }
+    #[test]
+    fn motions_step_by_grapheme() -> Result<()> {
+        check_doc!("i中e\u{301}xy\x1b\0\0hhxhllx:q!\x0d", "中x\n");
+        // A selection takes all of the grapheme it ends in.
+        check_doc!("ie\u{301}e\u{301}x\x1b\0\x000vd:q!\x0d", "e\u{301}x\n");
+        check_doc!(
+            "ie\u{301}e\u{301}x\x1b\0\x000vly$p:q!\x0d",
+            "e\u{301}e\u{301}xe\u{301}e\u{301}\n"
+        );
+        Ok(())
+    }
+    #[test]
+    fn shifts_and_tabs() -> Result<()> {
+        check_doc!("ione\x1b\0\0>>:q!\x0d", "\tone\n");
+        check_doc!("ione\x1b\0\0>>.<<:q!\x0d", "\tone\n");
+        check_doc!("ione\x0dtwo\x1b\0\0Vk>:q!\x0d", "\tone\ttwo\n");
+        check_doc!("ione\x1b\0\0:set et sw=2\x0d>>:q!\x0d", "  one\n");
+        check_doc!("ia\x09b\x1b\0\0:q!\x0d", "a\tb\n");
+        check_doc!(":set et\x0dia\x09b\x1b\0\0:q!\x0d", "a   b\n");
         Ok(())
     }
     #[test]
     fn insert_text() {
-        check!("iHello world.\x1b\0\0:quit\x0d");
+        check!("iHello world.\x1b\0\0:quit!\x0d");
     }
     #[test]
     fn delete_word() {
-        check!("iHello world.\x1b\0\0bbdw:quit\x0d");
+        check!("iHello world.\x1b\0\0bbdw:quit!\x0d");
     }
     #[test]
     fn delete_word_end() {
-        check!("iHello world.\x1b\0\0bbde:quit\x0d");
+        check!("iHello world.\x1b\0\0bbde:quit!\x0d");
+    }
+    #[test]
+    fn ex_delete_lines() -> Result<()> {
+        check_doc!("ione\x0dtwo\x0dthree\x1b\0\0:2d\x0d:q!\x0d", "onethree\n");
+        check_doc!("ione\x0dtwo\x0dthree\x1b\0\0:1,2d\x0d:q!\x0d", "three\n");
+        check_doc!("ione\x0dtwo\x0dthree\x1b\0\0:%d\x0d:q!\x0d", "\n");
+        Ok(())
+    }
+    #[test]
+    fn yank_and_put() -> Result<()> {
+        check_doc!("ione\x1b\0\0yyp:q!\x0d", "oneone\n");
+        check_doc!("ione\x1b\0\0yypu:q!\x0d", "one\n");
+        check_doc!("iab cd\x1b\0\0bbdwP:q!\x0d", "ab cd\n");
+        check_doc!("iab cd\x1b\0\0bbdwp:q!\x0d", "cab d\n");
+        // Yanking lines leaves the cursor on the first of them.
+        check_doc!("iabc\x0ddef\x1b\0\0ggVjy2p:q!\x0d", "abcabcdefabcdefdef\n");
+        check_doc!("iabc\x0ddef\x1b\0\0ykp:q!\x0d", "abcabcdefdef\n");
+        Ok(())
+    }
+    #[test]
+    fn named_registers() -> Result<()> {
+        check_doc!("ione\x0dtwo\x1b\0\0\"ayyk\"_ddyy\"ap:q!\x0d", "twotwo\n");
+        Ok(())
+    }
+    #[test]
+    fn search() -> Result<()> {
+        check_doc!("ifoo bar foo\x1b\0\0/foo\x0dx:q!\x0d", "oo bar foo\n");
+        check_doc!("ifoo bar foo\x1b\0\0/foo\x0dnx:q!\x0d", "foo bar oo\n");
+        check_doc!("ifoo bar foo\x1b\0\0/foo\x0dnNx:q!\x0d", "oo bar foo\n");
+        check_doc!("ifoo foo\x1b\0\0?foo\x0dx:q!\x0d", "foo oo\n");
+        check_doc!("ifoo bar baz\x1b\0\0bbd/baz\x0d:q!\x0d", "foo baz\n");
+        check!("ifoo\x1b\0\0/nope\x0d:q!\x0d");
+        Ok(())
+    }
+    #[test]
+    fn visual_modes() -> Result<()> {
+        check_doc!("ione\x0dtwo\x1b\0\0kvjd:q!\x0d", "on\n");
+        check_doc!("iabc\x1b\0\0vhhyP:q!\x0d", "abcabc\n");
+        check_doc!("ione\x0dtwo\x0dthree\x1b\0\0kVkd:q!\x0d", "three\n");
+        check_doc!("ione\x0dtwo\x1b\0\0Vkyjp:q!\x0d", "onetwoonetwo\n");
+        check_doc!("iabc\x0ddef\x1b\0\0\x16khd:q!\x0d", "ad\n");
+        check_doc!("iab\x0dcd\x1b\0\0\x16kyP:q!\x0d", "abbcdd\n");
+        // Blocks are made of screen columns, whatever tabs and wide chars the rows have.
+        check_doc!("i\tab\x0d1234cd\x1b\0\x000fc\x16kd:q!\x0d", "\t1234\n");
+        check_doc!("i中x\x0dabc\x1b\0\0gg$\x16jd:q!\x0d", "a\n");
+        Ok(())
+    }
+    #[test]
+    fn visual_block_insert() -> Result<()> {
+        check_doc!("iabc\x0ddef\x1b\0\0k\x16jIX\x1b\0\0:q!\x0d", "abXcdeXf\n");
+        check_doc!("iabc\x0ddef\x1b\0\0\x16khcX\x1b\0\0:q!\x0d", "aXdX\n");
+        check_doc!("i中x\x0dabc\x1b\0\0\x16kIX\x1b\0\0:q!\x0d", "中XxabXc\n");
+        Ok(())
+    }
+    #[test]
+    fn counts() -> Result<()> {
+        check_doc!("ia b c d\x1b\0\x003bx:q!\x0d", " b c d\n");
+        check_doc!("ia b c d\x1b\0\x003bd2w:q!\x0d", "c d\n");
+        check_doc!("ia b c d\x1b\0\x003b2dw:q!\x0d", "c d\n");
+        check_doc!(
+            "ione\x0dtwo\x0dthree\x0dfour\x1b\0\x003k2dd:q!\x0d",
+            "threefour\n"
+        );
+        check_doc!("iabcd\x1b\0\x003h3x:q!\x0d", "d\n");
+        check_doc!("iab\x1b\0\0x3p:q!\x0d", "abbb\n");
+        check_doc!("ia\x0db\x0dc\x1b\0\x002k3J:q!\x0d", "abc\n");
+        check_doc!("i12\x1b\0\0:q!\x0d", "12\n");
+        check_doc!("ia\x0db\x0dc\x1b\0\x002k2:d\x0d:q!\x0d", "c\n");
+        Ok(())
+    }
+    #[test]
+    fn repeat_change() -> Result<()> {
+        check_doc!("iabcd\x1b\0\x003hx..:q!\x0d", "d\n");
+        check_doc!("ione\x0dtwo\x0dthree\x1b\0\0kkdd.:q!\x0d", "three\n");
+        check_doc!("ifoo\x1b\0\0.:q!\x0d", "fofooo\n");
+        check_doc!("ione\x1b\0\0otwo\x1b\0\0.:q!\x0d", "onetwotwo\n");
+        check_doc!("iabcdef\x1b\0\x005hx3.:q!\x0d", "ef\n");
+        check_doc!("ia b\x1b\0\0xd\x1b\0\0.:q!\x0d", "a\n");
+        check!("ifoo\x1b\0\0u.:q!\x0d");
+        // Visual operators repeat over as much text from the cursor.
+        check_doc!("iabc\x0ddef\x1b\0\0ggvld.:q!\x0d", "def\n");
+        check_doc!("iabcdef\x1b\0\x000vld.:q!\x0d", "ef\n");
+        check_doc!(
+            "ione\x0dtwo\x0dthree\x0dfour\x0dfive\x1b\0\0ggVjd.:q!\x0d",
+            "five\n"
+        );
+        check_doc!("iabcd\x0dabcd\x0dabcd\x1b\0\0gg0\x16jld.:q!\x0d", "abcd\n");
+        // Ex commands aren't repeated, so `.` repeats the change before.
+        check_doc!("ione\x0dtwo\x0dthree\x1b\0\0ggx:d\x0d.:q!\x0d", "wothree\n");
+        check_doc!("ifoo\x1b\0\x003.:q!\x0d", "fofoofoofooo\n");
+        check_doc!("ione\x1b\0\0ofoo\x1b\0\x002.:q!\x0d", "onefoofoofoo\n");
+        Ok(())
+    }
+    #[test]
+    fn undo_tree() -> Result<()> {
+        // Deleting `c`, undoing, then deleting `b` leaves both branches in the tree.
+        check_doc!("iabc\x1b\0\0xuhx:q!\x0d", "ac\n");
+        check_doc!("iabc\x1b\0\0xuhxg-:q!\x0d", "ab\n");
+        check_doc!("iabc\x1b\0\0xuhxg-g-g+g+:q!\x0d", "ac\n");
+        check_doc!("iabc\x1b\0\0xuhx2g-:q!\x0d", "abc\n");
+        check_doc!("iabc\x1b\0\0xuhxu\x12:q!\x0d", "ac\n");
+        check_doc!("iabc\x1b\0\0xuhx:earlier 2\x0d:q!\x0d", "abc\n");
+        check_doc!("iabc\x1b\0\0xuhx:ea 2\x0d:lat\x0d:q!\x0d", "ab\n");
+        check_doc!("iabc\x1b\0\0xuhx:earlier 1h\x0d:q!\x0d", "\n");
+        check_doc!("iabc\x1b\0\0:earlier 1h\x0d:later 1d\x0d:q!\x0d", "abc\n");
+        check!("iabc\x1b\0\0:earlier 1y\x0d:q!\x0d");
+        Ok(())
+    }
+    #[test]
+    fn insert_undoes_as_one_change() -> Result<()> {
+        check_doc!("ione two\x1b\0\0u:q!\x0d", "\n");
+        check_doc!("ione\x1b\0\0otwo\x0dthree\x1b\0\0u:q!\x0d", "one\n");
+        check_doc!("ione\x1b\0\0otwo\x1b\0\0u\x12:q!\x0d", "onetwo\n");
+        check_doc!("ione two\x1b\0\0bcwxyz\x1b\0\0u:q!\x0d", "one two\n");
+        check_doc!("iab\x0dcd\x1b\0\0\x16kcX\x1b\0\0u:q!\x0d", "abcd\n");
+        Ok(())
+    }
+    #[test]
+    fn buffers() -> Result<()> {
+        let dir = TempDir::new("buffers")?;
+        let a = dir.join("a.txt");
+        let b = dir.join("b.txt");
+        std::fs::write(&a, "alpha\n")?;
+        std::fs::write(&b, "beta\n")?;
+        let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+        let run = |keys: String, should_be: &str| check_doc!(keys.as_str(), should_be);
+
+        run(format!(":e {}\x0d:e {}\x0d:bp\x0dx:q!\x0d", a, b), "lpha\n");
+        run(format!(":e {}\x0d:e {}\x0d:bn\x0dx:q!\x0d", a, b), "lpha\n");
+        // Each buffer keeps its own cursor.
+        run(
+            format!(":e {}\x0dlll:e {}\x0d:b1\x0dx:q!\x0d", a, b),
+            "alpa\n",
+        );
+        run(
+            format!(":e {}\x0dx:e {}\x0d:ls\x0d:b a.txt\x0d:q!\x0d", a, b),
+            "lpha\n",
+        );
+        // Dropping a modified buffer takes a `!`.
+        run(format!(":e {}\x0dx:bd\x0d:q!\x0d", a), "lpha\n");
+        run(format!(":e {}\x0dx:bd!\x0d:q!\x0d", a), "\n");
+        run(format!(":e {}\x0d:e {}\x0d:bd\x0d:q!\x0d", a, b), "alpha\n");
+        run(format!(":badd {}\x0d:bn\x0d:q!\x0d", b), "beta\n");
+
+        Ok(())
+    }
+    #[test]
+    fn window_splits() -> Result<()> {
+        // Both windows show the same doc, each with its own cursor.
+        check_doc!("ione\x1b\0\0:sp\x0dx\x17jx:q!\x0d:q!\x0d", "o\n");
+        check_doc!("ione\x1b\0\0\x17v\x17lx\x17hx\x17c:q!\x0d", "o\n");
+        check_doc!(
+            "ione\x1b\0\0:sp\x0d:vsp\x0d\x17w\x17wx:only\x0d:q!\x0d",
+            "on\n"
+        );
+        check!("ione\x1b\0\0\x17s3\x17+\x17-\x17v2\x17<\x17=\x17j\x17o:q!\x0d");
+        // Splitting stops when there's no room left for another window.
+        let keys = format!(
+            "ione\x1b\0\0{}:vsp /nonexistent-wim-dir/x.txt\x0dx:only\x0d:q!\x0d",
+            "\x17v\x17s".repeat(8)
+        );
+        check_doc!(keys.as_str(), "on\n");
+
+        let dir = TempDir::new("splits")?;
+        let a = dir.join("a.txt");
+        std::fs::write(&a, "alpha\n")?;
+        let keys = format!(
+            "ione\x1b\0\0:vsp {}\x0d\x17lx\x17c:q!\x0d",
+            a.to_str().unwrap()
+        );
+        check_doc!(keys.as_str(), "alpha\n");
+        Ok(())
+    }
+    #[test]
+    fn tab_pages() -> Result<()> {
+        check_doc!(
+            "ione\x1b\0\0:tabnew\x0ditwo\x1b\0\0gTx:tabo\x0d:q!\x0d",
+            "on\n"
+        );
+        check_doc!(
+            "ione\x1b\0\0:tabnew\x0d:tabnew\x0d2gTx:tabo\x0d:q!\x0d",
+            "on\n"
+        );
+        check_doc!(
+            "ione\x1b\0\0:tabnew\x0d:tabnew\x0d1gtx:tabo\x0d:q!\x0d",
+            "on\n"
+        );
+        check_doc!(
+            "ione\x1b\0\0:tabnew\x0d:tabn 1\x0dx:tabo\x0d:q!\x0d",
+            "on\n"
+        );
+        check_doc!("ione\x1b\0\0:tabnew\x0d:tabp\x0dx:tabo\x0d:q!\x0d", "on\n");
+        // Quitting the last window in a tab closes the tab.
+        check_doc!("ione\x1b\0\0:tabnew\x0d:q!\x0dx:q!\x0d", "on\n");
+        check!("ione\x1b\0\0:tabc\x0d:q!\x0d");
+
+        let dir = TempDir::new("tabs")?;
+        let a = dir.join("a.txt");
+        std::fs::write(&a, "alpha\n")?;
+        let a = a.to_str().unwrap();
+        let keys = format!("ione\x1b\0\0:tabnew {}\x0dx:tabo\x0d:q!\x0d", a);
+        check_doc!(keys.as_str(), "lpha\n");
+        // Each tab comes back to the window that was focused in it.
+        let keys = format!(
+            "ione\x1b\0\0:vsp {}\x0d:tabnew\x0dgTxgt:tabc\x0d:only\x0d:q!\x0d",
+            a
+        );
+        check_doc!(keys.as_str(), "lpha\n");
+        Ok(())
+    }
+    #[test]
+    fn text_objects() {
+        check_doc!("ifoo bar baz\x1b\0\0bbdiw:q!\x0d", "foo  baz\n");
+        check_doc!("ifoo bar baz\x1b\0\0bbdaw:q!\x0d", "foo baz\n");
+        check_doc!("if(a, (b))\x1b\0\0hhci(x\x1b\0\0:q!\x0d", "f(a, (x))\n");
+        check_doc!("if(a, (b))\x1b\0\0hh2di(:q!\x0d", "f()\n");
+        check_doc!("ix = \"y z\";\x1b\0\0hhhda\":q!\x0d", "x =;\n");
+        check_doc!("ia\x0db\x0d\x0dc\x1b\0\0kkdap:q!\x0d", "c\n");
+        check_doc!(
+            "i<a><b>x</b></a>\x1b\0\0hhhhhhhdit:q!\x0d",
+            "<a><b></b></a>\n"
+        );
+        check_doc!("ione (two) three\x1b\0\0bbvi(d:q!\x0d", "one () three\n");
+        check_doc!("ia\x0db\x0d\x0dc\x1b\0\0kkvipd:q!\x0d", "c\n");
+    }
+    #[test]
+    fn line_and_doc_motions() {
+        check_doc!("iabc def\x1b\0\0h0x:q!\x0d", "bc def\n");
+        check_doc!("iabc def\x1b\0\0hd0:q!\x0d", "ef\n");
+        check_doc!("i  abc def\x1b\0\0^d$:q!\x0d", "  \n");
+        check_doc!("ia\x0db\x0dc\x1b\0\0ggd2$:q!\x0d", "c\n");
+        check_doc!("ia\x0db\x0dc\x1b\0\0kdG:q!\x0d", "a\n");
+        check_doc!("ia\x0db\x0dc\x1b\0\0kdgg:q!\x0d", "c\n");
+        check_doc!("ia\x0db\x0dc\x1b\0\0gg2Gx:q!\x0d", "ac\n");
+        check_doc!("ia\x0db\x0dc\x0dd\x0de\x1b\0\0HxMxLx:q!\x0d", "bd\n");
+    }
+    #[test]
+    fn char_finds_repeat() {
+        check_doc!("ia,b,c,d\x1b\0\0h0f,;x:q!\x0d", "a,bc,d\n");
+        check_doc!("ia,b,c,d\x1b\0\0h0f,;,x:q!\x0d", "ab,c,d\n");
+        check_doc!("ia,b,c,d\x1b\0\0h0dt,:q!\x0d", ",b,c,d\n");
+        check_doc!("ia,b,c,d\x1b\0\0dF,:q!\x0d", "a,b,cd\n");
+        check_doc!("ia,b,c,d\x1b\0\0h0d2f,:q!\x0d", "c,d\n");
+        check_doc!("ia,b,c\x1b\0\0h0dt,.:q!\x0d", ",c\n");
+        check_doc!("iabc\x1b\0\0df\x1b\0\0x:q!\x0d", "ab\n");
+    }
+    #[test]
+    fn bracket_and_paragraph_motions() {
+        check_doc!("if(a[b]c)\x1b\0\0h0%x:q!\x0d", "f(a[b]c\n");
+        check_doc!("if(a[b]c) x\x1b\0\0h0d%:q!\x0d", " x\n");
+        check_doc!("ia\x0db\x0d\x0dc\x0dd\x1b\0\0ggd}:q!\x0d", "cd\n");
+        check_doc!("ia\x0db\x0d\x0dc\x0dd\x1b\0\0d{:q!\x0d", "abd\n");
+    }
+    #[test]
+    fn word_and_big_word_motions() {
+        check_doc!("ifoo.bar baz\x1b\0\0BBx:q!\x0d", "oo.bar baz\n");
+        check_doc!("ifoo.bar baz\x1b\0\0ggdW:q!\x0d", "baz\n");
+        check_doc!("ifoo.bar baz\x1b\0\0ggEx:q!\x0d", "foo.ba baz\n");
+        check_doc!("i中文かな\x1b\0\0ggwx:q!\x0d", "中文な\n");
+        check_doc!("ia\u{2014}b\x1b\0\0ggwx:q!\x0d", "ab\n");
+        check_doc!("ia-b c\x1b\0\0ggdw:q!\x0d", "-b c\n");
+        check_doc!(":set isk=-\x0dia-b c\x1b\0\0ggdw:q!\x0d", "c\n");
+        check_doc!("ifoo bar\x1b\0\x000dw:q!\x0d", "bar\n");
+        check_doc!("ifoo bar\x1b\0\x000cwx\x1b\0\0:q!\x0d", "x bar\n");
+        // The last word on a line takes neither the line break nor the next line's indent.
+        check_doc!("ifoo\x0d  bar\x1b\0\0ggdw:q!\x0d", "  bar\n");
+        check_doc!("ifoo bar\x1b\0\0bdw:q!\x0d", "foo \n");
+    }
+    #[test]
+    fn marks_follow_their_lines() {
+        check_doc!(
+            "ione\x0dtwo\x0dthree\x1b\0\0kmaggx'ax:q!\x0d",
+            "newothree\n"
+        );
+        check_doc!(
+            "ione\x0dtwo\x1b\0\0maggOzero\x1b\0\0'ax:q!\x0d",
+            "zeroonewo\n"
+        );
+        check_doc!(
+            "ione\x0dtwo\x1b\0\0maggOzero\x1b\0\0`ax:q!\x0d",
+            "zeroonetw\n"
+        );
+        check_doc!("ione\x0dtwo\x0dthree\x1b\0\0kmaggd'a:q!\x0d", "three\n");
+        check_doc!("ione\x1b\0\0hd'bx:q!\x0d", "oe\n");
+    }
+    #[test]
+    fn automatic_marks() {
+        check_doc!("ione\x0dtwo\x0dthree\x1b\0\0gg''x:q!\x0d", "onetwohree\n");
+        check_doc!("ione\x0dtwo\x0dthree\x1b\0\0gg``x:q!\x0d", "onetwothre\n");
+        check_doc!("ione\x0dtwo\x1b\0\0ggxG`.x:q!\x0d", "etwo\n");
+        check_doc!("ione\x1b\0\0hhx`^x:q!\x0d", "n\n");
+        check_doc!("ione two\x1b\0\0gg0yw$`]x:q!\x0d", "onetwo\n");
+    }
+    #[test]
+    fn jump_list() {
+        check_doc!(
+            "ione\x0dtwo\x0dthree\x1b\0\0ggG\x0fx\x09x:q!\x0d",
+            "netwohree\n"
+        );
+    }
+    #[test]
+    fn global_marks_open_their_file() -> Result<()> {
+        let dir = TempDir::new("marks")?;
+        let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+        std::fs::write(&a, "one\ntwo\n")?;
+        std::fs::write(&b, "three\n")?;
+        let keys = format!(
+            ":e {}\x0djmA:e {}\x0d:bd 1\x0d'Ax:q!\x0d",
+            a.to_str().unwrap(),
+            b.to_str().unwrap()
+        );
+        check_doc!(keys.as_str(), "onewo\n");
+        Ok(())
+    }
+    #[test]
+    fn macros() {
+        check_doc!(
+            "ione\x0dtwo\x0dthree\x1b\0\0ggqaxjq@a:q!\x0d",
+            "newothree\n"
+        );
+        check_doc!(
+            "ione\x0dtwo\x0dthree\x1b\0\0ggqaxjq2@a:q!\x0d",
+            "newohree\n"
+        );
+        check_doc!(
+            "ione\x0dtwo\x0dthree\x1b\0\0ggqaxjq@a@@:q!\x0d",
+            "newohree\n"
+        );
+        check_doc!("ione\x0dtwo\x1b\0\0ggqai!\x1b\0\0qj@a:q!\x0d", "!onet!wo\n");
+        // Macros play macros, and one that plays itself stops once `j` can't move down.
+        check_doc!(
+            "ione\x0dtwo\x0dthree\x1b\0\0qaqggqa0xj@aq@a:q!\x0d",
+            "newohree\n"
+        );
+    }
+    #[test]
+    fn macros_are_text() {
+        check_doc!("ione\x1b\0\0qahxq\"ap:q!\x0d", "oehx\n");
+        check_doc!("ione\x0dtwo\x0d0x\x1b\0\0h\"ay$ddgg@a:q!\x0d", "netwo\n");
+    }
+    #[test]
+    fn highlights_while_editing() -> Result<()> {
+        let dir = TempDir::new("syntax")?;
+        let a = dir.join("a.rs");
+        std::fs::write(&a, "fn main() {} /* a\nb */\n")?;
+        let a = a.to_str().unwrap();
+        let colors = Settings::default().colors;
+        let (keyword, comment) = (colors.format(Group::Keyword), colors.format(Group::Comment));
+        assert_ne!(keyword, comment);
+
+        let formats = formats_of_line(&format!(":e {}\x0d:q!\x0d", a), "fn main() {} /* a")?;
+        assert_eq!(formats[..2], [keyword; 2]);
+        assert_eq!(formats[13..], [comment; 4]);
+        let formats = formats_of_line(&format!(":e {}\x0d:q!\x0d", a), "b */")?;
+        assert_eq!(formats, [comment; 4]);
+
+        // Opening a comment above turns the lines below into comment too, as far as it goes.
+        let keys = format!(":e {}\x0dO/*\x1b\0\0jx:q!\x0d", a);
+        check_doc!(keys.as_str(), "/*f main() {} /* ab */\n");
+        let formats = formats_of_line(&keys, "f main() {} /* a")?;
+        assert_eq!(formats, [comment; 16]);
+        let formats = formats_of_line(&keys, "b */")?;
+        assert_eq!(formats, [comment; 4]);
+        Ok(())
+    }
+    #[test]
+    fn failed_save_keeps_editing() {
+        check_doc!(
+            "ione\x1b\0\0:w /nonexistent-wim-dir/x.txt\x0dx:q!\x0d",
+            "on\n"
+        );
+    }
+    #[test]
+    fn edit_missing_file_opens_empty_buffer() {
+        check_doc!(
+            ":e /nonexistent-wim-dir/new.txt\x0dinew\x1b\0\0:q!\x0d",
+            "new\n"
+        );
+    }
+    #[test]
+    fn edit_unreadable_file_keeps_editing() {
+        check_doc!("ione\x1b\0\0:e /\x0dx:q!\x0d", "on\n");
+    }
+    #[test]
+    fn write_without_a_name_keeps_editing() {
+        check_doc!("ione\x1b\0\0:w\x0dx:q!\x0d", "on\n");
+    }
+    #[test]
+    fn quit_refuses_unsaved_changes() {
+        check_doc!("ione\x1b\0\0:q\x0dx:wq\x0dx:q!\x0d", "o\n");
+        // Hidden buffers count too.
+        check_doc!(
+            "ione\x1b\0\0:badd /nonexistent-wim-dir/x.txt\x0d:b2\x0d:q\x0d:b1\x0dx:q!\x0d",
+            "on\n"
+        );
+    }
+    #[test]
+    fn ex_parse_error() {
+        check!("ione\x1b\0\0:frobnicate\x0d:q!\x0d");
     }
 }
@@ -0,0 +1,191 @@
+use crate::buffers::BufferId;
+use crate::types::{Coord, Pos};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// The mark set before the latest jump, which `''` goes back to.
+pub static LAST_JUMP: char = '\'';
+/// The mark set where the last change was made.
+pub static LAST_CHANGE: char = '.';
+/// The mark set where insert mode was last left.
+pub static LAST_INSERT: char = '^';
+/// The marks set at the start and end of the last changed or yanked text.
+pub static CHANGE_START: char = '[';
+pub static CHANGE_END: char = ']';
+
+/// How many jumps a window remembers, as in vim.
+const MAX_JUMPS: usize = 100;
+
+/// The marks set in a doc, by name. They stay on their lines as rows are spliced in and out
+/// above them.
+#[derive(Clone, Debug, Default)]
+pub struct Marks {
+    marks: HashMap<char, Pos>,
+}
+
+impl Marks {
+    /// The names that marks can be jumped to by: the ones `m` sets, the automatic ones, and `` `
+    /// `` as another name for `'`.
+    pub fn names() -> impl Iterator<Item = char> {
+        ('a'..='z').chain('A'..='Z').chain([
+            LAST_JUMP,
+            '`',
+            LAST_CHANGE,
+            LAST_INSERT,
+            CHANGE_START,
+            CHANGE_END,
+        ])
+    }
+    /// Whether `m` can set mark `name`. Uppercase marks are global, so set marks in a file
+    /// rather than a doc.
+    pub fn is_settable(name: char) -> bool {
+        name.is_ascii_alphabetic()
+    }
+    pub fn is_global(name: char) -> bool {
+        name.is_ascii_uppercase()
+    }
+    pub fn get(&self, name: char) -> Option<Pos> {
+        self.marks.get(&canonical(name)).copied()
+    }
+    pub fn set(&mut self, name: char, pos: Pos) {
+        self.marks.insert(canonical(name), pos);
+    }
+    pub fn remove(&mut self, name: char) {
+        self.marks.remove(&canonical(name));
+    }
+    /// Follows rows `range` being replaced with `count` new ones. Marks below the range move up
+    /// or down with it; marks on rows that go away move to the last new row, or are dropped if
+    /// the rows are only deleted.
+    pub fn splice(&mut self, range: Range<Coord>, count: usize) {
+        let new_end = range.start + count;
+        self.marks.retain(|_, pos| {
+            if pos.y >= range.end {
+                pos.y = pos.y + new_end - range.end;
+            } else if pos.y >= new_end {
+                if count == 0 {
+                    return false;
+                }
+                pos.y = new_end - 1;
+            }
+            true
+        });
+    }
+}
+
+/// `` ` `` is the same mark as `'`.
+fn canonical(name: char) -> char {
+    if name == '`' {
+        LAST_JUMP
+    } else {
+        name
+    }
+}
+
+/// Where a global mark was set: in a buffer, which may since have been deleted, and the file it
+/// was editing, if any. While the buffer is open, its doc keeps the mark's position up to date.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FileMark {
+    pub buffer: BufferId,
+    pub filename: Option<String>,
+    pub pos: Pos,
+}
+
+/// A place a window jumped from.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Jump {
+    pub buffer: BufferId,
+    pub pos: Pos,
+}
+
+/// The places a window has jumped from, oldest first, which Ctrl-o and Ctrl-i go back and forth
+/// through.
+#[derive(Clone, Debug, Default)]
+pub struct JumpList {
+    jumps: Vec<Jump>,
+    /// Where in `jumps` Ctrl-o and Ctrl-i have got to, or its length when they haven't been used
+    /// since the last jump.
+    index: usize,
+}
+
+impl JumpList {
+    /// Remembers a jump from `from`, in place of any earlier jump from the same line.
+    pub fn push(&mut self, from: Jump) {
+        self.jumps
+            .retain(|jump| jump.buffer != from.buffer || jump.pos.y != from.pos.y);
+        self.jumps.push(from);
+        if self.jumps.len() > MAX_JUMPS {
+            self.jumps.remove(0);
+        }
+        self.index = self.jumps.len();
+    }
+    /// The place `count` jumps back, from `current` where the window is now. Going back from
+    /// the newest jump remembers `current` so that Ctrl-i can return to it.
+    pub fn back(&mut self, current: Jump, count: usize) -> Option<Jump> {
+        if self.index == self.jumps.len() {
+            self.push(current);
+            self.index = self.jumps.len() - 1;
+        }
+        self.index = self.index.checked_sub(count)?;
+        Some(self.jumps[self.index])
+    }
+    /// The place `count` jumps on from where Ctrl-o last went back to.
+    pub fn forward(&mut self, count: usize) -> Option<Jump> {
+        if self.index + count >= self.jumps.len() {
+            return None;
+        }
+        self.index += count;
+        Some(self.jumps[self.index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(y: Coord) -> Pos {
+        Pos { x: 0, y }
+    }
+
+    #[test]
+    fn marks_follow_spliced_rows() {
+        let mut marks = Marks::default();
+        marks.set('a', line(5));
+        marks.set('b', line(2));
+        marks.set('c', line(3));
+        // Two rows inserted above 'a'.
+        marks.splice(1..1, 2);
+        assert_eq!(marks.get('a'), Some(line(7)));
+        assert_eq!(marks.get('b'), Some(line(4)));
+        // Rows 4 and 5 deleted.
+        marks.splice(4..6, 0);
+        assert_eq!(marks.get('a'), Some(line(5)));
+        assert_eq!(marks.get('b'), None);
+        assert_eq!(marks.get('c'), None);
+        // Rows 4 to 6 joined into one.
+        marks.set('d', line(6));
+        marks.splice(4..7, 1);
+        assert_eq!(marks.get('d'), Some(line(4)));
+        assert_eq!(marks.get('a'), Some(line(4)));
+        marks.set('`', line(1));
+        assert_eq!(marks.get(LAST_JUMP), Some(line(1)));
+    }
+
+    #[test]
+    fn jumps_go_back_and_forth() {
+        let jump = |y| Jump {
+            buffer: 1,
+            pos: line(y),
+        };
+        let mut jumps = JumpList::default();
+        jumps.push(jump(1));
+        jumps.push(jump(5));
+        assert_eq!(jumps.forward(1), None);
+        assert_eq!(jumps.back(jump(9), 1), Some(jump(5)));
+        assert_eq!(jumps.back(jump(5), 1), Some(jump(1)));
+        assert_eq!(jumps.back(jump(1), 1), None);
+        assert_eq!(jumps.forward(2), Some(jump(9)));
+        // Jumping from a line that's already in the list moves it to the end.
+        jumps.push(jump(1));
+        assert_eq!(jumps.back(jump(3), 2), Some(jump(9)));
+    }
+}
@@ -0,0 +1,108 @@
+use crate::noun::Noun;
+use crate::rel::Rel;
+use crate::types::Coord;
+
+/// How an operator treats the text between the cursor and where a motion takes it, as in vim's
+/// `:help exclusive` and `:help linewise`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MotionKind {
+    /// Charwise, leaving out the char the motion ends on.
+    Exclusive,
+    /// Charwise, taking in the char the motion ends on.
+    Inclusive,
+    /// Every line from the cursor's to the motion's.
+    Linewise,
+}
+
+impl MotionKind {
+    pub fn of(noun: Noun, rel: Rel) -> Self {
+        match (noun, rel) {
+            (Noun::Line, Rel::Prior | Rel::Next) | (Noun::Doc | Noun::Screen, _) => Self::Linewise,
+            (Noun::Line, Rel::End)
+            | (Noun::Word | Noun::BigWord, Rel::End)
+            | (Noun::Bracket, _) => Self::Inclusive,
+            _ => Self::Exclusive,
+        }
+    }
+}
+
+/// Whether a motion by `noun` is a jump, remembered in the jump list so that Ctrl-o can go back.
+pub fn is_jump(noun: Noun) -> bool {
+    matches!(
+        noun,
+        Noun::Doc | Noun::Paragraph | Noun::Bracket | Noun::Screen
+    )
+}
+
+/// A search along the cursor's line for a char, as `f`, `t`, `F` and `T` do. `;` and `,`
+/// repeat the last one.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CharFind {
+    pub ch: char,
+    /// `Next` to search to the right, `Prior` to the left.
+    pub rel: Rel,
+    /// Whether to stop just short of the char.
+    pub till: bool,
+}
+
+impl CharFind {
+    pub fn reversed(self) -> Self {
+        Self {
+            rel: match self.rel {
+                Rel::Prior => Rel::Next,
+                _ => Rel::Prior,
+            },
+            ..self
+        }
+    }
+    pub fn kind(&self) -> MotionKind {
+        match self.rel {
+            Rel::Prior => MotionKind::Exclusive,
+            _ => MotionKind::Inclusive,
+        }
+    }
+    /// Where in `line` the `count`th match from `x` takes the cursor. A repeated till skips a
+    /// match right next to the cursor, which it would otherwise never get past.
+    pub fn target(&self, line: &[char], x: Coord, count: usize, repeat: bool) -> Option<Coord> {
+        let skip = usize::from(self.till && repeat);
+        let found = match self.rel {
+            Rel::Prior => line[..x.min(line.len()).saturating_sub(skip)]
+                .iter()
+                .enumerate()
+                .rev()
+                .filter(|(_, &ch)| ch == self.ch)
+                .nth(count.max(1) - 1)
+                .map(|(x, _)| x),
+            _ => line
+                .iter()
+                .enumerate()
+                .skip(x + 1 + skip)
+                .filter(|(_, &ch)| ch == self.ch)
+                .nth(count.max(1) - 1)
+                .map(|(x, _)| x),
+        }?;
+        Some(match (self.till, self.rel) {
+            (false, _) => found,
+            (true, Rel::Prior) => found + 1,
+            (true, _) => found - 1,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_and_tills_repeat_past_adjacent_matches() {
+        let line: Vec<char> = "a,b,c,d".chars().collect();
+        let find = |rel, till| CharFind { ch: ',', rel, till };
+        assert_eq!(find(Rel::Next, false).target(&line, 0, 2, false), Some(3));
+        assert_eq!(find(Rel::Next, true).target(&line, 0, 1, false), Some(0));
+        assert_eq!(find(Rel::Next, true).target(&line, 0, 1, true), Some(2));
+        assert_eq!(find(Rel::Prior, false).target(&line, 6, 1, false), Some(5));
+        assert_eq!(find(Rel::Prior, true).target(&line, 6, 1, false), Some(6));
+        assert_eq!(find(Rel::Prior, true).target(&line, 6, 1, true), Some(4));
+        assert_eq!(find(Rel::Next, false).target(&line, 5, 1, false), None);
+    }
+}
@@ -2,7 +2,18 @@
 pub enum Noun {
     Char,
     Word,
+    /// A run of anything but whitespace.
+    BigWord,
     Line,
+    /// A line's text after its indent.
+    Text,
+    Doc,
+    /// A run of non-empty lines, as `{` and `}` move by.
+    Paragraph,
+    /// A bracket and the one that matches it, as `%` jumps between.
+    Bracket,
+    /// The lines showing in the view.
+    Screen,
 }
 
 impl std::str::FromStr for Noun {
@@ -12,7 +23,13 @@ impl std::str::FromStr for Noun {
         match s {
             "char" => Ok(Noun::Char),
             "word" => Ok(Noun::Word),
+            "WORD" => Ok(Noun::BigWord),
             "line" => Ok(Noun::Line),
+            "text" => Ok(Noun::Text),
+            "doc" => Ok(Noun::Doc),
+            "paragraph" => Ok(Noun::Paragraph),
+            "bracket" => Ok(Noun::Bracket),
+            "screen" => Ok(Noun::Screen),
             missing => Err(Self::Err::new(format!("{} is not a valid Noun", missing))),
         }
     }
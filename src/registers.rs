@@ -0,0 +1,328 @@
+use crate::error::{error, Result};
+use crate::key::Key;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub type RegistersRef = Rc<RefCell<Registers>>;
+
+static UNNAMED: char = '"';
+static BLACK_HOLE: char = '_';
+static SMALL_DELETE: char = '-';
+static LAST_YANK: char = '0';
+static LAST_MACRO: char = '@';
+
+/// How many macros may be played before a key is typed again, which stops a macro that plays
+/// itself from going on forever.
+const MAX_MACRO_PLAYS: usize = 100;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RegisterKind {
+    Charwise,
+    Linewise,
+    /// A rectangle of text, one entry of `lines` per row.
+    Blockwise,
+}
+
+/// The contents of a register. `lines` holds the text split on newlines, so charwise text that
+/// spans rows has more than one entry.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Register {
+    pub kind: RegisterKind,
+    pub lines: Vec<String>,
+}
+
+impl Register {
+    pub fn charwise(lines: Vec<String>) -> Self {
+        Self {
+            kind: RegisterKind::Charwise,
+            lines,
+        }
+    }
+    pub fn linewise(lines: Vec<String>) -> Self {
+        Self {
+            kind: RegisterKind::Linewise,
+            lines,
+        }
+    }
+    pub fn blockwise(lines: Vec<String>) -> Self {
+        Self {
+            kind: RegisterKind::Blockwise,
+            lines,
+        }
+    }
+    /// The text this register puts when given a count.
+    #[must_use]
+    pub fn repeated(&self, count: usize) -> Self {
+        let mut lines = self.lines.clone();
+        match self.kind {
+            RegisterKind::Linewise => {
+                lines = self
+                    .lines
+                    .iter()
+                    .cycle()
+                    .take(self.lines.len() * count)
+                    .cloned()
+                    .collect()
+            }
+            RegisterKind::Blockwise => lines.iter_mut().for_each(|line| *line = line.repeat(count)),
+            RegisterKind::Charwise => {
+                for _ in 1..count {
+                    let mut copy = self.lines.clone().into_iter();
+                    if let (Some(last), Some(first)) = (lines.last_mut(), copy.next()) {
+                        last.push_str(&first);
+                    }
+                    lines.extend(copy);
+                }
+            }
+        }
+        Self {
+            kind: self.kind,
+            lines,
+        }
+    }
+    pub fn is_multiline(&self) -> bool {
+        self.kind == RegisterKind::Linewise || self.lines.len() > 1
+    }
+    fn append(&mut self, other: Register) {
+        match (self.kind, other.kind) {
+            (RegisterKind::Charwise, RegisterKind::Charwise) => {
+                let mut other_lines = other.lines.into_iter();
+                if let (Some(last), Some(first)) = (self.lines.last_mut(), other_lines.next()) {
+                    last.push_str(&first);
+                }
+                self.lines.extend(other_lines);
+            }
+            _ => {
+                // Appending anything linewise makes the whole register linewise.
+                self.kind = RegisterKind::Linewise;
+                self.lines.extend(other.lines);
+            }
+        }
+    }
+}
+
+/// Vim-style registers: the unnamed register, named registers `a`-`z` (uppercase appends),
+/// `0` for the last yank, `1`-`9` for the delete history, `-` for small deletes and the `_`
+/// black hole. Macros are recorded into them as text, so they can be put, edited and yanked
+/// back.
+#[derive(Default, Debug)]
+pub struct Registers {
+    map: HashMap<char, Register>,
+    /// The register a macro is being recorded into, and the keys typed since recording began.
+    recording: Option<(char, Vec<Key>)>,
+    /// The register last played, which `@@` plays again.
+    last_macro: Option<char>,
+    /// How many macros have been played since a key was last typed.
+    macro_plays: usize,
+}
+
+impl Registers {
+    pub fn new() -> RegistersRef {
+        Rc::new(RefCell::new(Self::default()))
+    }
+    pub fn is_valid_name(name: char) -> bool {
+        name.is_ascii_alphanumeric() || [UNNAMED, BLACK_HOLE, SMALL_DELETE].contains(&name)
+    }
+    /// All the register names that may be selected with a `"x` prefix.
+    pub fn names() -> impl Iterator<Item = char> {
+        ('a'..='z')
+            .chain('A'..='Z')
+            .chain('0'..='9')
+            .chain([UNNAMED, BLACK_HOLE, SMALL_DELETE])
+    }
+    /// Whether a macro may be recorded into register `name` with `q`.
+    pub fn is_macro_name(name: char) -> bool {
+        name.is_ascii_alphanumeric() || name == UNNAMED
+    }
+    #[must_use]
+    pub fn get(&self, name: Option<char>) -> Option<&Register> {
+        let name = name.unwrap_or(UNNAMED).to_ascii_lowercase();
+        self.map.get(&name)
+    }
+    /// Stores yanked text. Without an explicit register the text lands in `0`.
+    pub fn yank(&mut self, name: Option<char>, register: Register) {
+        match name {
+            Some(name) if name == BLACK_HOLE => {}
+            Some(name) if name != UNNAMED => self.set_named(name, register),
+            _ => {
+                self.map.insert(LAST_YANK, register.clone());
+                self.map.insert(UNNAMED, register);
+            }
+        }
+    }
+    /// Stores deleted text. Without an explicit register, multi-line deletes shift the numbered
+    /// history `1`-`9` and smaller ones land in `-`.
+    pub fn delete(&mut self, name: Option<char>, register: Register) {
+        match name {
+            Some(name) if name == BLACK_HOLE => {}
+            Some(name) if name != UNNAMED => self.set_named(name, register),
+            _ => {
+                if register.is_multiline() {
+                    self.shift_delete_history();
+                    self.map.insert('1', register.clone());
+                } else {
+                    self.map.insert(SMALL_DELETE, register.clone());
+                }
+                self.map.insert(UNNAMED, register);
+            }
+        }
+    }
+    /// The register a macro is being recorded into, if any.
+    pub fn recording(&self) -> Option<char> {
+        self.recording.as_ref().map(|(name, _)| *name)
+    }
+    pub fn start_recording(&mut self, name: char) {
+        self.recording = Some((name, Vec::new()));
+    }
+    /// Notes a key typed at the keyboard, recording it if a macro is being recorded. Keys that
+    /// macros play aren't typed, so they're never recorded.
+    pub fn key_typed(&mut self, key: Key) {
+        self.macro_plays = 0;
+        if let Some((_, keys)) = self.recording.as_mut() {
+            keys.push(key);
+        }
+    }
+    /// Stores the keys typed since recording began in the register being recorded into, but for
+    /// the `q` that stopped it.
+    pub fn stop_recording(&mut self) {
+        let (name, mut keys) = match self.recording.take() {
+            Some(recording) => recording,
+            None => return,
+        };
+        if keys.last() == Some(&Key::Utf8('q')) {
+            keys.pop();
+        }
+        let text: String = keys.into_iter().map(Key::to_macro_text).collect();
+        let register = Register::charwise(text.split('\n').map(str::to_string).collect());
+        if name == UNNAMED {
+            self.map.insert(UNNAMED, register);
+        } else {
+            self.store_named(name, register);
+        }
+    }
+    /// The keys of the macro in register `name`, or for `@`, in the register last played.
+    pub fn play(&mut self, name: char) -> Result<Vec<Key>> {
+        let name = if name == LAST_MACRO {
+            self.last_macro
+                .ok_or_else(|| error!("E748: No previously used register"))?
+        } else {
+            name
+        };
+        if self.macro_plays >= MAX_MACRO_PLAYS {
+            return Err(error!("E223: recursive mapping"));
+        }
+        let register = self
+            .get(Some(name))
+            .ok_or_else(|| error!("Nothing in register {}", name))?;
+        let mut text = register.lines.join("\n");
+        if register.kind == RegisterKind::Linewise {
+            text.push('\n');
+        }
+        self.macro_plays += 1;
+        self.last_macro = Some(name);
+        Ok(Key::parse_macro_text(&text))
+    }
+    fn set_named(&mut self, name: char, register: Register) {
+        self.store_named(name, register);
+        if let Some(register) = self.map.get(&name.to_ascii_lowercase()).cloned() {
+            self.map.insert(UNNAMED, register);
+        }
+    }
+    fn store_named(&mut self, name: char, register: Register) {
+        if name.is_ascii_uppercase() {
+            let name = name.to_ascii_lowercase();
+            match self.map.get_mut(&name) {
+                Some(existing) => existing.append(register),
+                None => {
+                    self.map.insert(name, register);
+                }
+            }
+        } else {
+            self.map.insert(name, register);
+        }
+    }
+    fn shift_delete_history(&mut self) {
+        for i in (1..9).rev() {
+            let from = char::from_digit(i, 10).unwrap();
+            let to = char::from_digit(i + 1, 10).unwrap();
+            if let Some(register) = self.map.remove(&from) {
+                self.map.insert(to, register);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(register: Option<&Register>) -> Option<Vec<String>> {
+        register.map(|register| register.lines.clone())
+    }
+
+    #[test]
+    fn named_registers_append_with_uppercase() {
+        let mut registers = Registers::default();
+        registers.yank(Some('a'), Register::charwise(vec!["foo".to_string()]));
+        registers.yank(Some('A'), Register::charwise(vec!["bar".to_string()]));
+        assert_eq!(
+            text(registers.get(Some('a'))),
+            Some(vec!["foobar".to_string()])
+        );
+        assert_eq!(text(registers.get(None)), Some(vec!["foobar".to_string()]));
+        // The last yank register is only written by unnamed yanks.
+        assert_eq!(registers.get(Some('0')), None);
+    }
+
+    #[test]
+    fn deletes_shift_numbered_history() {
+        let mut registers = Registers::default();
+        for i in 0..10 {
+            registers.delete(None, Register::linewise(vec![i.to_string()]));
+        }
+        assert_eq!(text(registers.get(Some('1'))), Some(vec!["9".to_string()]));
+        assert_eq!(text(registers.get(Some('9'))), Some(vec!["1".to_string()]));
+        registers.delete(None, Register::charwise(vec!["x".to_string()]));
+        assert_eq!(text(registers.get(Some('-'))), Some(vec!["x".to_string()]));
+        assert_eq!(text(registers.get(Some('1'))), Some(vec!["9".to_string()]));
+    }
+
+    #[test]
+    fn repeated_charwise_text_joins_copies() {
+        let register = Register::charwise(vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(
+            register.repeated(2).lines,
+            vec!["a".to_string(), "ba".to_string(), "b".to_string()]
+        );
+    }
+
+    #[test]
+    fn macros_record_typed_keys() -> Result<()> {
+        let mut registers = Registers::default();
+        registers.start_recording('a');
+        for key in [Key::Utf8('x'), Key::Enter, Key::Utf8('q')] {
+            registers.key_typed(key);
+        }
+        registers.stop_recording();
+        assert_eq!(
+            text(registers.get(Some('a'))),
+            Some(vec!["x\r".to_string()])
+        );
+        // Recording doesn't touch the unnamed register.
+        assert_eq!(registers.get(None), None);
+        assert_eq!(registers.play('@').ok(), None);
+        assert_eq!(registers.play('a')?, vec![Key::Utf8('x'), Key::Enter]);
+        assert_eq!(registers.play('@')?, vec![Key::Utf8('x'), Key::Enter]);
+        assert!(registers.play('b').is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn black_hole_keeps_nothing() {
+        let mut registers = Registers::default();
+        registers.yank(None, Register::charwise(vec!["keep".to_string()]));
+        registers.delete(Some('_'), Register::charwise(vec!["lose".to_string()]));
+        assert_eq!(text(registers.get(None)), Some(vec!["keep".to_string()]));
+    }
+}
@@ -2,6 +2,7 @@
 pub enum Rel {
     Prior,
     Begin,
+    Middle,
     End,
     Next,
 }
@@ -13,6 +14,7 @@ impl std::str::FromStr for Rel {
         match s {
             "prior" => Ok(Rel::Prior),
             "begin" => Ok(Rel::Begin),
+            "middle" => Ok(Rel::Middle),
             "end" => Ok(Rel::End),
             "next" => Ok(Rel::Next),
             missing => Err(Self::Err::new(format!("{} is not a valid Rel", missing))),
@@ -1,12 +1,54 @@
-use crate::classify::classify;
-use crate::consts::{BLANKS, TAB_STOP_SIZE};
-use crate::types::{Coord, SafeCoordCast};
+use crate::classify::WordChars;
+use crate::consts::BLANKS;
+use crate::types::Coord;
+use crate::utils::grapheme_width;
 use std::ops::{Range, RangeBounds};
+use unicode_segmentation::UnicodeSegmentation;
 
-#[derive(Clone, Default, Debug)]
+/// Where a grapheme cluster starts in a row's chars, and how many cells it takes on screen.
+/// Tabs take however many cells it is to the next tab stop, so their width is left at zero.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+struct Cluster {
+    x: Coord,
+    width: Coord,
+}
+
+/// A grapheme cluster of a row as it's drawn, `width` cells wide from column `col`.
+#[derive(Debug)]
+pub struct RenderCluster<'a> {
+    pub x: Coord,
+    pub chars: &'a [char],
+    pub col: Coord,
+    pub width: Coord,
+}
+
+impl RenderCluster<'_> {
+    /// The chars to draw for the cluster. Tabs are drawn as blanks, and combining marks with
+    /// nothing before them to combine with are drawn over a blank.
+    pub fn display_chars(&self) -> Vec<char> {
+        if self.chars == ['\t'] {
+            BLANKS[..self.width].to_vec()
+        } else if grapheme_width(&self.chars.iter().collect::<String>()) == 0 {
+            std::iter::once(' ')
+                .chain(self.chars.iter().copied())
+                .collect()
+        } else {
+            self.chars.to_vec()
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Row {
     buf: Vec<char>,
-    render: Vec<char>,
+    /// Where each grapheme cluster in `buf` starts, then where the row ends.
+    clusters: Vec<Cluster>,
+}
+
+impl Default for Row {
+    fn default() -> Self {
+        Self::from_buf(Vec::new())
+    }
 }
 
 impl Row {
@@ -19,14 +61,14 @@ impl Row {
     // }
     pub fn from_buf(buf: Vec<char>) -> Self {
         Self {
-            render: Self::renderize(&buf),
+            clusters: Self::clusterize(&buf),
             buf,
         }
     }
     pub fn from_line(line: &str) -> Self {
         let buf: Vec<char> = line.chars().collect();
         Self {
-            render: Self::renderize(&buf),
+            clusters: Self::clusterize(&buf),
             buf,
         }
     }
@@ -39,14 +81,14 @@ impl Row {
         let mut buf = first.buf[..first_index].to_vec();
         buf.extend(&second.buf[second_index..]);
         Self {
-            render: Self::renderize(&buf),
+            clusters: Self::clusterize(&buf),
             buf,
         }
     }
     pub fn from_chars(chs: &[char]) -> Self {
         let mut slf = Self {
             buf: chs.to_vec(),
-            render: Vec::new(),
+            clusters: Vec::new(),
         };
         slf.update_render();
         slf
@@ -71,19 +113,100 @@ impl Row {
     //     self.render.len()
     // }
 
-    /// Adjust the render column to account for tabs.
-    pub fn cursor_to_render_col(&self, cursor: Coord) -> Coord {
-        let mut render_x: usize = 0;
-        for (i, &ch) in self.buf.iter().enumerate() {
-            if i == cursor {
-                break;
-            }
-            if ch == '\t' {
-                render_x += (TAB_STOP_SIZE - 1) - render_x % TAB_STOP_SIZE;
-            }
-            render_x += 1;
+    /// The index into `clusters` of the cluster `x` is in, or of the end of the row if `x` is
+    /// past it.
+    fn cluster_index(&self, x: Coord) -> usize {
+        self.clusters
+            .partition_point(|cluster| cluster.x <= x)
+            .saturating_sub(1)
+    }
+
+    /// Each cluster along with the screen column it starts at when tabs stop every `tabstop`
+    /// columns, ending with where the row ends.
+    fn layout(&self, tabstop: Coord) -> impl Iterator<Item = (Cluster, Coord)> + '_ {
+        let mut col = 0;
+        self.clusters.iter().map(move |&cluster| {
+            let start = col;
+            col += if self.buf.get(cluster.x) == Some(&'\t') {
+                tabstop - col % tabstop
+            } else {
+                cluster.width
+            };
+            (cluster, start)
+        })
+    }
+
+    /// The screen column the grapheme at `cursor` starts at, accounting for tabs and wide chars.
+    pub fn cursor_to_render_col(&self, cursor: Coord, tabstop: Coord) -> Coord {
+        let (_, col) = self
+            .layout(tabstop)
+            .nth(self.cluster_index(cursor))
+            .unwrap();
+        col
+    }
+
+    /// How many screen columns the whole row takes.
+    pub fn render_width(&self, tabstop: Coord) -> Coord {
+        let (_, col) = self.layout(tabstop).last().unwrap();
+        col
+    }
+
+    /// The start of the grapheme `x` is in.
+    pub fn grapheme_start(&self, x: Coord) -> Coord {
+        self.clusters[self.cluster_index(x)].x
+    }
+
+    /// The start of the grapheme after the one `x` is in, or the end of the row.
+    pub fn next_grapheme(&self, x: Coord) -> Coord {
+        self.clusters
+            .get(self.cluster_index(x) + 1)
+            .map_or(self.buf.len(), |cluster| cluster.x)
+    }
+
+    /// Where a selection that ends on `x` stops: just past the grapheme `x` is in, or past the
+    /// line break at the end of the row.
+    pub fn selection_end(&self, x: Coord) -> Coord {
+        if x < self.buf.len() {
+            self.next_grapheme(x)
+        } else {
+            x + 1
+        }
+    }
+
+    /// The start of the grapheme before the one `x` is in, or of that one if `x` is partway
+    /// into it.
+    pub fn prev_grapheme(&self, x: Coord) -> Coord {
+        let index = self.cluster_index(x);
+        if self.clusters[index].x < x {
+            self.clusters[index].x
+        } else {
+            self.clusters[index.saturating_sub(1)].x
+        }
+    }
+
+    /// The graphemes that are at least partly within the screen columns `cols`.
+    pub fn render_clusters(&self, cols: Range<Coord>, tabstop: Coord) -> Vec<RenderCluster<'_>> {
+        let layout: Vec<(Cluster, Coord)> = self.layout(tabstop).collect();
+        layout
+            .windows(2)
+            .filter(|pair| pair[1].1 > cols.start && pair[0].1 < cols.end)
+            .map(|pair| RenderCluster {
+                x: pair[0].0.x,
+                chars: &self.buf[pair[0].0.x..pair[1].0.x],
+                col: pair[0].1,
+                width: pair[1].1 - pair[0].1,
+            })
+            .collect()
+    }
+
+    /// The chars shown in screen columns `cols`, as a range of indexes into the row. Graphemes
+    /// straddling either edge, such as tabs and wide chars, count as inside.
+    pub fn render_cols_to_range(&self, cols: Range<Coord>, tabstop: Coord) -> Range<Coord> {
+        let clusters = self.render_clusters(cols, tabstop);
+        match (clusters.first(), clusters.last()) {
+            (Some(first), Some(last)) => first.x..last.x + last.chars.len(),
+            _ => self.len()..self.len(),
         }
-        render_x.as_coord()
     }
 
     pub fn char_at(&self, x: Coord) -> Option<char> {
@@ -102,7 +225,8 @@ impl Row {
     }
 
     pub fn get_slice(&self, range: Range<usize>) -> &[char] {
-        &self.buf[range.start..range.end.clamp(range.start, self.buf.len())]
+        let start = range.start.min(self.buf.len());
+        &self.buf[start..range.end.clamp(start, self.buf.len())]
     }
 
     pub fn as_slice(&self) -> &[char] {
@@ -110,29 +234,24 @@ impl Row {
     }
 
     pub fn update_render(&mut self) {
-        Self::renderize_in_place(&self.buf, &mut self.render);
-    }
-    fn renderize(buf: &[char]) -> Vec<char> {
-        let mut render = Default::default();
-        Self::renderize_in_place(buf, &mut render);
-        render
-    }
-    fn renderize_in_place(buf: &[char], render: &mut Vec<char>) {
-        // Deal with rendering Tabs.
-        let tabs = buf.iter().copied().filter(|&x| x == '\t').count();
-        if tabs == 0 {
-            render.truncate(0);
-            render.extend_from_slice(buf);
-        } else {
-            render.reserve(buf.len() + tabs * (TAB_STOP_SIZE - 1));
-            for ch in buf.iter().copied() {
-                if ch == '\t' {
-                    render.extend_from_slice(&BLANKS[..TAB_STOP_SIZE]);
-                } else {
-                    render.push(ch);
-                }
-            }
+        self.clusters = Self::clusterize(&self.buf);
+    }
+    fn clusterize(buf: &[char]) -> Vec<Cluster> {
+        let text: String = buf.iter().collect();
+        let mut clusters = Vec::new();
+        let mut x = 0;
+        for grapheme in text.graphemes(true) {
+            let width = if grapheme == "\t" {
+                0
+            } else {
+                // Marks with nothing to combine with get a cell of their own.
+                grapheme_width(grapheme).max(1)
+            };
+            clusters.push(Cluster { x, width });
+            x += grapheme.chars().count();
         }
+        clusters.push(Cluster { x, width: 0 });
+        clusters
     }
 
     pub fn splice(&self, range: impl RangeBounds<Coord>, text: &str) -> Self {
@@ -153,13 +272,13 @@ impl Row {
     }
     */
 
-    pub fn next_word_break(&self, x: Coord) -> Coord {
+    pub fn next_word_break(&self, x: Coord, word_chars: &WordChars) -> Coord {
         if self.buf.len() <= x + 1 {
             self.buf.len()
         } else {
-            let start_class = classify(self.buf[x]);
+            let start_class = word_chars.classify(self.buf[x]);
             for (i, &ch) in self.buf[x + 1..].iter().enumerate() {
-                let next_class = classify(ch);
+                let next_class = word_chars.classify(ch);
                 if next_class != start_class {
                     return x + i + 1;
                 }
@@ -169,15 +288,15 @@ impl Row {
     }
 
     #[allow(dead_code)]
-    pub fn prev_word_break(&self, mut x: Coord) -> Coord {
+    pub fn prev_word_break(&self, mut x: Coord, word_chars: &WordChars) -> Coord {
         x = x.clamp(0, self.buf.len());
         if x <= 1 {
             0
         } else {
-            let start_class = classify(self.buf[x - 1]);
+            let start_class = word_chars.classify(self.buf[x - 1]);
             for i in 1..=x {
                 let ch = self.buf[x - i];
-                let next_class = classify(ch);
+                let next_class = word_chars.classify(ch);
                 if next_class != start_class {
                     return x - i + 1;
                 }
@@ -189,8 +308,57 @@ impl Row {
     //     x = x.clamp(0, self.buf.len());
     //     self.buf[x..].iter()
     // }
-    pub fn render_chars_from(&self, mut x: Coord) -> std::slice::Iter<'_, char> {
-        x = x.clamp(0, self.render.len());
-        self.render[x..].iter()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clusters_graphemes_into_cells() {
+        // A tab, a wide char, an e with a combining acute accent, and a lone accent.
+        let row = Row::from_line("\t中e\u{301}x");
+        assert_eq!(row.cursor_to_render_col(1, 4), 4);
+        assert_eq!(row.cursor_to_render_col(2, 4), 6);
+        assert_eq!(row.cursor_to_render_col(3, 4), 6);
+        assert_eq!(row.cursor_to_render_col(4, 4), 7);
+        assert_eq!(row.render_width(4), 8);
+        assert_eq!(row.grapheme_start(3), 2);
+        assert_eq!(row.next_grapheme(2), 4);
+        assert_eq!(row.next_grapheme(5), 5);
+        assert_eq!(row.prev_grapheme(4), 2);
+        assert_eq!(row.prev_grapheme(3), 2);
+        assert_eq!(row.prev_grapheme(0), 0);
+        assert_eq!(Row::from_line("\u{301}a").render_width(4), 2);
+    }
+
+    #[test]
+    fn tabs_advance_to_the_next_stop() {
+        let row = Row::from_line("ab\tc\t\td");
+        assert_eq!(row.cursor_to_render_col(3, 4), 4);
+        assert_eq!(row.cursor_to_render_col(3, 8), 8);
+        assert_eq!(row.cursor_to_render_col(6, 4), 12);
+        assert_eq!(row.cursor_to_render_col(6, 3), 9);
+        assert_eq!(row.render_width(2), 9);
+    }
+
+    #[test]
+    fn renders_clusters_in_columns() {
+        let row = Row::from_line("a\t中\u{301}b");
+        let clusters: Vec<_> = row
+            .render_clusters(5..6, 4)
+            .iter()
+            .map(|cluster| (cluster.x, cluster.col, cluster.width))
+            .collect();
+        assert_eq!(clusters, vec![(2, 4, 2)]);
+        // Graphemes partly in the columns count as in them.
+        assert_eq!(row.render_cols_to_range(2..5, 4), 1..4);
+        assert_eq!(row.render_cols_to_range(20..21, 4), 5..5);
+        let tab = &row.render_clusters(1..2, 4)[0];
+        assert_eq!(tab.display_chars(), vec![' '; 3]);
+        assert_eq!(
+            Row::from_line("\u{301}").render_clusters(0..1, 4)[0].display_chars(),
+            vec![' ', '\u{301}']
+        );
     }
 }
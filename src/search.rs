@@ -0,0 +1,98 @@
+use regex::Regex;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub type SearchRef = Rc<RefCell<Search>>;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+impl Direction {
+    pub fn reversed(self) -> Self {
+        match self {
+            Self::Forward => Self::Backward,
+            Self::Backward => Self::Forward,
+        }
+    }
+    pub fn prompt(self) -> char {
+        match self {
+            Self::Forward => '/',
+            Self::Backward => '?',
+        }
+    }
+}
+
+impl std::str::FromStr for Direction {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "forward" => Ok(Self::Forward),
+            "backward" => Ok(Self::Backward),
+            missing => Err(Self::Err::new(format!(
+                "{} is not a valid search direction",
+                missing
+            ))),
+        }
+    }
+}
+
+/// Search state shared between the command line (which edits the pattern) and the doc views
+/// (which jump to and highlight matches).
+#[derive(Default, Debug)]
+pub struct Search {
+    last: Option<(Regex, Direction)>,
+    /// The pattern being typed at the `/` or `?` prompt, if it compiles.
+    incremental: Option<Regex>,
+}
+
+impl Search {
+    pub fn new() -> SearchRef {
+        Rc::new(RefCell::new(Self::default()))
+    }
+    /// Records a search typed at the prompt. An empty pattern reuses the last one.
+    pub fn commit(&mut self, pattern: &str, direction: Direction) -> crate::error::Result<()> {
+        self.incremental = None;
+        if pattern.is_empty() {
+            match self.last {
+                Some((ref regex, _)) => {
+                    self.last = Some((regex.clone(), direction));
+                    Ok(())
+                }
+                None => Err(crate::error::error!("No previous regular expression")),
+            }
+        } else {
+            match Regex::new(pattern) {
+                Ok(regex) => {
+                    self.last = Some((regex, direction));
+                    Ok(())
+                }
+                Err(error) => Err(crate::error::error!("Invalid pattern: {}", error)),
+            }
+        }
+    }
+    #[must_use]
+    pub fn get_last(&self) -> Option<(Regex, Direction)> {
+        self.last.clone()
+    }
+    pub fn set_incremental(&mut self, pattern: &str) {
+        self.incremental = if pattern.is_empty() {
+            None
+        } else {
+            Regex::new(pattern).ok()
+        };
+    }
+    pub fn clear_incremental(&mut self) {
+        self.incremental = None;
+    }
+    /// The pattern whose matches should be highlighted right now.
+    #[must_use]
+    pub fn get_highlight(&self) -> Option<Regex> {
+        self.incremental
+            .clone()
+            .or_else(|| self.last.as_ref().map(|(regex, _)| regex.clone()))
+    }
+}
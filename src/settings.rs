@@ -1,44 +1,176 @@
-use crate::color::{BgColor, FgColor};
+use crate::color::ColorDepth;
 use crate::error::{ErrorContext, Result};
+use crate::gutter::LineNumbers;
+use crate::indent::Indent;
+use crate::statusline::StatusLine;
+use crate::syntax::Syntaxes;
+use crate::theme::Theme;
 use serde_derive::Deserialize;
+use std::collections::HashMap;
 use std::fs;
 
 // Top level struct to hold the TOML data.
 #[derive(Deserialize)]
 pub struct Settings {
-    pub display: DisplaySettings,
     pub debug: DebugSettings,
+    #[serde(default)]
+    pub terminal: TerminalSettings,
+    #[serde(default)]
+    pub theme: ThemeSettings,
+    /// The theme named by `theme.name`.
+    #[serde(skip)]
+    pub colors: Theme,
+    #[serde(default)]
+    pub undo: UndoSettings,
+    #[serde(default)]
+    pub status_line: StatusLineSettings,
+    #[serde(default)]
+    pub gutter: GutterSettings,
+    /// How new buffers are indented.
+    #[serde(default)]
+    pub indent: Indent,
+    #[serde(default)]
+    pub list: ListSettings,
+    /// The chars besides letters, numbers and `_` that words are made of, by file type, like
+    /// vim's `iskeyword`.
+    #[serde(default)]
+    pub word_chars: HashMap<String, String>,
+    #[serde(default)]
+    pub syntax: SyntaxSettings,
+    /// The built-in grammars, along with any from `syntax.directory`.
+    #[serde(skip)]
+    pub syntaxes: Syntaxes,
+}
+
+#[derive(Deserialize, Default)]
+pub struct TerminalSettings {
+    /// How many colors the terminal can show: `"16"`, `"256"` or `"truecolor"`. Left out, it's
+    /// worked out from `COLORTERM` and `TERM`, and RGB colors are drawn as the nearest color
+    /// the terminal has.
+    pub color_depth: Option<ColorDepth>,
 }
 
 #[derive(Deserialize)]
-pub struct DebugSettings {
-    pub write_writes: bool,
+#[serde(default)]
+pub struct ThemeSettings {
+    /// Which theme to draw with: one of the built-in `default` or `light`, or the name of a
+    /// `.toml` file in `directory`.
+    pub name: String,
+    /// A directory of theme `.toml` files, each named for the theme in it.
+    pub directory: Option<String>,
+}
+
+impl Default for ThemeSettings {
+    fn default() -> Self {
+        Self {
+            name: "default".to_string(),
+            directory: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct SyntaxSettings {
+    /// A directory of grammar `.toml` files to highlight with, besides the built-in ones. A
+    /// grammar with the same name as a built-in one takes its place.
+    pub directory: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct GutterSettings {
+    /// How lines are numbered: `none`, `absolute`, `relative` to the cursor, or `hybrid`.
+    #[serde(default)]
+    pub line_numbers: LineNumbers,
 }
 
 #[derive(Deserialize)]
-pub struct DisplaySettings {
-    pub fg: FgColor,
-    pub bg: BgColor,
+#[serde(default)]
+pub struct ListSettings {
+    /// Whether windows start out in list mode, which draws whitespace that's otherwise hard to
+    /// see. `:set list` and `:set nolist` turn it on and off for a window.
+    pub enabled: bool,
+    /// What a tab is drawn as, like the `tab:` of vim's `listchars`: its first char, then its
+    /// second repeated to fill the tab. A third char, if there is one, ends the tab.
+    pub tab: String,
+    /// What each space at the end of a line is drawn as, or nothing to leave them blank.
+    pub trail: String,
+    /// What's drawn past the end of each line, if anything.
+    pub eol: String,
+}
+
+impl Default for ListSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            tab: "> ".to_string(),
+            trail: "-".to_string(),
+            eol: "$".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct StatusLineSettings {
+    /// The template for the status line at the bottom of each window. See `StatusLine` for what
+    /// it can show.
+    #[serde(default)]
+    pub format: StatusLine,
+}
+
+#[derive(Deserialize, Default)]
+pub struct UndoSettings {
+    /// Keep each document's undo tree in a hidden `.name.wim-undo` file beside it, so that undo
+    /// history survives closing and reopening the document.
+    pub file: bool,
+}
+
+#[derive(Deserialize)]
+pub struct DebugSettings {
+    pub write_writes: bool,
 }
 
 impl Settings {
     pub fn load() -> Result<Self> {
         let filename = "settings.toml";
         let contents = fs::read_to_string(filename)?;
-        toml::from_str(&contents).context("load-settings")
+        let mut settings: Self = toml::from_str(&contents).context("load-settings")?;
+        settings.colors = Theme::load(&settings.theme.name, settings.theme.directory.as_deref())?;
+        if let Some(directory) = &settings.syntax.directory {
+            settings.syntaxes.load_directory(directory)?;
+        }
+        Ok(settings)
     }
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Self {
-            display: DisplaySettings {
-                fg: FgColor::White,
-                bg: BgColor::Black,
-            },
             debug: DebugSettings {
                 write_writes: false,
             },
+            terminal: Default::default(),
+            theme: Default::default(),
+            colors: Default::default(),
+            undo: Default::default(),
+            status_line: Default::default(),
+            gutter: Default::default(),
+            indent: Default::default(),
+            list: Default::default(),
+            word_chars: Default::default(),
+            syntax: Default::default(),
+            syntaxes: Default::default(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_settings_toml() -> Result<()> {
+        let settings = Settings::load()?;
+        assert_eq!(settings.status_line.format, StatusLine::default());
+        Ok(())
+    }
+}
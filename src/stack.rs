@@ -0,0 +1,281 @@
+use crate::error::{ensure, Result};
+use crate::prelude::*;
+
+/// Which way a stack lays out its children.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Axis {
+    /// Side by side, left to right, as in an `HStack`.
+    Horizontal,
+    /// One above the other, top to bottom, as in a `VStack`.
+    Vertical,
+}
+
+impl Axis {
+    fn length(self, size: Size) -> Coord {
+        match self {
+            Self::Horizontal => size.width,
+            Self::Vertical => size.height,
+        }
+    }
+    /// The cells left between neighbouring windows: a border between windows side by side, and
+    /// none between windows one above the other, whose status lines already divide them.
+    fn gap(self) -> Coord {
+        match self {
+            Self::Horizontal => 1,
+            Self::Vertical => 0,
+        }
+    }
+    /// The least room a window is given along this axis: one cell, and going down, a line of
+    /// text above its status line.
+    pub fn min_window_length(self) -> Coord {
+        match self {
+            Self::Horizontal => 1,
+            Self::Vertical => 2,
+        }
+    }
+    /// The room a window needs along this axis to be split in two.
+    pub fn split_length(self) -> Coord {
+        2 * self.min_window_length() + self.gap()
+    }
+}
+
+/// The children of a `VStack` or `HStack` along with how much room each gets. Lengths are kept
+/// from one layout to the next so that resizing a window sticks.
+pub struct Stack {
+    axis: Axis,
+    view_keys: Vec<ViewKey>,
+    /// Each child's length along the axis as of the last layout, or zero for a child that still
+    /// needs to be given its share.
+    lengths: Vec<Coord>,
+    /// The number of cells left between neighbouring children, e.g. for a border.
+    gap: Coord,
+    /// The least room each child gets, when there's room enough for it.
+    min_length: Coord,
+}
+
+impl Stack {
+    pub fn new(axis: Axis, view_keys: Vec<ViewKey>) -> Self {
+        let lengths = vec![0; view_keys.len()];
+        Self {
+            axis,
+            view_keys,
+            lengths,
+            gap: axis.gap(),
+            min_length: axis.min_window_length(),
+        }
+    }
+    pub fn axis(&self) -> Axis {
+        self.axis
+    }
+    pub fn view_keys(&self) -> &[ViewKey] {
+        &self.view_keys
+    }
+    fn index_of(&self, vk: ViewKey) -> Result<usize> {
+        match self.view_keys.iter().position(|&child| child == vk) {
+            Some(index) => Ok(index),
+            None => Err(error!("{:?} is not in this stack", vk)),
+        }
+    }
+    /// Puts `new` just before `at`, splitting the room `at` had between them.
+    pub fn insert_before(&mut self, at: ViewKey, new: ViewKey) -> Result<()> {
+        let index = self.index_of(at)?;
+        let half = self.lengths[index] / 2;
+        self.lengths[index] -= half;
+        self.view_keys.insert(index, new);
+        self.lengths.insert(index, half);
+        Ok(())
+    }
+    /// Puts `new` in the place of `old`, with the room `old` had.
+    pub fn replace(&mut self, old: ViewKey, new: ViewKey) -> Result<()> {
+        let index = self.index_of(old)?;
+        self.view_keys[index] = new;
+        Ok(())
+    }
+    /// Takes `vk` out of the stack, giving its room to the child before it (or after it, if it
+    /// was first).
+    pub fn remove(&mut self, vk: ViewKey) -> Result<()> {
+        let index = self.index_of(vk)?;
+        ensure!(self.view_keys.len() > 1);
+        let freed = self.lengths.remove(index);
+        self.view_keys.remove(index);
+        let neighbour = index.saturating_sub(1);
+        if self.lengths[neighbour] > 0 {
+            self.lengths[neighbour] += freed + self.gap;
+        }
+        Ok(())
+    }
+    /// Grows (or with a negative `delta`, shrinks) `vk` at the expense of the child after it, or
+    /// the one before it if it's last. Every child keeps at least its least room.
+    pub fn resize(&mut self, vk: ViewKey, delta: isize) -> Result<()> {
+        let index = self.index_of(vk)?;
+        if self.view_keys.len() < 2 {
+            return Ok(());
+        }
+        let neighbour = if index + 1 < self.view_keys.len() {
+            index + 1
+        } else {
+            index - 1
+        };
+        let pair = self.lengths[index] + self.lengths[neighbour];
+        let min = self.min_length;
+        if pair < 2 * min {
+            return Ok(());
+        }
+        let length = (self.lengths[index] as isize + delta)
+            .clamp(min as isize, (pair - min) as isize) as Coord;
+        self.lengths[index] = length;
+        self.lengths[neighbour] = pair - length;
+        Ok(())
+    }
+    /// Gives every child the same room at the next layout.
+    pub fn equalize(&mut self) {
+        self.lengths.iter_mut().for_each(|length| *length = 0);
+    }
+    /// Fits the children's lengths to `size`, returning the rect of each child.
+    pub fn layout(&mut self, size: Size) -> Vec<(ViewKey, Rect)> {
+        let count = self.view_keys.len();
+        if count == 0 {
+            return Vec::new();
+        }
+        let gaps = self.gap * (count - 1);
+        let available = self.axis.length(size).saturating_sub(gaps);
+        self.fit(available);
+        let mut offset = 0;
+        self.view_keys
+            .iter()
+            .zip(self.lengths.iter())
+            .map(|(&vk, &length)| {
+                let rect = match self.axis {
+                    Axis::Horizontal => Rect {
+                        x: offset,
+                        y: 0,
+                        width: length,
+                        height: size.height,
+                    },
+                    Axis::Vertical => Rect {
+                        x: 0,
+                        y: offset,
+                        width: size.width,
+                        height: length,
+                    },
+                };
+                offset += length + self.gap;
+                (vk, rect)
+            })
+            .collect()
+    }
+    /// Where the gaps between children start along the axis, as of the last layout.
+    pub fn gap_offsets(&self) -> Vec<Coord> {
+        let mut offset = 0;
+        let mut offsets = Vec::new();
+        for length in self
+            .lengths
+            .iter()
+            .take(self.lengths.len().saturating_sub(1))
+        {
+            offset += length;
+            offsets.push(offset);
+            offset += self.gap;
+        }
+        offsets
+    }
+    /// Carries out the commands `ViewMap` uses to rearrange windows.
+    pub fn execute_command(&mut self, name: &str, args: &[Variant]) -> Result<Status> {
+        match (name, args) {
+            ("insert-child", [Variant::ViewKey(at), Variant::ViewKey(new)]) => {
+                self.insert_before(*at, *new)?
+            }
+            ("replace-child", [Variant::ViewKey(old), Variant::ViewKey(new)]) => {
+                self.replace(*old, *new)?
+            }
+            ("remove-child", [Variant::ViewKey(vk)]) => self.remove(*vk)?,
+            ("resize-child", [Variant::ViewKey(vk), Variant::Int(delta)]) => {
+                self.resize(*vk, *delta as isize)?
+            }
+            ("equalize", []) => self.equalize(),
+            _ => {
+                return Err(not_impl!(
+                    "Stack::execute_command does not impl {:?} {:?}",
+                    name,
+                    args
+                ))
+            }
+        }
+        Ok(Status::Ok)
+    }
+    fn fit(&mut self, available: Coord) {
+        let count = self.lengths.len();
+        if self.lengths.contains(&0) {
+            for (i, length) in self.lengths.iter_mut().enumerate() {
+                *length = available / count + usize::from(i < available % count);
+            }
+            return;
+        }
+        // Growing or shrinking the whole stack (as when the terminal is resized) is taken up by
+        // the last children first. Children only go below their least room when the stack has
+        // no more to give, and then may be left with none at all.
+        let total: Coord = self.lengths.iter().sum();
+        if total < available {
+            self.lengths[count - 1] += available - total;
+        } else {
+            let mut excess = total - available;
+            for min in [self.min_length, 0] {
+                for length in self.lengths.iter_mut().rev() {
+                    let taken = excess.min(length.saturating_sub(min));
+                    *length -= taken;
+                    excess -= taken;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lengths(stack: &mut Stack, height: Coord) -> Vec<Coord> {
+        stack
+            .layout(Size { width: 10, height })
+            .into_iter()
+            .map(|(_, rect)| rect.height)
+            .collect()
+    }
+
+    #[test]
+    fn splits_resizes_and_removes() -> Result<()> {
+        let (a, b, c) = (ViewKey::from(1), ViewKey::from(2), ViewKey::from(3));
+        let mut stack = Stack::new(Axis::Vertical, vec![a]);
+        assert_eq!(lengths(&mut stack, 10), vec![10]);
+        stack.insert_before(a, b)?;
+        assert_eq!(lengths(&mut stack, 10), vec![5, 5]);
+        stack.insert_before(a, c)?;
+        assert_eq!(lengths(&mut stack, 10), vec![5, 2, 3]);
+        stack.resize(c, 1)?;
+        assert_eq!(lengths(&mut stack, 10), vec![5, 3, 2]);
+        // Every window keeps a line of text above its status line.
+        stack.resize(a, -10)?;
+        assert_eq!(lengths(&mut stack, 10), vec![5, 3, 2]);
+        assert_eq!(lengths(&mut stack, 8), vec![4, 2, 2]);
+        stack.remove(b)?;
+        assert_eq!(lengths(&mut stack, 8), vec![6, 2]);
+        // Unless there's no room for it.
+        assert_eq!(lengths(&mut stack, 3), vec![2, 1]);
+        stack.equalize();
+        assert_eq!(lengths(&mut stack, 8), vec![4, 4]);
+        Ok(())
+    }
+
+    #[test]
+    fn leaves_gaps_between_children() {
+        let (a, b) = (ViewKey::from(1), ViewKey::from(2));
+        let mut stack = Stack::new(Axis::Horizontal, vec![a, b]);
+        let rects = stack.layout(Size {
+            width: 11,
+            height: 3,
+        });
+        assert_eq!(rects[0].1.width, 5);
+        assert_eq!(rects[1].1.x, 6);
+        assert_eq!(stack.gap_offsets(), vec![5]);
+    }
+}
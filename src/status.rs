@@ -1,8 +1,19 @@
+use crate::dk::DK;
 use std::time::Instant;
 
 #[derive(Debug)]
 pub enum Status {
-    Message { message: String, expiry: Instant },
+    Message {
+        message: String,
+        expiry: Instant,
+    },
+    /// An error to report, which stays shown until the next command.
+    Error(String),
+    /// Asks the main loop to dispatch `DK` next, ahead of any pending keys.
+    Dispatch(DK),
+    /// The command couldn't do anything, as when a motion can't move. Like vim's beep, this
+    /// stops the macro or other sequence of commands it was part of.
+    Failed,
     Quit,
     Ok,
 }
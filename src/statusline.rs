@@ -0,0 +1,233 @@
+use crate::error::{Error, Result};
+use crate::prelude::*;
+use serde_derive::Deserialize;
+
+/// What the status line shows when settings.toml doesn't say.
+static DEFAULT_TEMPLATE: &str =
+    " {mode} │ {file} {modified}{=}{filetype} │ {line}:{col} │ {percent} ";
+
+/// The template each window's status line is drawn from, as set by `format` under
+/// `[status_line]` in settings.toml. Text is shown as is, except that `{field}` is replaced with
+/// the window's `mode`, `file`, `modified` flag, `line`, `col`, `percent` through the file or
+/// `filetype`. Anything after `{=}` is pushed to the right edge. `{{` and `}}` are literal braces.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(try_from = "String")]
+pub struct StatusLine {
+    left: Vec<Segment>,
+    right: Vec<Segment>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Segment {
+    Text(String),
+    Field(Field),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Field {
+    Mode,
+    File,
+    Modified,
+    Line,
+    Column,
+    Percent,
+    FileType,
+}
+
+impl FromStr for Field {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "mode" => Ok(Self::Mode),
+            "file" => Ok(Self::File),
+            "modified" => Ok(Self::Modified),
+            "line" => Ok(Self::Line),
+            "col" => Ok(Self::Column),
+            "percent" => Ok(Self::Percent),
+            "filetype" => Ok(Self::FileType),
+            _ => Err(error!("'{{{}}}' is not a status line field", s)),
+        }
+    }
+}
+
+impl Field {
+    fn value(self, context: &dyn ViewContext) -> String {
+        let cursor = context
+            .get_property_pos(PROP_DOCVIEW_CURSOR_POS)
+            .unwrap_or_default();
+        match self {
+            Self::Mode => context
+                .get_property_string(PROP_DOCVIEW_MODE)
+                .unwrap_or_default(),
+            Self::File => context
+                .get_property_string(PROP_DOC_FILENAME)
+                .unwrap_or_else(|| "[No Name]".to_string()),
+            Self::Modified => {
+                if context.get_property_bool(PROP_DOC_IS_MODIFIED, false) {
+                    "[+]".to_string()
+                } else {
+                    String::new()
+                }
+            }
+            Self::Line => (cursor.y + 1).to_string(),
+            Self::Column => (cursor.x + 1).to_string(),
+            Self::Percent => {
+                let line_count = context.get_property_int(PROP_DOC_LINE_COUNT).unwrap_or(1);
+                format!("{}%", (cursor.y + 1) * 100 / line_count.max(1) as usize)
+            }
+            Self::FileType => context
+                .get_property_string(PROP_DOC_FILE_TYPE)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl StatusLine {
+    /// Fills in the template from `context`'s properties, padded or cut to `width` characters.
+    pub fn render(&self, context: &dyn ViewContext, width: Coord) -> String {
+        let render = |segments: &[Segment]| -> String {
+            segments
+                .iter()
+                .map(|segment| match segment {
+                    Segment::Text(text) => text.clone(),
+                    Segment::Field(field) => field.value(context),
+                })
+                .collect()
+        };
+        let left = render(&self.left);
+        let right = render(&self.right);
+        let padding = width.saturating_sub(left.chars().count() + right.chars().count());
+        format!("{}{}{}", left, " ".repeat(padding), right)
+            .chars()
+            .take(width)
+            .collect()
+    }
+}
+
+impl FromStr for StatusLine {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut sides: Vec<Vec<Segment>> = vec![Vec::new()];
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '{' | '}' if chars.peek() == Some(&ch) => {
+                    chars.next();
+                    text.push(ch);
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(ch) => name.push(ch),
+                            None => return Err(error!("unclosed '{{' in status line template")),
+                        }
+                    }
+                    let side = sides.last_mut().unwrap();
+                    if !text.is_empty() {
+                        side.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    if name == "=" {
+                        if sides.len() > 1 {
+                            return Err(error!("status line template has more than one '{{=}}'"));
+                        }
+                        sides.push(Vec::new());
+                    } else {
+                        side.push(Segment::Field(name.parse()?));
+                    }
+                }
+                '}' => return Err(error!("unmatched '}}' in status line template")),
+                ch => text.push(ch),
+            }
+        }
+        if !text.is_empty() {
+            sides.last_mut().unwrap().push(Segment::Text(text));
+        }
+        let right = if sides.len() > 1 {
+            sides.pop().unwrap()
+        } else {
+            Vec::new()
+        };
+        Ok(Self {
+            left: sides.pop().unwrap(),
+            right,
+        })
+    }
+}
+
+impl TryFrom<String> for StatusLine {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl Default for StatusLine {
+    fn default() -> Self {
+        DEFAULT_TEMPLATE.parse().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Window;
+
+    impl ViewContext for Window {
+        fn get_property(&self, property: &str) -> Option<Variant> {
+            if property == PROP_DOCVIEW_MODE {
+                Some(Variant::String("NORMAL".to_string()))
+            } else if property == PROP_DOC_FILENAME {
+                Some(Variant::String("main.rs".to_string()))
+            } else if property == PROP_DOC_IS_MODIFIED {
+                Some(Variant::Bool(true))
+            } else if property == PROP_DOCVIEW_CURSOR_POS {
+                Some(Variant::Pos(Pos { x: 4, y: 9 }))
+            } else if property == PROP_DOC_LINE_COUNT {
+                Some(Variant::Int(40))
+            } else if property == PROP_DOC_FILE_TYPE {
+                Some(Variant::String("rust".to_string()))
+            } else {
+                None
+            }
+        }
+    }
+
+    fn render(template: &str, width: Coord) -> String {
+        template
+            .parse::<StatusLine>()
+            .unwrap()
+            .render(&Window, width)
+    }
+
+    #[test]
+    fn fills_in_fields() {
+        assert_eq!(
+            render(
+                "{mode} {file}{modified}{=}{filetype} {line}:{col} {percent}",
+                40
+            ),
+            "NORMAL main.rs[+]          rust 10:5 25%"
+        );
+        assert_eq!(render("{{{line}}}", 6), "{10}  ");
+        assert_eq!(render("{file}{=}{line}", 4), "main");
+        assert_eq!(
+            StatusLine::default().render(&Window, 10).chars().count(),
+            10
+        );
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        assert!("{nope}".parse::<StatusLine>().is_err());
+        assert!("{file".parse::<StatusLine>().is_err());
+        assert!("file}".parse::<StatusLine>().is_err());
+        assert!("{=}{=}".parse::<StatusLine>().is_err());
+    }
+}
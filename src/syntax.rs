@@ -0,0 +1,429 @@
+use crate::error::{ErrorContext, Result};
+use crate::prelude::*;
+use crate::row::Row;
+use crate::theme::Group;
+use regex::Regex;
+use serde_derive::Deserialize;
+use std::fs;
+
+/// The grammars wim knows without being told, by the file they come from.
+static BUILTIN_GRAMMARS: &[(&str, &str)] = &[
+    ("diff.toml", include_str!("../syntaxes/diff.toml")),
+    ("markdown.toml", include_str!("../syntaxes/markdown.toml")),
+    ("rust.toml", include_str!("../syntaxes/rust.toml")),
+    ("sh.toml", include_str!("../syntaxes/sh.toml")),
+    ("toml.toml", include_str!("../syntaxes/toml.toml")),
+];
+
+/// Zero-width matches that push or pop a context are allowed this many times in a row at one
+/// spot before the lexer gives up on them and moves on a char.
+const MAX_STALLS: usize = 8;
+
+/// A name for the kind of text a token is, such as `comment` or `string.escape`.
+pub type Scope = Rc<str>;
+
+/// A grammar as written in its TOML file. Each context is a list of rules, each a regex with
+/// the `scope` of the text it matches, and the context to `push` or whether to `pop` back out
+/// of this one afterwards. Lines are lexed starting in the `main` context. Text matched by no
+/// rule takes the context's own `scope`, if any.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GrammarDef {
+    name: String,
+    file_types: Vec<String>,
+    contexts: HashMap<String, ContextDef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ContextDef {
+    scope: Option<String>,
+    #[serde(default)]
+    rules: Vec<RuleDef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleDef {
+    #[serde(rename = "match")]
+    pattern: String,
+    scope: Option<String>,
+    push: Option<String>,
+    #[serde(default)]
+    pop: bool,
+}
+
+/// A language's highlighting rules, compiled from its TOML definition.
+#[derive(Debug)]
+pub struct Grammar {
+    name: String,
+    file_types: Vec<String>,
+    /// The contexts by index, with `main` first.
+    contexts: Vec<Context>,
+}
+
+#[derive(Debug)]
+struct Context {
+    scope: Option<Scope>,
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    regex: Regex,
+    scope: Option<Scope>,
+    push: Option<usize>,
+    pop: bool,
+}
+
+/// A run of a line's chars and the scope they're in.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Token {
+    pub range: Range<Coord>,
+    pub scope: Scope,
+}
+
+/// The contexts open at some point in a doc, innermost last.
+type State = Vec<usize>;
+
+impl Grammar {
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let def: GrammarDef = toml::from_str(text).context("load-grammar")?;
+        if !def.contexts.contains_key("main") {
+            return Err(error!("grammar '{}' has no main context", def.name));
+        }
+        let mut names: Vec<&String> = def.contexts.keys().collect();
+        names.sort_by_key(|&name| (name != "main", name));
+        let index_of = |name: &str| -> Result<usize> {
+            match names.iter().position(|&other| other == name) {
+                Some(index) => Ok(index),
+                None => Err(error!(
+                    "grammar '{}' has no context named '{}'",
+                    def.name, name
+                )),
+            }
+        };
+        let mut contexts = Vec::new();
+        for &name in names.iter() {
+            let context = &def.contexts[name];
+            let mut rules = Vec::new();
+            for rule in context.rules.iter() {
+                let regex = Regex::new(&rule.pattern)
+                    .map_err(|err| error!("grammar '{}', context '{}': {}", def.name, name, err))?;
+                rules.push(Rule {
+                    regex,
+                    scope: rule.scope.as_deref().map(Scope::from),
+                    push: rule.push.as_deref().map(index_of).transpose()?,
+                    pop: rule.pop,
+                });
+            }
+            contexts.push(Context {
+                scope: context.scope.as_deref().map(Scope::from),
+                rules,
+            });
+        }
+        Ok(Self {
+            name: def.name.clone(),
+            file_types: def.file_types.clone(),
+            contexts,
+        })
+    }
+    /// Splits `line` into scoped tokens, starting in `state` and leaving it as it is at the end
+    /// of the line. Text that's in no scope gets no token.
+    fn lex_line(&self, line: &str, state: &mut State) -> Vec<Token> {
+        let mut tokens: Vec<Token> = Vec::new();
+        // Rules match on byte offsets, but tokens are in chars like the rest of the doc.
+        let mut push_token = |bytes: Range<usize>, scope: Option<&Scope>| {
+            if let (Some(scope), false) = (scope, bytes.is_empty()) {
+                let start = line[..bytes.start].chars().count();
+                let end = start + line[bytes].chars().count();
+                match tokens.last_mut() {
+                    Some(last) if last.range.end == start && last.scope == *scope => {
+                        last.range.end = end;
+                        return;
+                    }
+                    _ => (),
+                }
+                tokens.push(Token {
+                    range: start..end,
+                    scope: scope.clone(),
+                });
+            }
+        };
+        let mut at = 0;
+        let mut stalls = 0;
+        loop {
+            let context = &self.contexts[*state.last().unwrap()];
+            // The earliest match wins, and of those starting at the same spot, the first rule.
+            let found = context
+                .rules
+                .iter()
+                .filter_map(|rule| rule.regex.find_at(line, at).map(|m| (m, rule)))
+                .min_by_key(|(m, _)| m.start());
+            let (m, rule) = match found {
+                Some(found) => found,
+                None => {
+                    push_token(at..line.len(), context.scope.as_ref());
+                    break;
+                }
+            };
+            push_token(at..m.start(), context.scope.as_ref());
+            push_token(m.range(), rule.scope.as_ref().or(context.scope.as_ref()));
+            if rule.pop && state.len() > 1 {
+                state.pop();
+            }
+            if let Some(push) = rule.push {
+                state.push(push);
+            }
+            if m.end() > at {
+                at = m.end();
+                stalls = 0;
+            } else if at >= line.len() {
+                break;
+            } else if (rule.pop || rule.push.is_some()) && stalls < MAX_STALLS {
+                stalls += 1;
+            } else {
+                let next = at + line[at..].chars().next().unwrap().len_utf8();
+                push_token(
+                    at..next,
+                    self.contexts[*state.last().unwrap()].scope.as_ref(),
+                );
+                at = next;
+                stalls = 0;
+            }
+        }
+        tokens
+    }
+}
+
+/// Highlights a doc's lines with a grammar. The state each line starts in is kept, so after a
+/// change only the lines from the changed one down are lexed again, and only as far as they're
+/// shown.
+#[derive(Debug)]
+pub struct Highlighter {
+    grammar: Rc<Grammar>,
+    /// `states[y]` is the state line `y` starts in. There's one more of these than lexed lines.
+    states: Vec<State>,
+    /// The tokens of each line lexed so far, from the top of the doc.
+    lines: Vec<Vec<Token>>,
+}
+
+impl Highlighter {
+    pub fn new(grammar: Rc<Grammar>) -> Self {
+        Self {
+            grammar,
+            states: vec![vec![0]],
+            lines: Vec::new(),
+        }
+    }
+    pub fn grammar(&self) -> &Rc<Grammar> {
+        &self.grammar
+    }
+    /// Forgets the tokens of line `y` and those below it, as they may have changed.
+    pub fn invalidate_from(&mut self, y: Coord) {
+        self.lines.truncate(y);
+        self.states.truncate(self.lines.len() + 1);
+    }
+    /// Lexes any of `rows` up to and including line `y` that aren't lexed yet.
+    pub fn lex_through(&mut self, rows: &[Row], y: Coord) {
+        while self.lines.len() <= y && self.lines.len() < rows.len() {
+            let line: String = rows[self.lines.len()].as_slice().iter().collect();
+            let mut state = self.states.last().unwrap().clone();
+            let tokens = self.grammar.lex_line(&line, &mut state);
+            self.lines.push(tokens);
+            self.states.push(state);
+        }
+    }
+    /// The tokens of line `y`, or none if it hasn't been lexed.
+    pub fn tokens(&self, y: Coord) -> &[Token] {
+        self.lines.get(y).map_or(&[], |tokens| tokens.as_slice())
+    }
+}
+
+/// The grammars there are to highlight docs with.
+#[derive(Debug)]
+pub struct Syntaxes {
+    grammars: Vec<Rc<Grammar>>,
+}
+
+impl Syntaxes {
+    pub fn builtin() -> Self {
+        let mut syntaxes = Self {
+            grammars: Vec::new(),
+        };
+        for (filename, text) in BUILTIN_GRAMMARS {
+            match Grammar::from_toml(text) {
+                Ok(grammar) => syntaxes.add(grammar),
+                Err(err) => panic!("built-in grammar {} is broken: {}", filename, err),
+            }
+        }
+        syntaxes
+    }
+    /// Adds `grammar`, in place of any grammar of the same name.
+    pub fn add(&mut self, grammar: Grammar) {
+        self.grammars.retain(|other| other.name != grammar.name);
+        self.grammars.push(Rc::new(grammar));
+    }
+    /// Adds the grammar in each `.toml` file in `directory`.
+    pub fn load_directory(&mut self, directory: &str) -> Result<()> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "toml")
+            {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        for path in paths {
+            let text = fs::read_to_string(&path)?;
+            let grammar =
+                Grammar::from_toml(&text).map_err(|err| error!("{}: {}", path.display(), err))?;
+            self.add(grammar);
+        }
+        Ok(())
+    }
+    /// The grammar for files of type `file_type` (see `filetype::detect`).
+    pub fn for_file_type(&self, file_type: &str) -> Option<Rc<Grammar>> {
+        self.grammars
+            .iter()
+            .find(|grammar| grammar.file_types.iter().any(|other| other == file_type))
+            .cloned()
+    }
+}
+
+impl Default for Syntaxes {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+/// The highlight group text in `scope` is drawn with. Scopes go by their first part, so
+/// `string.escape` is drawn like any other `string`.
+pub fn scope_group(scope: &str) -> Option<Group> {
+    match scope.split('.').next().unwrap_or_default() {
+        "comment" => Some(Group::Comment),
+        "string" => Some(Group::String),
+        "keyword" => Some(Group::Keyword),
+        "constant" => Some(Group::Constant),
+        "number" => Some(Group::Number),
+        "type" => Some(Group::Type),
+        "macro" | "attribute" => Some(Group::PreProc),
+        "label" => Some(Group::Label),
+        "variable" => Some(Group::Identifier),
+        "link" => Some(Group::Underlined),
+        "heading" => Some(Group::Title),
+        "emphasis" => Some(Group::Special),
+        "inserted" => Some(Group::DiffAdd),
+        "deleted" => Some(Group::DiffDelete),
+        "changed" => Some(Group::DiffChange),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scopes(tokens: &[Token]) -> Vec<(Range<Coord>, &str)> {
+        tokens
+            .iter()
+            .map(|token| (token.range.clone(), &*token.scope))
+            .collect()
+    }
+
+    fn rows(lines: &[&str]) -> Vec<Row> {
+        lines.iter().map(|line| Row::from_line(line)).collect()
+    }
+
+    #[test]
+    fn builtin_grammars_compile() {
+        let syntaxes = Syntaxes::builtin();
+        for file_type in ["rust", "toml", "markdown", "sh", "diff"] {
+            assert!(syntaxes.for_file_type(file_type).is_some(), "{}", file_type);
+        }
+    }
+
+    #[test]
+    fn carries_state_across_lines() {
+        let grammar = Syntaxes::builtin().for_file_type("rust").unwrap();
+        let mut highlighter = Highlighter::new(grammar);
+        let rows = rows(&["let x = 1; /* é", "*/ fn"]);
+        highlighter.lex_through(&rows, 1);
+        assert_eq!(
+            scopes(highlighter.tokens(0)),
+            vec![(0..3, "keyword"), (8..9, "number"), (11..15, "comment")]
+        );
+        assert_eq!(
+            scopes(highlighter.tokens(1)),
+            vec![(0..2, "comment"), (3..5, "keyword")]
+        );
+    }
+
+    #[test]
+    fn raw_strings_end_with_their_hashes() {
+        let grammar = Syntaxes::builtin().for_file_type("rust").unwrap();
+        let mut highlighter = Highlighter::new(grammar);
+        let rows = rows(&[r##"r#"a"b"# fn"##, r#"r"a" fn"#]);
+        highlighter.lex_through(&rows, 1);
+        for y in 0..2 {
+            let tokens = scopes(highlighter.tokens(y));
+            let (last, strings) = tokens.split_last().unwrap();
+            assert!(strings.iter().all(|(_, scope)| *scope == "string"));
+            assert_eq!(last.1, "keyword");
+        }
+        assert_eq!(scopes(highlighter.tokens(0)).last().unwrap().0, 9..11);
+    }
+
+    #[test]
+    fn relexes_from_a_changed_line() {
+        let grammar = Syntaxes::builtin().for_file_type("rust").unwrap();
+        let mut highlighter = Highlighter::new(grammar);
+        let mut rows = rows(&["/* a", "b", "c"]);
+        highlighter.lex_through(&rows, 0);
+        assert!(highlighter.tokens(1).is_empty());
+        highlighter.lex_through(&rows, 5);
+        assert_eq!(scopes(highlighter.tokens(2)), vec![(0..1, "comment")]);
+        rows[0] = Row::from_line("// a");
+        highlighter.invalidate_from(1);
+        highlighter.lex_through(&rows, 2);
+        // Line 0 was left as it was.
+        assert_eq!(scopes(highlighter.tokens(0)), vec![(0..4, "comment")]);
+        assert_eq!(scopes(highlighter.tokens(2)), vec![(0..1, "comment")]);
+        highlighter.invalidate_from(0);
+        highlighter.lex_through(&rows, 2);
+        assert!(highlighter.tokens(2).is_empty());
+    }
+
+    #[test]
+    fn survives_zero_width_matches() -> Result<()> {
+        let grammar = Grammar::from_toml(
+            r#"
+            name = "test"
+            file_types = []
+            [contexts.main]
+            rules = [{ match = '\b', push = "word" }, { match = 'x*' }]
+            [contexts.word]
+            scope = "word"
+            rules = [{ match = '\b', pop = true }]
+            "#,
+        )?;
+        let mut state = vec![0];
+        let tokens = grammar.lex_line("ab cd", &mut state);
+        assert!(!tokens.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_bad_grammars() {
+        let grammar = |contexts: &str| {
+            Grammar::from_toml(&format!("name = \"test\"\nfile_types = []\n{}", contexts))
+        };
+        assert!(grammar("[contexts.main]\nrules = [{ match = 'a', push = \"nope\" }]").is_err());
+        assert!(grammar("[contexts.main]\nrules = [{ match = '(' }]").is_err());
+        assert!(grammar("[contexts.other]\nrules = []").is_err());
+        assert!(grammar("[contexts.main]\nrules = [{ match = 'a' }]").is_ok());
+    }
+}
@@ -0,0 +1,209 @@
+use crate::color::{BgColor, FgColor, UnderlineColor};
+use crate::error::{Error, ErrorContext, Result};
+use crate::format::Attrs;
+use crate::prelude::*;
+use serde_derive::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// The themes wim knows without being told, by name.
+static BUILTIN_THEMES: &[(&str, &str)] = &[
+    ("default", include_str!("../themes/default.toml")),
+    ("light", include_str!("../themes/light.toml")),
+];
+
+/// A kind of thing on screen that a theme gives colors to. The names are vim's.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Group {
+    /// Text with nothing more specific to say about it, and the background of everything.
+    Normal,
+    Visual,
+    Search,
+    /// Tabs and trailing spaces in list mode.
+    Whitespace,
+    /// What's drawn past the end of lines, like list mode's end of line marker.
+    NonText,
+    LineNr,
+    CursorLineNr,
+    StatusLine,
+    /// The status line of windows other than the focused one.
+    StatusLineNC,
+    TabLine,
+    TabLineSel,
+    /// The command line, and the messages shown in it.
+    MsgArea,
+    ErrorMsg,
+    Comment,
+    Constant,
+    String,
+    Number,
+    Identifier,
+    Keyword,
+    Type,
+    PreProc,
+    Label,
+    Special,
+    Title,
+    Underlined,
+    DiffAdd,
+    DiffChange,
+    DiffDelete,
+}
+
+static GROUP_NAMES: &[(&str, Group)] = &[
+    ("Normal", Group::Normal),
+    ("Visual", Group::Visual),
+    ("Search", Group::Search),
+    ("Whitespace", Group::Whitespace),
+    ("NonText", Group::NonText),
+    ("LineNr", Group::LineNr),
+    ("CursorLineNr", Group::CursorLineNr),
+    ("StatusLine", Group::StatusLine),
+    ("StatusLineNC", Group::StatusLineNC),
+    ("TabLine", Group::TabLine),
+    ("TabLineSel", Group::TabLineSel),
+    ("MsgArea", Group::MsgArea),
+    ("ErrorMsg", Group::ErrorMsg),
+    ("Comment", Group::Comment),
+    ("Constant", Group::Constant),
+    ("String", Group::String),
+    ("Number", Group::Number),
+    ("Identifier", Group::Identifier),
+    ("Keyword", Group::Keyword),
+    ("Type", Group::Type),
+    ("PreProc", Group::PreProc),
+    ("Label", Group::Label),
+    ("Special", Group::Special),
+    ("Title", Group::Title),
+    ("Underlined", Group::Underlined),
+    ("DiffAdd", Group::DiffAdd),
+    ("DiffChange", Group::DiffChange),
+    ("DiffDelete", Group::DiffDelete),
+];
+
+impl FromStr for Group {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match GROUP_NAMES.iter().find(|(name, _)| *name == s) {
+            Some((_, group)) => Ok(*group),
+            None => Err(error!("'{}' is not a highlight group", s)),
+        }
+    }
+}
+
+/// A theme as written in its TOML file: a table of groups, each with an optional `fg`, `bg` and
+/// `underline_color`, and a list of `attrs` such as `["bold", "italic"]`. Colors are either a
+/// name like `"red"` or `{ rgb = { r = 0, g = 0, b = 0 } }`. A group with no color is drawn in
+/// whatever is beneath it.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThemeDef {
+    groups: HashMap<String, GroupDef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GroupDef {
+    fg: Option<FgColor>,
+    bg: Option<BgColor>,
+    #[serde(default)]
+    attrs: Attrs,
+    underline_color: Option<UnderlineColor>,
+}
+
+/// The formats a theme gives each highlight group.
+#[derive(Clone, Debug)]
+pub struct Theme {
+    formats: HashMap<Group, Format>,
+}
+
+impl Theme {
+    /// Loads the theme called `name`, from `name.toml` in `directory` if there is one, or else
+    /// from the built-in themes. Groups the theme leaves out keep their default formats.
+    pub fn load(name: &str, directory: Option<&str>) -> Result<Self> {
+        let path = directory.map(|directory| Path::new(directory).join(format!("{}.toml", name)));
+        let text = match path {
+            Some(path) if path.exists() => fs::read_to_string(path)?,
+            _ => match BUILTIN_THEMES.iter().find(|(builtin, _)| *builtin == name) {
+                Some((_, text)) => text.to_string(),
+                None => return Err(error!("there is no theme named '{}'", name)),
+            },
+        };
+        let mut theme = Self::default();
+        theme.extend_from_toml(&text)?;
+        Ok(theme)
+    }
+    fn extend_from_toml(&mut self, text: &str) -> Result<()> {
+        let def: ThemeDef = toml::from_str(text).context("load-theme")?;
+        for (name, group) in def.groups {
+            self.formats.insert(
+                name.parse()?,
+                Format {
+                    fg: group.fg.unwrap_or(FgColor::None),
+                    bg: group.bg.unwrap_or(BgColor::None),
+                    attrs: group.attrs,
+                    underline: group.underline_color.unwrap_or(UnderlineColor::None),
+                },
+            );
+        }
+        Ok(())
+    }
+    pub fn format(&self, group: Group) -> Format {
+        self.formats
+            .get(&group)
+            .copied()
+            .unwrap_or_else(Format::none)
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        let mut theme = Self {
+            formats: HashMap::new(),
+        };
+        theme
+            .extend_from_toml(BUILTIN_THEMES[0].1)
+            .expect("the default theme is broken");
+        theme
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_themes_load() -> Result<()> {
+        for (name, _) in BUILTIN_THEMES {
+            let theme = Theme::load(name, None)?;
+            assert_ne!(theme.format(Group::Visual), Format::none(), "{}", name);
+            assert_ne!(theme.format(Group::ErrorMsg), Format::none(), "{}", name);
+        }
+        assert!(Theme::load("nope", None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn themes_override_the_default() -> Result<()> {
+        let mut theme = Theme::default();
+        let search = theme.format(Group::Search);
+        theme.extend_from_toml("[groups]\nVisual = { fg = \"red\", attrs = [\"bold\"] }")?;
+        assert_eq!(
+            theme.format(Group::Visual),
+            Format {
+                fg: FgColor::Red,
+                attrs: Attrs::BOLD,
+                ..Format::none()
+            }
+        );
+        assert_eq!(theme.format(Group::Search), search);
+        assert!(theme
+            .extend_from_toml("[groups]\nNope = { fg = \"red\" }")
+            .is_err());
+        assert!(theme
+            .extend_from_toml("[groups]\nVisual = { attrs = [\"blink\"] }")
+            .is_err());
+        Ok(())
+    }
+}
@@ -6,6 +6,7 @@ use crate::prelude::*;
 pub struct TrieNode {
     dk: Option<DK>,
     children: HashMap<Key, TrieNode>,
+    counts: bool,
 }
 
 impl TrieNode {
@@ -17,6 +18,7 @@ impl TrieNode {
             .fold(Self::default(), |node, b| node.with_bindings(b))
     }
     fn with_bindings(mut self, bindings: Bindings) -> Self {
+        self.counts |= bindings.accepts_counts();
         for (keys, dk) in bindings.get_map() {
             self.insert(dk, &keys);
         }
@@ -30,6 +32,34 @@ impl TrieNode {
         cur.dk = Some(dk);
     }
 
+    /// Splits a leading count off `input`. A count starts with a non-zero digit so that `0` can
+    /// still be bound on its own.
+    pub(crate) fn split_count<'b>(&self, input: &'b [Key]) -> (Option<i64>, &'b [Key]) {
+        if !self.counts {
+            return (None, input);
+        }
+        let mut count: Option<i64> = None;
+        for (i, key) in input.iter().enumerate() {
+            match (*key, count) {
+                (Key::Utf8(ch @ '1'..='9'), None) => {
+                    count = Some(ch.to_digit(10).unwrap() as i64);
+                }
+                (Key::Utf8(ch @ '0'..='9'), Some(n)) => {
+                    count = Some(
+                        n.saturating_mul(10)
+                            .saturating_add(ch.to_digit(10).unwrap() as i64),
+                    );
+                }
+                _ => return (count, &input[i..]),
+            }
+        }
+        (count, &[])
+    }
+    /// The keys that may start a binding.
+    pub(crate) fn first_keys(&self) -> Vec<Key> {
+        self.children.keys().cloned().collect()
+    }
+
     fn match_prefix<'a>(&'a self, prefix: &[Key]) -> PrefixMatch<'a> {
         let mut cur = self;
         for &key in prefix {
@@ -1,46 +1,330 @@
 use crate::doc::Doc;
+use crate::error::{ErrorContext, Result};
 use crate::prelude::*;
 use crate::row::Row;
+use serde_derive::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Default, Debug)]
-pub struct ChangeStack {
-    changes: Vec<Change>,
-    index: usize,
+/// Every state the document has been in, as a tree: undoing and then making a new change starts
+/// a new branch rather than discarding the undone changes. Node 0 is the document as it was
+/// opened, and because nodes are only ever appended, a node's index is also its sequence number
+/// in the order the changes were made.
+#[derive(Debug)]
+pub struct UndoTree {
+    nodes: Vec<UndoNode>,
+    current: usize,
+}
+
+#[derive(Debug)]
+struct UndoNode {
+    /// Takes the parent's state to this node's state, or back again once it has been applied.
+    change: Change,
+    parent: usize,
+    /// The child `redo` moves to: the one most recently undone or created.
+    redo_child: Option<usize>,
+    time: SystemTime,
 }
 
-impl ChangeStack {
+impl UndoNode {
+    fn root() -> Self {
+        Self {
+            change: Change::default(),
+            parent: 0,
+            redo_child: None,
+            time: SystemTime::now(),
+        }
+    }
+}
+
+impl Default for UndoTree {
+    fn default() -> Self {
+        Self {
+            nodes: vec![UndoNode::root()],
+            current: 0,
+        }
+    }
+}
+
+impl UndoTree {
     pub fn clear(&mut self) {
-        self.index = 0;
-        self.changes.truncate(0);
+        *self = Self::default();
+    }
+    /// The sequence number of the current state.
+    pub fn seq(&self) -> usize {
+        self.current
+    }
+    pub fn last_seq(&self) -> usize {
+        self.nodes.len() - 1
     }
 
     #[must_use]
     pub fn push(&mut self, doc: &mut Doc, mut change: Change) -> Pos {
-        self.changes.truncate(self.index);
         let pos = change.execute(doc);
-        self.changes.push(change);
-        self.index += 1;
+        self.push_applied(change);
         pos
     }
 
+    /// Records a change that has already been executed against the document.
+    pub fn push_applied(&mut self, change: Change) {
+        self.nodes.push(UndoNode {
+            change,
+            parent: self.current,
+            redo_child: None,
+            time: SystemTime::now(),
+        });
+        let seq = self.last_seq();
+        self.nodes[self.current].redo_child = Some(seq);
+        self.current = seq;
+    }
+
     #[must_use]
     pub fn redo(&mut self, doc: &mut Doc) -> Option<Pos> {
-        if self.index < self.changes.len() {
-            let pos = self.changes[self.index].execute(doc);
-            self.index += 1;
-            Some(pos)
-        } else {
-            None
-        }
+        let child = self.nodes[self.current].redo_child?;
+        let pos = self.nodes[child].change.execute(doc);
+        self.current = child;
+        Some(pos)
     }
 
     #[must_use]
     pub fn pop(&mut self, doc: &mut Doc) -> Option<Pos> {
-        if self.index > 0 {
-            self.index -= 1;
-            Some(self.changes[self.index].execute(doc))
-        } else {
-            None
+        if self.current == 0 {
+            return None;
+        }
+        let pos = self.nodes[self.current].change.execute(doc);
+        let parent = self.nodes[self.current].parent;
+        self.nodes[parent].redo_child = Some(self.current);
+        self.current = parent;
+        Some(pos)
+    }
+
+    /// Moves to the state with sequence number `seq`, undoing back to the closest common
+    /// ancestor and then redoing down the other branch.
+    #[must_use]
+    pub fn goto(&mut self, doc: &mut Doc, seq: usize) -> Option<Pos> {
+        let seq = seq.min(self.last_seq());
+        let mut path = vec![seq];
+        while let Some(&node) = path.last() {
+            if node == 0 {
+                break;
+            }
+            path.push(self.nodes[node].parent);
+        }
+        let mut pos = None;
+        while !path.contains(&self.current) {
+            pos = self.pop(doc);
+        }
+        let ancestor = path.iter().position(|&node| node == self.current).unwrap();
+        for &node in path[..ancestor].iter().rev() {
+            self.nodes[self.current].redo_child = Some(node);
+            pos = self.redo(doc);
+        }
+        pos
+    }
+
+    /// Moves `travel` backwards (or forwards, if `later`) in the order changes were made.
+    #[must_use]
+    pub fn travel(&mut self, doc: &mut Doc, travel: Travel, later: bool) -> Option<Pos> {
+        let seq = match travel {
+            Travel::Steps(steps) if later => self.current.saturating_add(steps),
+            Travel::Steps(steps) => self.current.saturating_sub(steps),
+            Travel::Time(duration) => {
+                let now = self.nodes[self.current].time;
+                let target = if later {
+                    now.checked_add(duration)
+                } else {
+                    now.checked_sub(duration)
+                };
+                match target {
+                    // The state at `target` is the last one created by then.
+                    Some(target) => self
+                        .nodes
+                        .iter()
+                        .rposition(|node| node.time <= target)
+                        .unwrap_or(0),
+                    None if later => self.last_seq(),
+                    None => 0,
+                }
+            }
+        };
+        self.goto(doc, seq)
+    }
+
+    /// Serializes the tree for an undo file. `hash` identifies the document contents the tree
+    /// is for.
+    pub fn to_undo_file(&self, hash: u64) -> Result<String> {
+        let undo_file = UndoFile {
+            version: UNDO_FILE_VERSION,
+            hash: format!("{:016x}", hash),
+            current: self.current,
+            nodes: self.nodes.iter().map(NodeRecord::from).collect(),
+        };
+        toml::to_string(&undo_file).context("write-undo-file")
+    }
+
+    /// Reads a tree back from an undo file, or returns None if the file was written for other
+    /// document contents than those hashing to `hash`. `line_count` is the document's length,
+    /// which every change in the tree has to fit.
+    pub fn from_undo_file(text: &str, hash: u64, line_count: usize) -> Result<Option<Self>> {
+        let undo_file: UndoFile = toml::from_str(text).context("read-undo-file")?;
+        if undo_file.version != UNDO_FILE_VERSION || undo_file.hash != format!("{:016x}", hash) {
+            return Ok(None);
+        }
+        let len = undo_file.nodes.len();
+        let is_valid = len > 0
+            && undo_file.current < len
+            && undo_file.nodes.iter().enumerate().all(|(i, node)| {
+                (i == 0 || node.parent < i) && node.redo_child.is_none_or(|child| child < len)
+            });
+        if !is_valid {
+            return Err(error!("read-undo-file: malformed undo tree"));
+        }
+        let undo_tree = Self {
+            nodes: undo_file.nodes.into_iter().map(UndoNode::from).collect(),
+            current: undo_file.current,
+        };
+        if undo_tree.line_counts(line_count).is_none() {
+            return Err(error!("read-undo-file: change outside the document"));
+        }
+        Ok(Some(undo_tree))
+    }
+
+    /// How many lines the document has in each state, given `line_count` in the current one,
+    /// or None if some change touches rows that the state it runs from doesn't have.
+    fn line_counts(&self, line_count: usize) -> Option<Vec<usize>> {
+        let mut counts = vec![None; self.nodes.len()];
+        counts[self.current] = Some(line_count);
+        // Changes on the way up from the current state are held ready to undo...
+        let mut node = self.current;
+        while node != 0 {
+            let parent = self.nodes[node].parent;
+            counts[parent] = Some(self.nodes[node].change.line_count_after(counts[node]?)?);
+            node = parent;
+        }
+        // ...and the rest ready to redo from their parent's state.
+        for node in 1..self.nodes.len() {
+            if counts[node].is_none() {
+                let parent = counts[self.nodes[node].parent]?;
+                counts[node] = Some(self.nodes[node].change.line_count_after(parent)?);
+            }
+        }
+        counts.into_iter().collect()
+    }
+}
+
+/// How far `:earlier` and `:later` travel: a number of changes or a span of time, written like
+/// `3`, `10s`, `5m`, `1h` or `2d`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Travel {
+    Steps(usize),
+    Time(Duration),
+}
+
+impl std::str::FromStr for Travel {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Ok(Self::Steps(1));
+        }
+        let digits = s.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(s.len());
+        let n: u64 = match s[..digits].parse() {
+            Ok(n) => n,
+            Err(_) => return Err(error!("Invalid argument: {}", s)),
+        };
+        let seconds = match &s[digits..] {
+            "" => return Ok(Self::Steps(n as usize)),
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => return Err(error!("Invalid argument: {}", s)),
+        };
+        Ok(Self::Time(Duration::from_secs(n.saturating_mul(seconds))))
+    }
+}
+
+static UNDO_FILE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct UndoFile {
+    version: u32,
+    hash: String,
+    current: usize,
+    nodes: Vec<NodeRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct NodeRecord {
+    parent: usize,
+    redo_child: Option<usize>,
+    /// Seconds since the Unix epoch.
+    time: u64,
+    before_cursor: (usize, usize),
+    after_cursor: (usize, usize),
+    ops: Vec<OpRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OpRecord {
+    start: usize,
+    end: usize,
+    rows: Vec<String>,
+}
+
+impl From<&UndoNode> for NodeRecord {
+    fn from(node: &UndoNode) -> Self {
+        let change = &node.change;
+        Self {
+            parent: node.parent,
+            redo_child: node.redo_child,
+            time: node
+                .time
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs()),
+            before_cursor: (change.before_cursor.x, change.before_cursor.y),
+            after_cursor: (change.after_cursor.x, change.after_cursor.y),
+            ops: change
+                .ops
+                .iter()
+                .map(|op| OpRecord {
+                    start: op.range.start,
+                    end: op.range.end,
+                    rows: op
+                        .rows
+                        .iter()
+                        .map(|row| row.as_slice().iter().collect())
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<NodeRecord> for UndoNode {
+    fn from(record: NodeRecord) -> Self {
+        Self {
+            change: Change {
+                ops: record
+                    .ops
+                    .into_iter()
+                    .map(|op| ChangeOp {
+                        range: op.start..op.end,
+                        rows: op.rows.iter().map(|row| Row::from_line(row)).collect(),
+                    })
+                    .collect(),
+                before_cursor: Pos {
+                    x: record.before_cursor.0,
+                    y: record.before_cursor.1,
+                },
+                after_cursor: Pos {
+                    x: record.after_cursor.0,
+                    y: record.after_cursor.1,
+                },
+            },
+            parent: record.parent,
+            redo_child: record.redo_child,
+            time: UNIX_EPOCH + Duration::from_secs(record.time),
         }
     }
 }
@@ -91,6 +375,23 @@ pub struct ChangeOp {
 }
 
 impl Change {
+    /// Folds `later`, a change executed after this one, into this one so that they undo
+    /// together. Both must already have been executed.
+    pub fn absorb(&mut self, later: Change) {
+        // Executed changes hold their ops inverted and in reverse order, ready to be undone.
+        let mut ops = later.ops;
+        ops.append(&mut self.ops);
+        self.ops = ops;
+        self.before_cursor = later.before_cursor;
+    }
+    /// How many lines a document of `line_count` lines has once this change is executed, or
+    /// None if an op's rows aren't all in the document.
+    fn line_count_after(&self, line_count: usize) -> Option<usize> {
+        self.ops.iter().try_fold(line_count, |count, op| {
+            (op.range.start <= op.range.end && op.range.end <= count)
+                .then(|| count - op.range.len() + op.rows.len())
+        })
+    }
     #[must_use]
     pub fn execute(&mut self, doc: &mut Doc) -> Pos {
         for op in &mut self.ops {
@@ -108,3 +409,99 @@ impl Change {
         self.before_cursor
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    fn insert_char(doc: &mut Doc, x: Coord, ch: char) {
+        let (op, pos) = doc.insert_char(Pos { x, y: 0 }, ch);
+        let mut change_tracker = doc.new_change_tracker(Pos { x, y: 0 });
+        change_tracker.add_op(op, pos);
+        let _ = change_tracker.commit();
+    }
+
+    #[test]
+    fn parse_travel() {
+        assert_eq!(Travel::from_str("").ok(), Some(Travel::Steps(1)));
+        assert_eq!(Travel::from_str("3").ok(), Some(Travel::Steps(3)));
+        assert_eq!(
+            Travel::from_str("2m").ok(),
+            Some(Travel::Time(Duration::from_secs(120)))
+        );
+        assert!(Travel::from_str("2y").is_err());
+        assert!(Travel::from_str("s").is_err());
+    }
+
+    #[test]
+    fn groups_undo_as_one_change() {
+        let mut doc = Doc::empty();
+        insert_char(&mut doc, 0, 'a');
+        doc.group(|doc| {
+            insert_char(doc, 1, 'b');
+            // Nested groups join the outer one.
+            doc.group(|doc| insert_char(doc, 2, 'c'));
+        });
+        assert_eq!(doc.to_string(), "abc\n");
+        assert_eq!(doc.undo_change(), Some(Pos { x: 1, y: 0 }));
+        assert_eq!(doc.to_string(), "a\n");
+        assert!(doc.redo_change().is_some());
+        assert_eq!(doc.to_string(), "abc\n");
+    }
+
+    #[test]
+    fn undo_file_changes_fit_the_doc() {
+        let undo_file = |end: usize| {
+            format!(
+                r#"
+                version = 1
+                hash = "0000000000000000"
+                current = 1
+                [[nodes]]
+                parent = 0
+                redo_child = 1
+                time = 0
+                before_cursor = [0, 0]
+                after_cursor = [0, 0]
+                ops = []
+                [[nodes]]
+                parent = 0
+                time = 0
+                before_cursor = [0, 0]
+                after_cursor = [0, 0]
+                ops = [{{ start = 0, end = {}, rows = ["a"] }}]
+                "#,
+                end
+            )
+        };
+        assert!(UndoTree::from_undo_file(&undo_file(1), 0, 1).is_ok_and(|tree| tree.is_some()));
+        assert!(UndoTree::from_undo_file(&undo_file(3), 0, 1).is_err());
+    }
+    #[test]
+    fn undo_file_round_trip() -> Result<()> {
+        let dir = TempDir::new("undo")?;
+        let filename = dir.join("undo.txt").to_string_lossy().to_string();
+        std::fs::write(&filename, "a\n")?;
+
+        let mut doc = Doc::open(filename.clone())?;
+        assert!(!doc.use_undo_file()?);
+        insert_char(&mut doc, 1, 'b');
+        insert_char(&mut doc, 2, 'c');
+        doc.save()?;
+
+        let mut doc = Doc::open(filename.clone())?;
+        assert!(doc.use_undo_file()?);
+        assert_eq!(doc.to_string(), "abc\n");
+        assert!(doc.undo_change().is_some());
+        assert!(doc.undo_change().is_some());
+        assert_eq!(doc.to_string(), "a\n");
+        assert!(doc.undo_change().is_none());
+
+        // Once the file is changed behind our back, the undo file no longer applies.
+        std::fs::write(&filename, "xyz\n")?;
+        let mut doc = Doc::open(filename)?;
+        assert!(!doc.use_undo_file()?);
+        Ok(())
+    }
+}
@@ -1,4 +1,3 @@
-use crate::error::Result;
 use crate::widechar_width::WcWidth;
 use libc::strerror;
 use std::ffi::CStr;
@@ -89,9 +88,15 @@ impl Errno {
     pub fn is_enoent(self) -> bool {
         self.errno == libc::ENOENT
     }
+    pub fn is_eexist(self) -> bool {
+        self.errno == libc::EEXIST
+    }
     pub fn is_eagain(self) -> bool {
         self.errno == libc::EAGAIN
     }
+    pub fn is_eintr(self) -> bool {
+        self.errno == libc::EINTR
+    }
 }
 
 impl std::fmt::Display for Errno {
@@ -108,7 +113,7 @@ impl From<Errno> for String {
     }
 }
 
-pub fn read_lines<P>(filename: P) -> Result<Lines<BufReader<File>>>
+pub fn read_lines<P>(filename: P) -> std::io::Result<Lines<BufReader<File>>>
 where
     P: AsRef<Path>,
 {
@@ -116,6 +121,25 @@ where
     Ok(BufReader::new(file).lines())
 }
 
+/// 64-bit FNV-1a, a hash that (unlike `DefaultHasher`) stays the same across Rust releases, so
+/// it can be written to disk.
+pub fn fnv1a_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// How many cells the grapheme cluster `grapheme` takes up on screen. That's the width of its
+/// first char, as the rest are drawn over it, except that a cluster of nothing but combining
+/// marks takes none.
+pub fn grapheme_width(grapheme: &str) -> usize {
+    match grapheme.chars().next().map(WcWidth::from_char) {
+        None | Some(WcWidth::Combining) => 0,
+        Some(WcWidth::Two | WcWidth::WidenedIn9) => 2,
+        Some(_) => 1,
+    }
+}
+
 #[allow(dead_code)]
 pub fn wcwidth(buf: &[u8]) -> usize {
     let utf8_str = std::str::from_utf8(buf).unwrap();
@@ -140,3 +164,32 @@ pub fn open_dev_null() -> libc::c_int {
         }
     }
 }
+
+/// A scratch directory for a test's files, named for the test and the process. It's removed when
+/// dropped, so a failing test doesn't leave it behind.
+#[cfg(test)]
+pub struct TempDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(name: &str) -> std::io::Result<Self> {
+        let path = std::env::temp_dir().join(format!("wim-{}-test-{}", name, std::process::id()));
+        std::fs::create_dir_all(&path)?;
+        Ok(Self(path))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, name: &str) -> std::path::PathBuf {
+        self.0.join(name)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
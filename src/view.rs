@@ -1,4 +1,5 @@
 use crate::prelude::*;
+use crate::stack::Axis;
 use crate::types::{Pos, Rect};
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
@@ -24,7 +25,12 @@ pub trait ViewContext {
             _ => None,
         }
     }
-    #[allow(dead_code)]
+    fn get_property_int(&self, property: &str) -> Option<i64> {
+        match self.get_property(property) {
+            Some(Variant::Int(b)) => Some(b),
+            _ => None,
+        }
+    }
     fn get_property_pos(&self, property: &str) -> Option<Pos> {
         match self.get_property(property) {
             Some(Variant::Pos(b)) => Some(b),
@@ -51,4 +57,14 @@ pub trait View: DispatchTarget + ViewContext {
     }
     #[allow(dead_code)]
     fn get_doc_text(&self, _view_map: &ViewMap) -> Option<String>;
+    /// For views that lay out windows (`VStack` and `HStack`), the way they stack and their
+    /// children in order.
+    fn get_stack(&self) -> Option<(Axis, Vec<ViewKey>)> {
+        None
+    }
+    /// Makes a new view showing what this one does, for splitting a window. Views that can't be
+    /// split return None.
+    fn clone_view(&self, _view_key: ViewKey) -> Option<ViewRef> {
+        None
+    }
 }
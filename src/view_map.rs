@@ -1,14 +1,34 @@
 use crate::error::Result;
+use crate::hstack::HStack;
 use crate::keygen::ViewKeyGenerator;
 use crate::prelude::*;
+use crate::stack::Axis;
 use crate::trie::{Mapping, TrieNode};
+use crate::vstack::VStack;
 use regex::Regex;
 
+/// A tab page: the stack at the root of its windows, and the views focused in it from least to
+/// most recent, so that coming back to a tab focuses the window that was left.
+struct Tab {
+    root: ViewKey,
+    previous_views: Vec<ViewKey>,
+}
+
+/// What the tabline shows of a tab page.
+pub struct TabPage {
+    /// The tab's windows in layout order.
+    pub windows: Vec<ViewKey>,
+    /// The window last focused in the tab.
+    pub focused: Option<ViewKey>,
+    pub is_current: bool,
+}
+
 pub struct ViewMap {
     map: HashMap<ViewKey, ViewRef>,
     named_views: HashMap<String, ViewKey>,
     parents_map: HashMap<ViewKey, ViewKey>,
-    previous_views: Vec<ViewKey>,
+    tabs: Vec<Tab>,
+    current_tab: usize,
     view_key_gen: ViewKeyGenerator,
     root_view_key: Option<ViewKey>,
 }
@@ -18,8 +38,9 @@ impl ViewMap {
         Self {
             map: Default::default(),
             named_views: Default::default(),
-            previous_views: Default::default(),
             parents_map: Default::default(),
+            tabs: Default::default(),
+            current_tab: 0,
             view_key_gen: ViewKeyGenerator::new(),
             root_view_key: None,
         }
@@ -27,13 +48,17 @@ impl ViewMap {
     pub fn set_focused_view(&mut self, view_key_to_focus: ViewKey) {
         assert!(self.map.contains_key(&view_key_to_focus));
         log::trace!("focusing view '{:?}'", view_key_to_focus);
-        self.previous_views.retain(|vk| {
+        let previous_views = &mut self.tabs[self.current_tab].previous_views;
+        previous_views.retain(|vk| {
             // Keep the views that still exist and that aren't the intended one so we can move it
             // to the top of the stack..
             *vk != view_key_to_focus
         });
 
-        self.previous_views.push(view_key_to_focus);
+        previous_views.push(view_key_to_focus);
+    }
+    fn previous_views(&self) -> &[ViewKey] {
+        &self.tabs[self.current_tab].previous_views
     }
     pub fn set_root_view_key(&mut self, vk: ViewKey) {
         self.root_view_key = Some(vk)
@@ -56,9 +81,16 @@ impl ViewMap {
             assert!(re.is_match(name.as_ref()));
             assert!(!self.named_views.contains_key(&name));
             self.named_views.insert(name, vk);
-            if let Some(vk_parent) = vk_parent {
-                self.parents_map.insert(vk, vk_parent);
-            }
+        }
+        if let Some(vk_parent) = vk_parent {
+            self.parents_map.insert(vk, vk_parent);
+        }
+    }
+    pub fn remove(&mut self, vk: ViewKey) {
+        self.map.remove(&vk);
+        self.parents_map.remove(&vk);
+        for tab in self.tabs.iter_mut() {
+            tab.previous_views.retain(|&previous| previous != vk);
         }
     }
     #[allow(dead_code)]
@@ -75,8 +107,8 @@ impl ViewMap {
         }
     }
     pub fn focused_view_key(&self) -> ViewKey {
-        assert!(!self.previous_views.is_empty());
-        *self.previous_views.last().unwrap()
+        assert!(!self.previous_views().is_empty());
+        *self.previous_views().last().unwrap()
     }
     pub fn focused_view(&self) -> ViewRef {
         self.get_view(self.focused_view_key())
@@ -128,18 +160,28 @@ impl ViewMap {
         }
         trace!("inbound_keys of dks === {:?} of {:?}", inbound_keys, dks);
         assert!(!inbound_keys.is_empty());
-        match trie.longest_prefix(&inbound_keys) {
+        let (count, keys) = trie.split_count(&inbound_keys);
+        if keys.is_empty() {
+            // Still typing a count.
+            return HandleKey::Choices(trie.first_keys());
+        }
+        let count_len = inbound_keys.len() - keys.len();
+        match trie.longest_prefix(keys) {
             Mapping::Bound { dk, remaining } => {
                 trace!(
-                    "keys {:?} translated into dk={:?}, leaving remaining={:?}",
+                    "keys {:?} translated into dk={:?} with count={:?}, leaving remaining={:?}",
                     inbound_keys,
                     dk,
+                    count,
                     remaining
                 );
                 (0..(inbound_keys.len() - remaining.len())).for_each(|_| {
                     dks.pop_front();
                 });
-                HandleKey::DK(dk)
+                match count {
+                    Some(count) => HandleKey::DK(dk.with_count(count)),
+                    None => HandleKey::DK(dk),
+                }
             }
             Mapping::Choices(choices) => {
                 trace!("found choices {:?}", choices);
@@ -147,30 +189,464 @@ impl ViewMap {
                 HandleKey::Choices(choices.iter().map(|(key, _)| key).cloned().collect())
             }
             Mapping::None => {
-                trace!("no mapping found, returning SendKey({:?})", inbound_keys[0]);
-                dks.pop_front();
+                trace!("no mapping found, returning SendKey({:?})", keys[0]);
+                // Any count typed before an unbound key is dropped.
+                (0..=count_len).for_each(|_| {
+                    dks.pop_front();
+                });
                 HandleKey::DK(DK::Dispatch(
                     Target::View(self.focused_view_key()),
-                    Message::SendKey(inbound_keys[0]),
+                    Message::SendKey(keys[0]),
                 ))
             }
         }
     }
 
     pub fn get_previous_view(&self) -> ViewRef {
-        self.get_view(*self.previous_views.last().unwrap())
+        self.get_view(*self.previous_views().last().unwrap())
     }
 
     pub fn goto_previous_view(&mut self) {
-        if self.previous_views.len() > 1 {
-            self.previous_views.pop();
+        let previous_views = &mut self.tabs[self.current_tab].previous_views;
+        if previous_views.len() > 1 {
+            previous_views.pop();
+        }
+    }
+}
+
+/// Windows are the views laid out by a `VStack` or `HStack`. Splitting a window puts a new view
+/// of the same doc beside it, nesting a stack of the other kind where the split goes across the
+/// window's stack.
+impl ViewMap {
+    fn get_stack(&self, vk: ViewKey) -> Option<(Axis, Vec<ViewKey>)> {
+        self.get_view(vk).get_stack()
+    }
+    fn is_window(&self, vk: ViewKey) -> bool {
+        self.get_stack(vk).is_none()
+            && self
+                .parents_map
+                .get(&vk)
+                .is_some_and(|&parent| self.get_stack(parent).is_some())
+    }
+    /// The most recently focused window, which is the one ex commands apply to.
+    pub fn focused_window_key(&self) -> Option<ViewKey> {
+        self.last_window(&self.tabs[self.current_tab])
+    }
+    fn last_window(&self, tab: &Tab) -> Option<ViewKey> {
+        tab.previous_views
+            .iter()
+            .rev()
+            .copied()
+            .find(|&vk| self.is_window(vk))
+    }
+    /// The outermost stack holding `vk`.
+    fn windows_root(&self, vk: ViewKey) -> ViewKey {
+        let mut root = vk;
+        while let Some(&parent) = self.parents_map.get(&root) {
+            if self.get_stack(parent).is_none() {
+                break;
+            }
+            root = parent;
+        }
+        root
+    }
+    /// The windows in and under `vk`, in layout order.
+    fn windows_under(&self, vk: ViewKey) -> Vec<ViewKey> {
+        match self.get_stack(vk) {
+            Some((_, children)) => children
+                .into_iter()
+                .flat_map(|child| self.windows_under(child))
+                .collect(),
+            None => vec![vk],
+        }
+    }
+    /// The window in `vk` nearest its start (or with `last`, its end) along `axis`.
+    fn edge_window(&self, vk: ViewKey, axis: Axis, last: bool) -> ViewKey {
+        match self.get_stack(vk) {
+            Some((stack_axis, children)) => {
+                let child = if stack_axis == axis && last {
+                    *children.last().unwrap()
+                } else {
+                    children[0]
+                };
+                self.edge_window(child, axis, last)
+            }
+            None => vk,
+        }
+    }
+    /// The window next to `vk` in the direction given by `axis` and `forward`, if there is one.
+    fn neighbour_window(&self, vk: ViewKey, axis: Axis, forward: bool) -> Option<ViewKey> {
+        let mut child = vk;
+        while let Some(&parent) = self.parents_map.get(&child) {
+            let (stack_axis, children) = self.get_stack(parent)?;
+            if stack_axis == axis {
+                let index = children.iter().position(|&vk| vk == child).unwrap();
+                let next = if forward {
+                    children.get(index + 1)
+                } else {
+                    index.checked_sub(1).and_then(|index| children.get(index))
+                };
+                if let Some(&next) = next {
+                    return Some(self.edge_window(next, axis, !forward));
+                }
+            }
+            child = parent;
+        }
+        None
+    }
+    fn split_window(&mut self, vk: ViewKey, axis: Axis) -> Result<Status> {
+        let length = self.get_view(vk).get_property(match axis {
+            Axis::Horizontal => PROP_DOCVIEW_WIDTH,
+            Axis::Vertical => PROP_DOCVIEW_HEIGHT,
+        });
+        if !matches!(length, Some(Variant::Int(length)) if length as Coord >= axis.split_length()) {
+            // An error rather than a status, so that `:sp {file}` doesn't go on to open the file.
+            return Err(error!("E36: Not enough room"));
+        }
+        let new_vk = self.get_next_key();
+        let view = match self.get_view(vk).clone_view(new_vk) {
+            Some(view) => view,
+            None => return Ok(status!("This view cannot be split")),
+        };
+        let parent = self.parents_map[&vk];
+        let (parent_axis, _) = self.get_stack(parent).unwrap();
+        if parent_axis == axis {
+            self.insert(view, Some(parent), None);
+            self.get_view(parent)
+                .execute_command("insert-child".to_string(), vec![vk.into(), new_vk.into()])?;
+        } else {
+            let stack_vk = self.get_next_key();
+            let stack = match axis {
+                Axis::Horizontal => viewref(HStack::new(stack_vk, vec![new_vk, vk])),
+                Axis::Vertical => viewref(VStack::new(stack_vk, vec![new_vk, vk])),
+            };
+            self.get_view(parent).execute_command(
+                "replace-child".to_string(),
+                vec![vk.into(), stack_vk.into()],
+            )?;
+            self.insert(stack, Some(parent), None);
+            self.insert(view, Some(stack_vk), None);
+            self.parents_map.insert(vk, stack_vk);
+        }
+        self.set_focused_view(new_vk);
+        Ok(Status::Ok)
+    }
+    /// Closes `vk`, focusing the window that takes its room. Closing the last window quits if
+    /// `quit_if_last` is set.
+    fn close_window(&mut self, vk: ViewKey, quit_if_last: bool) -> Result<Status> {
+        let root = self.windows_root(vk);
+        if self.windows_under(root).len() == 1 {
+            // The last window of a tab page takes the tab with it.
+            if self.tabs.len() > 1 {
+                return self.close_tab();
+            }
+            return Ok(if quit_if_last {
+                Status::Quit
+            } else {
+                status!("Cannot close last window")
+            });
+        }
+        let parent = self.parents_map[&vk];
+        let (axis, siblings) = self.get_stack(parent).unwrap();
+        let index = siblings.iter().position(|&sibling| sibling == vk).unwrap();
+        let focus = if index > 0 {
+            self.edge_window(siblings[index - 1], axis, true)
+        } else {
+            self.edge_window(siblings[1], axis, false)
+        };
+        self.get_view(parent)
+            .execute_command("remove-child".to_string(), vec![vk.into()])?;
+        self.remove(vk);
+        // A stack left holding one view gives way to that view, unless it's the root.
+        if siblings.len() == 2 && parent != root {
+            let only = siblings[1 - index];
+            let grandparent = self.parents_map[&parent];
+            self.get_view(grandparent).execute_command(
+                "replace-child".to_string(),
+                vec![parent.into(), only.into()],
+            )?;
+            self.parents_map.insert(only, grandparent);
+            self.remove(parent);
+        }
+        self.set_focused_view(focus);
+        Ok(Status::Ok)
+    }
+    /// Closes `vk` as `:quit` does. Unsaved changes keep it open without `force`, unless another
+    /// window shows the same buffer. Closing the last window also refuses while any buffer,
+    /// shown or hidden, is modified.
+    fn quit_window(&mut self, vk: ViewKey, force: bool) -> Result<Status> {
+        let view = self.get_view(vk);
+        if !force && view.get_property_bool(PROP_DOC_IS_MODIFIED, false) {
+            let buffer = view.get_property(PROP_DOCVIEW_BUFFER_ID);
+            let shown_elsewhere = self
+                .tabs
+                .iter()
+                .flat_map(|tab| self.windows_under(tab.root))
+                .any(|other| {
+                    other != vk
+                        && self.get_view(other).get_property(PROP_DOCVIEW_BUFFER_ID) == buffer
+                });
+            if !shown_elsewhere {
+                return Err(error!(
+                    "E37: No write since last change (add ! to override)"
+                ));
+            }
+        }
+        let is_last = self.tabs.len() <= 1 && self.windows_under(self.windows_root(vk)).len() == 1;
+        if !force && is_last {
+            if let Some(Variant::String(name)) = view.get_property(PROP_DOCVIEW_MODIFIED_BUFFER) {
+                return Err(error!(
+                    "E162: No write since last change for buffer \"{}\"",
+                    name
+                ));
+            }
+        }
+        self.close_window(vk, true)
+    }
+    fn only_window(&mut self, vk: ViewKey) -> Result<Status> {
+        for other in self.windows_under(self.windows_root(vk)) {
+            if other != vk {
+                self.close_window(other, false)?;
+            }
+        }
+        self.set_focused_view(vk);
+        Ok(Status::Ok)
+    }
+    /// Changes the size of `vk` along `axis` by resizing it within the nearest stack that runs
+    /// that way.
+    fn resize_window(&mut self, vk: ViewKey, axis: Axis, delta: i64) -> Result<Status> {
+        let mut child = vk;
+        while let Some(&parent) = self.parents_map.get(&child) {
+            match self.get_stack(parent) {
+                Some((stack_axis, children)) if stack_axis == axis && children.len() > 1 => {
+                    return self.get_view(parent).execute_command(
+                        "resize-child".to_string(),
+                        vec![child.into(), delta.into()],
+                    );
+                }
+                Some(_) => child = parent,
+                None => break,
+            }
+        }
+        Ok(Status::Ok)
+    }
+    fn equalize_windows(&mut self, vk: ViewKey) -> Result<Status> {
+        if let Some((_, children)) = self.get_stack(vk) {
+            self.get_view(vk)
+                .execute_command("equalize".to_string(), Vec::new())?;
+            for child in children {
+                self.equalize_windows(child)?;
+            }
+        }
+        Ok(Status::Ok)
+    }
+    fn execute_window_command(&mut self, name: &str, mut args: Vec<Variant>) -> Result<Status> {
+        let vk = match self.focused_window_key() {
+            Some(vk) => vk,
+            None => return Ok(status!("No window is focused")),
+        };
+        let count = match args.last() {
+            Some(&Variant::Int(count)) if name != "resize-window" => {
+                args.pop();
+                count.max(1) as usize
+            }
+            _ => 1,
+        };
+        match (name, args.as_slice()) {
+            ("split-window", [Variant::String(side)]) => match side.as_str() {
+                "above" => self.split_window(vk, Axis::Vertical),
+                "left" => self.split_window(vk, Axis::Horizontal),
+                _ => Err(error!("'split-window' expects one of {{above,left}}")),
+            },
+            ("focus-window", [Variant::String(direction)]) => {
+                let mut focus = vk;
+                for _ in 0..count {
+                    let next = match direction.as_str() {
+                        "left" => self.neighbour_window(focus, Axis::Horizontal, false),
+                        "right" => self.neighbour_window(focus, Axis::Horizontal, true),
+                        "up" => self.neighbour_window(focus, Axis::Vertical, false),
+                        "down" => self.neighbour_window(focus, Axis::Vertical, true),
+                        "next" | "prior" => {
+                            let windows = self.windows_under(self.windows_root(focus));
+                            let index = windows.iter().position(|&w| w == focus).unwrap();
+                            let len = windows.len();
+                            Some(if direction == "next" {
+                                windows[(index + 1) % len]
+                            } else {
+                                windows[(index + len - 1) % len]
+                            })
+                        }
+                        _ => {
+                            return Err(error!(
+                                "'focus-window' expects one of {{left,right,up,down,next,prior}}"
+                            ))
+                        }
+                    };
+                    match next {
+                        Some(next) => focus = next,
+                        None => break,
+                    }
+                }
+                self.set_focused_view(focus);
+                Ok(Status::Ok)
+            }
+            ("close-window", []) => self.close_window(vk, false),
+            ("quit-window", [Variant::Bool(force)]) => self.quit_window(vk, *force),
+            ("only-window", []) => self.only_window(vk),
+            ("resize-window", [Variant::String(dimension), Variant::Int(delta)]) => {
+                match dimension.as_str() {
+                    "height" => self.resize_window(vk, Axis::Vertical, *delta),
+                    "width" => self.resize_window(vk, Axis::Horizontal, *delta),
+                    _ => Err(error!("'resize-window' expects one of {{height,width}}")),
+                }
+            }
+            ("equalize-windows", []) => {
+                let root = self.windows_root(vk);
+                self.equalize_windows(root)
+            }
+            _ => Err(error!("'{}' does not take {:?}", name, args)),
+        }
+    }
+}
+
+/// Tab pages each lay out their own tree of windows under the editor. Only the current tab is
+/// laid out, and each keeps its own focus history.
+impl ViewMap {
+    /// Adds a tab page holding the windows under `root` just after the current one, and makes it
+    /// current.
+    pub fn add_tab(&mut self, root: ViewKey) {
+        let index = if self.tabs.is_empty() {
+            0
+        } else {
+            self.current_tab + 1
+        };
+        self.tabs.insert(
+            index,
+            Tab {
+                root,
+                previous_views: Vec::new(),
+            },
+        );
+        self.current_tab = index;
+    }
+    /// The stack at the root of the current tab's windows.
+    pub fn current_tab_root(&self) -> Option<ViewKey> {
+        self.tabs.get(self.current_tab).map(|tab| tab.root)
+    }
+    pub fn tab_count(&self) -> usize {
+        self.tabs.len()
+    }
+    pub fn tab_pages(&self) -> Vec<TabPage> {
+        self.tabs
+            .iter()
+            .enumerate()
+            .map(|(index, tab)| TabPage {
+                windows: self.windows_under(tab.root),
+                focused: self.last_window(tab),
+                is_current: index == self.current_tab,
+            })
+            .collect()
+    }
+    /// Opens a tab page with one window, showing what the focused window does.
+    fn new_tab(&mut self) -> Result<Status> {
+        let vk = match self.focused_window_key() {
+            Some(vk) => vk,
+            None => return Ok(status!("No window is focused")),
+        };
+        let new_vk = self.get_next_key();
+        let view = match self.get_view(vk).clone_view(new_vk) {
+            Some(view) => view,
+            None => return Ok(status!("This view cannot be opened in a new tab")),
+        };
+        let root = self.get_next_key();
+        let parent = self.parents_map[&self.windows_root(vk)];
+        self.insert(viewref(VStack::new(root, vec![new_vk])), Some(parent), None);
+        self.insert(view, Some(root), None);
+        self.add_tab(root);
+        self.set_focused_view(new_vk);
+        Ok(Status::Ok)
+    }
+    /// Closes the current tab page, moving on to the one after it (or before it, if it was
+    /// last).
+    fn close_tab(&mut self) -> Result<Status> {
+        if self.tabs.len() == 1 {
+            return Ok(status!("Cannot close last tab page"));
+        }
+        let tab = self.tabs.remove(self.current_tab);
+        self.remove_tree(tab.root);
+        self.current_tab = self.current_tab.min(self.tabs.len() - 1);
+        Ok(Status::Ok)
+    }
+    fn only_tab(&mut self) -> Result<Status> {
+        let current = self.tabs.remove(self.current_tab);
+        for tab in std::mem::take(&mut self.tabs) {
+            self.remove_tree(tab.root);
+        }
+        self.tabs.push(current);
+        self.current_tab = 0;
+        Ok(Status::Ok)
+    }
+    /// Goes to tab page `count`, or with no count the next one. Going back goes `count` tabs
+    /// back. Either way wraps around the ends.
+    fn focus_tab(&mut self, forward: bool, count: Option<usize>) {
+        let len = self.tabs.len();
+        self.current_tab = match (forward, count) {
+            (true, Some(count)) => count.clamp(1, len) - 1,
+            (true, None) => (self.current_tab + 1) % len,
+            (false, count) => (self.current_tab + len - count.unwrap_or(1) % len) % len,
+        };
+    }
+    fn remove_tree(&mut self, vk: ViewKey) {
+        if let Some((_, children)) = self.get_stack(vk) {
+            for child in children {
+                self.remove_tree(child);
+            }
+        }
+        self.remove(vk);
+    }
+    fn execute_tab_command(&mut self, name: &str, mut args: Vec<Variant>) -> Result<Status> {
+        let count = match args.last() {
+            Some(&Variant::Int(count)) => {
+                args.pop();
+                Some(count.max(1) as usize)
+            }
+            _ => None,
+        };
+        match (name, args.as_slice()) {
+            ("new-tab", []) => self.new_tab(),
+            ("close-tab", []) => self.close_tab(),
+            ("only-tab", []) => self.only_tab(),
+            ("focus-tab", [Variant::String(direction)]) => match direction.as_str() {
+                "next" | "prior" => {
+                    self.focus_tab(direction == "next", count);
+                    Ok(Status::Ok)
+                }
+                _ => Err(error!("'focus-tab' expects one of {{next,prior}}")),
+            },
+            _ => Err(error!("'{}' does not take {:?}", name, args)),
         }
     }
 }
 
 impl DispatchTarget for ViewMap {
     fn execute_command(&mut self, name: String, args: Vec<Variant>) -> Result<Status> {
-        if name == "focus" {
+        if matches!(
+            name.as_str(),
+            "split-window"
+                | "focus-window"
+                | "close-window"
+                | "quit-window"
+                | "only-window"
+                | "resize-window"
+                | "equalize-windows"
+        ) {
+            self.execute_window_command(&name, args)
+        } else if matches!(
+            name.as_str(),
+            "new-tab" | "close-tab" | "only-tab" | "focus-tab"
+        ) {
+            self.execute_tab_command(&name, args)
+        } else if name == "focus" {
             if args.len() != 1 {
                 return Err(error!("focus command requires 1 Target"));
             }
@@ -1,5 +1,6 @@
 use crate::error::Result;
 use crate::prelude::*;
+use crate::stack::Axis;
 
 pub fn viewref<T>(x: T) -> ViewRef
 where
@@ -55,4 +56,10 @@ impl View for ViewRef {
     fn set_status(&mut self, status: Status) {
         self.ptr.borrow_mut().set_status(status)
     }
+    fn get_stack(&self) -> Option<(Axis, Vec<ViewKey>)> {
+        self.ptr.borrow().get_stack()
+    }
+    fn clone_view(&self, view_key: ViewKey) -> Option<ViewRef> {
+        self.ptr.borrow().clone_view(view_key)
+    }
 }
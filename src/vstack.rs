@@ -1,16 +1,25 @@
 use crate::bindings::Bindings;
-use crate::error::{Error, Result};
+use crate::error::Result;
 use crate::plugin::PluginRef;
 use crate::prelude::*;
+use crate::stack::{Axis, Stack};
 use crate::status::Status;
 use crate::types::{Pos, Rect};
 use crate::view::{ViewContext, ViewKey};
 
-#[allow(dead_code)]
+/// Lays out windows one above the other.
 pub struct VStack {
-    plugin: PluginRef,
     view_key: ViewKey,
-    view_keys: Vec<ViewKey>,
+    stack: Stack,
+}
+
+impl VStack {
+    pub fn new(view_key: ViewKey, view_keys: Vec<ViewKey>) -> Self {
+        Self {
+            view_key,
+            stack: Stack::new(Axis::Vertical, view_keys),
+        }
+    }
 }
 
 impl ViewContext for VStack {
@@ -22,47 +31,20 @@ impl View for VStack {
     fn get_doc_text(&self, _view_map: &ViewMap) -> Option<String> {
         panic!("not sure what to do with vstack get_doc_text call...");
     }
-    fn install_plugins(&mut self, plugin: PluginRef) {
-        self.plugin = plugin;
-    }
+    fn install_plugins(&mut self, _plugin: PluginRef) {}
     fn layout(&mut self, _view_map: &ViewMap, size: Size) -> Vec<(ViewKey, Rect)> {
-        let expected_per_view_height = std::cmp::max(1, size.height / self.view_keys.len());
-        let mut used = 0;
-        self.view_keys
-            .iter()
-            .filter_map(|view_key| {
-                if size.height - used < expected_per_view_height {
-                    return None;
-                }
-                let view_height = if used + expected_per_view_height * 2 > size.height {
-                    size.height - used
-                } else {
-                    expected_per_view_height
-                };
-
-                let ret = Some((
-                    *view_key,
-                    Rect {
-                        x: 0,
-                        y: used,
-                        width: size.width,
-                        height: view_height,
-                    },
-                ));
-                used += view_height;
-                ret
-            })
-            .collect()
+        self.stack.layout(size)
     }
     fn get_view_key(&self) -> ViewKey {
         self.view_key
     }
-    fn display(&self, _view_map: &ViewMap, _bmp: &mut BitmapView) {
-        // TODO: draw borders between views.
-    }
+    fn display(&self, _view_map: &ViewMap, _bmp: &mut BitmapView) {}
     fn get_cursor_pos(&self) -> Option<Pos> {
         panic!("VStack should not be focused!");
     }
+    fn get_stack(&self) -> Option<(Axis, Vec<ViewKey>)> {
+        Some((self.stack.axis(), self.stack.view_keys().to_vec()))
+    }
 }
 
 impl DispatchTarget for VStack {
@@ -70,10 +52,7 @@ impl DispatchTarget for VStack {
         Default::default()
     }
     fn execute_command(&mut self, name: String, args: Vec<Variant>) -> Result<Status> {
-        Err(Error::new(format!(
-            "Command {:?} {:?} not implemented for VStack",
-            name, args,
-        )))
+        self.stack.execute_command(&name, &args)
     }
     fn send_key(&mut self, key: Key) -> Result<Status> {
         panic!("why is the vstack receiving send_keys? [key={:?}]", key);